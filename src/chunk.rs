@@ -4,11 +4,16 @@ use crate::span::FreeSpan;
 use crate::value::Value;
 use indexmap::IndexSet;
 use std::assert_matches::assert_matches;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
 use std::iter;
+use std::ops::Range;
 
 
+pub mod serialize;
+
 /// Emmited bytecode Chunk
 #[derive(Default)]
 pub struct Chunk<'alloc> {
@@ -17,35 +22,132 @@ pub struct Chunk<'alloc> {
 
     /// Constant pool
     ///
-    /// Chunk may contain up to `u16::MAX` unique constants.
-    constants: IndexSet<Value<'alloc>>,
+    /// Chunk may contain up to `u16::MAX` unique constants. Keys follow insertion order, hashing is
+    /// only used for deduplication so the pool layout doesn't depend on hash values.
+    constants: IndexSet<PoolKey<'alloc>>,
 
     /// Opcode origin spans
     ///
     /// Indexed by bytes in `code`. Free spans are anchored against `source`.
     spans: Vec<FreeSpan>,
+
+    /// Code positions of jumps that were widened to their long form
+    ///
+    /// Widening inserts two bytes into the code, [`PatchPlace`]s and [`LoopPoint`]s created before
+    /// a widening use this to find their original position.
+    widened: Vec<usize>,
+
+    /// Named locals for debuggers, doesn't affect execution
+    locals_debug_info: Vec<LocalDebugInfo>,
+
+    /// Failed assertions are collected by the VM instead of stopping execution
+    catch_asserts: bool,
+
+    /// Names of the globals resolved at compile time, indexed by their slot
+    global_names: Vec<Box<str>>,
+
+    /// Instruction index ranges of desugared code, their spans are the construct they come from
+    /// rather than source text they implement
+    synthetic: Vec<Range<usize>>,
+
+    /// Copy of the source the chunk has been compiled from, if attached
+    source: Option<Box<str>>,
+
+    /// Spans of the blocks counted by `Hit`s, indexed by their id
+    coverage: Vec<FreeSpan>,
+
+    /// The spans and the rest of the debug info have been discarded by
+    /// [`strip_debug`](Chunk::strip_debug), new instructions don't get any either
+    stripped: bool,
+
+    /// Constant references of a [relocatable](Chunk::set_relocatable) chunk by the value they
+    /// name, `None` once the chunk has been [linked](Chunk::link)
+    relocations: Option<Vec<Relocation<'alloc>>>,
+
+    /// Values of the placeholder keys handed out to the compiler of a relocatable chunk
+    symbols: IndexSet<PoolKey<'alloc>>,
+}
+
+/// Operand of the instruction at `position` referring to `value`, `position` is rebased like a
+/// [`PatchPlace`]
+#[derive(Clone, Copy)]
+struct Relocation<'alloc> {
+    position: usize,
+    widened: usize,
+    value: Value<'alloc>,
+}
+
+/// Constant pool entry, Numbers are deduplicated only if they are bitwise identical
+///
+/// `Value`'s equality treats close Numbers as equal and `NaN` as unequal to itself, the pool has to
+/// keep `0.0` and `-0.0` apart and find a `NaN` again.
+#[derive(Clone, Copy)]
+pub(crate) struct PoolKey<'alloc>(pub(crate) Value<'alloc>);
+
+impl<'alloc> PartialEq for PoolKey<'alloc> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.0.to_float(), other.0.to_float()) {
+            (Some(lhs), Some(rhs)) => lhs.to_bits() == rhs.to_bits(),
+            _ => self.0 == other.0,
+        }
+    }
+}
+
+impl<'alloc> Eq for PoolKey<'alloc> {}
+
+impl<'alloc> Hash for PoolKey<'alloc> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // hashes Numbers by their bits
+        self.0.hash(state)
+    }
+}
+
+/// Where a named local lives during execution
+#[derive(Clone, Debug)]
+pub struct LocalDebugInfo {
+    /// Stack slot holding the value
+    pub slot: u16,
+    /// Span of the local's name in the source
+    pub name: FreeSpan,
+    /// Instructions during which the local is in scope, indexed like [`Chunk::opcodes`]
+    pub live: Range<usize>,
 }
 
 impl<'alloc> SourceDebug for Chunk<'alloc> {
     fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_listing(source, f)
+    }
+}
+
+impl<'alloc> Chunk<'alloc> {
+    /// Writes the disassembly to `f` as it goes, the listing of big chunks is never held in memory
+    ///
+    /// Every instruction is listed with its code offset, the constant or global it refers to or
+    /// the offset it jumps to, and the source line of its span.
+    pub fn write_listing<W: fmt::Write>(&self, source: &str, f: &mut W) -> fmt::Result {
         const RED: &str = "\x1B[31m";
         const RESET: &str = "\x1B[m";
 
-        let opcodes = self.opcodes();
-        let spans = self.spans.iter()
-            .map(|fs| fs.anchor(source));
-
         let mut prev_line = 0;
         writeln!(f, "Chunk {{")?;
-        for (opcode, span) in opcodes.zip(spans) {
+        for (index, (offset, opcode, span)) in self.steps().enumerate() {
+            let span = span.anchor(source);
             let (line, _) = span.lines();
+            write!(f, "{:04} ", offset)?;
             if line != prev_line {
                 prev_line = line;
                 write!(f, "{:>4}  ", line)?;
             } else {
                 write!(f, "   |  ")?;
             };
-            let opcodefmt = format!("{:?}", opcode);
+            let mut opcodefmt = format!("{:?}", opcode);
+            if let Some(operand) = self.describe_operand(offset, opcode) {
+                opcodefmt += " ";
+                opcodefmt += &operand;
+            }
+            if self.is_synthetic(index) {
+                opcodefmt += " (desugared)";
+            }
             if let Some((before, span, after)) = span.line_parts() {
                 writeln!(f, "  {:<32} |  {}{}{}{}{}", opcodefmt, before, RED, span, RESET, after)?;
             } else {
@@ -54,9 +156,122 @@ impl<'alloc> SourceDebug for Chunk<'alloc> {
         }
         writeln!(f, "}}")
     }
+
+    /// What the operand of the instruction at `offset` refers to, for listings
+    fn describe_operand(&self, offset: usize, opcode: OpCode) -> Option<String> {
+        let next = offset + 1 + OpCode::operand_len(opcode.tag())?;
+        match opcode {
+            OpCode::Constant { key } |
+            OpCode::GetGlobal { name_key: key } |
+            OpCode::DefGlobal { name_key: key } |
+            OpCode::SetGlobal { name_key: key } |
+            OpCode::AssertBecause { reason_key: key } => {
+                let value = self.get_constant(key)?;
+                Some(format!("= {:?}", value))
+            }
+            OpCode::GetGlobalSlot { slot } |
+            OpCode::DefGlobalSlot { slot } |
+            OpCode::SetGlobalSlot { slot } => {
+                let name = self.global_names.get(slot as usize)?;
+                Some(format!("= {}", name))
+            }
+            OpCode::Jump { offset } |
+            OpCode::JumpIfTrue { offset } |
+            OpCode::JumpIfFalse { offset } => Some(format!("-> {:04}", next + offset as usize)),
+            OpCode::JumpLong { offset } |
+            OpCode::JumpIfTrueLong { offset } |
+            OpCode::JumpIfFalseLong { offset } => Some(format!("-> {:04}", next + offset as usize)),
+            OpCode::Loop { offset } => Some(format!("-> {:04}", next.checked_sub(offset as usize)?)),
+            _ => None,
+        }
+    }
+
+    /// Returns the disassembly, see [`write_listing`](Chunk::write_listing)
+    pub fn disassemble(&self, source: &str) -> String {
+        let mut listing = String::new();
+        self.write_listing(source, &mut listing).expect("writing to a String can't fail");
+        listing
+    }
+
+    /// Same as [`disassemble`](Chunk::disassemble) against the [attached source](Chunk::source),
+    /// `None` without one
+    pub fn disassemble_attached(&self) -> Option<String> {
+        self.source().map(|source| self.disassemble(source))
+    }
+}
+
+/// Result of [`Chunk::simulate_stack_depth`]
+#[derive(Debug, Default)]
+pub struct StackDepths {
+    /// Depth before each reachable instruction, by code offset
+    pub depths: BTreeMap<usize, i32>,
+    /// Highest depth reached, after any instruction
+    pub max: i32,
+    /// Offsets of the instructions popping more values than there are
+    pub underflows: Vec<usize>,
+}
+
+impl<'alloc> Chunk<'alloc> {
+    /// Walks every path through the code computing the stack depth at each instruction
+    ///
+    /// Both successors of a conditional jump are followed. An instruction is visited once, by the
+    /// first path reaching it, the depths of later paths joining it aren't compared. Jumps out of
+    /// the code end the path like `Return` does.
+    pub fn simulate_stack_depth(&self) -> StackDepths {
+        let mut result = StackDepths::default();
+        let mut pending = vec![(0, 0)];
+        while let Some((offset, depth)) = pending.pop() {
+            if result.depths.contains_key(&offset) {
+                continue;
+            }
+            let (opcode, rest) = match self.code.get(offset..).and_then(OpCode::decode) {
+                Some(decoded) => decoded,
+                None => continue,
+            };
+            result.depths.insert(offset, depth);
+
+            let (pops, pushes) = opcode.stack_effect();
+            if depth < i32::from(pops) {
+                result.underflows.push(offset);
+            }
+            let depth = depth - i32::from(pops) + i32::from(pushes);
+            result.max = result.max.max(depth);
+
+            let next = self.code.len() - rest.len();
+            let target = match opcode {
+                OpCode::Jump { offset } |
+                OpCode::JumpIfTrue { offset } |
+                OpCode::JumpIfFalse { offset } => Some(next + offset as usize),
+                OpCode::JumpLong { offset } |
+                OpCode::JumpIfTrueLong { offset } |
+                OpCode::JumpIfFalseLong { offset } => Some(next + offset as usize),
+                OpCode::Loop { offset } => next.checked_sub(offset as usize),
+                _ => None,
+            };
+            if let Some(target) = target {
+                pending.push((target, depth));
+            }
+            if !opcode.is_terminator() {
+                pending.push((next, depth));
+            }
+        }
+        result.underflows.sort_unstable();
+        result
+    }
 }
 
 impl<'alloc> Chunk<'alloc> {
+    /// Creates an empty chunk with room for `code` bytes of code and `consts` constants
+    pub fn with_capacity(code: usize, consts: usize) -> Chunk<'alloc> {
+        Chunk {
+            code: Vec::with_capacity(code),
+            constants: IndexSet::with_capacity(consts),
+            // most instructions take up two to three bytes
+            spans: Vec::with_capacity(code / 2),
+            ..Chunk::default()
+        }
+    }
+
     pub fn opcodes(&self) -> impl Iterator<Item = OpCode> + '_ {
         let mut code = self.code.as_slice();
         iter::from_fn(move || {
@@ -66,16 +281,142 @@ impl<'alloc> Chunk<'alloc> {
         })
     }
 
+    /// Decodes the instructions together with their code offset and origin span
+    pub fn steps(&self) -> impl Iterator<Item = (usize, OpCode, FreeSpan)> + '_ {
+        let mut code = self.code.as_slice();
+        let mut index = 0;
+        iter::from_fn(move || {
+            let offset = self.code.len() - code.len();
+            let (opcode, rest) = OpCode::decode(code)?;
+            code = rest;
+            let span = self.span_at(index);
+            index += 1;
+            Some((offset, opcode, span))
+        })
+    }
+
+    /// Packed bytecode, decode it from the start or from one of the
+    /// [`instruction_offsets`](Chunk::instruction_offsets) only
     pub fn code(&self) -> &[u8] {
         &self.code
     }
 
+    /// Byte offsets into [`code`](Chunk::code) at which each instruction starts
+    pub fn instruction_offsets(&self) -> Vec<usize> {
+        self.steps().map(|(offset, _, _)| offset).collect()
+    }
+
+    /// Origin spans indexed by instruction, empty once [stripped](Chunk::strip_debug)
     pub fn spans(&self) -> &[FreeSpan] {
         &self.spans
     }
 
+    /// Origin span of instruction `index`, an empty span at the start of the source for a stripped
+    /// chunk
+    pub fn span_at(&self, index: usize) -> FreeSpan {
+        if self.stripped {
+            FreeSpan::default()
+        } else {
+            *self.spans.get(index).expect("missing span information")
+        }
+    }
+
+    /// Number of instructions emitted so far
+    pub fn instruction_count(&self) -> usize {
+        if self.stripped {
+            self.opcodes().count()
+        } else {
+            self.spans.len()
+        }
+    }
+
+    /// Discards the spans, the desugaring ranges, the locals debug info and the attached source,
+    /// keeping what's needed to run the code
+    ///
+    /// Runtime errors of a stripped chunk point at the start of the source.
+    pub fn strip_debug(&mut self) {
+        self.spans = Vec::new();
+        self.synthetic = Vec::new();
+        self.locals_debug_info = Vec::new();
+        self.source = None;
+        self.stripped = true;
+    }
+
+    /// Whether [`strip_debug`](Chunk::strip_debug) has been called on the chunk
+    pub fn is_stripped(&self) -> bool {
+        self.stripped
+    }
+
+    /// Marks `instructions`, indices into [`spans`](Chunk::spans), as generated by desugaring
+    pub fn mark_synthetic(&mut self, instructions: Range<usize>) {
+        if !instructions.is_empty() && !self.stripped {
+            self.synthetic.push(instructions);
+        }
+    }
+
+    /// Whether instruction `index` has been generated by desugaring, its span is then the
+    /// construct it has been desugared from
+    pub fn is_synthetic(&self, index: usize) -> bool {
+        self.synthetic.iter().any(|range| range.contains(&index))
+    }
+
+    /// Named locals in the order they went out of scope
+    pub fn locals_debug_info(&self) -> &[LocalDebugInfo] {
+        &self.locals_debug_info
+    }
+
+    pub fn add_local_debug_info(&mut self, info: LocalDebugInfo) {
+        if !self.stripped {
+            self.locals_debug_info.push(info);
+        }
+    }
+
+    /// Whether failed assertions are collected instead of stopping execution, see
+    /// [`VM::run_catching`](crate::vm::VM::run_catching)
+    pub fn catch_asserts(&self) -> bool {
+        self.catch_asserts
+    }
+
+    pub fn set_catch_asserts(&mut self, catch_asserts: bool) {
+        self.catch_asserts = catch_asserts;
+    }
+
+    /// Source the chunk has been compiled from, if it has been attached to it
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    /// Keeps a copy of `source` in the chunk, it has to be the source the chunk is compiled from
+    pub fn attach_source(&mut self, source: &str) {
+        self.source = Some(source.into());
+    }
+
+    /// Spans of the blocks instrumented for coverage, indexed by the id of their `Hit`, empty unless
+    /// compiled with [`Options::coverage`](crate::compiler::Options::coverage)
+    pub fn coverage_map(&self) -> &[FreeSpan] {
+        &self.coverage
+    }
+
+    /// Registers a block for coverage and returns the id its `Hit` should have
+    pub fn add_coverage_block(&mut self, span: FreeSpan) -> u16 {
+        self.coverage.push(span);
+        (self.coverage.len() - 1).try_into().expect("coverage block limit reached")
+    }
+
+    /// Names of the globals accessed by `GetGlobalSlot` and friends, indexed by slot
+    pub fn global_names(&self) -> &[Box<str>] {
+        &self.global_names
+    }
+
+    /// Allocates the next global slot for `name`
+    pub fn add_global_name(&mut self, name: &str) -> u16 {
+        let slot = self.global_names.len().try_into().expect("global slot limit reached");
+        self.global_names.push(name.into());
+        slot
+    }
+
     pub fn constants(&self) -> impl Iterator<Item = &Value<'alloc>> {
-        self.constants.iter()
+        self.constants.iter().map(|PoolKey(value)| value)
     }
 }
 
@@ -83,24 +424,38 @@ impl<'alloc> Chunk<'alloc> {
 #[derive(Clone, Copy, Debug)]
 pub struct PatchPlace {
     position: usize,
+    widened: usize,
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct LoopPoint {
     position: usize,
+    widened: usize,
 }
 
 impl<'alloc> Chunk<'alloc> {
     pub fn emit(&mut self, opcode: OpCode, span: FreeSpan) -> Option<PatchPlace> {
         let position = self.code.len();
+        if let (Some(relocations), Some(ConstKey { index })) = (&mut self.relocations, constant_key(opcode)) {
+            // the key is a placeholder, `link` overwrites it
+            let PoolKey(value) = self.symbols[index as usize];
+            relocations.push(Relocation { position, widened: self.widened.len(), value });
+        }
         opcode.encode(&mut self.code);
-        self.spans.push(span);
+        if !self.stripped {
+            self.spans.push(span);
+        }
         matches!(opcode, OpCode::Jump { .. } | OpCode::JumpIfTrue { .. } | OpCode::JumpIfFalse { .. })
-            .then(|| PatchPlace { position })
+            .then(|| PatchPlace { position, widened: self.widened.len() })
     }
 
+    /// Patches a placeholder jump to jump to the current end of the chunk
+    ///
+    /// Placeholders are emitted in the short form, if the jump ends up being too long it's
+    /// rewritten into the matching `*Long` instruction.
     pub fn patch_jump(&mut self, place: Option<PatchPlace>) {
-        let PatchPlace { position } = place.expect("tried to patch an unpatchable instruction");
+        let PatchPlace { position, widened } = place.expect("tried to patch an unpatchable instruction");
+        let position = self.rebase(position, widened);
 
         // Check that the position really points to a placeholder JUMP* instruction
         assert_matches!(
@@ -117,29 +472,154 @@ impl<'alloc> Chunk<'alloc> {
         let new_ip = self.code.len();
 
         // We want `new_ip = old_ip + offset`.
-        let offset = (new_ip - old_ip).try_into()
-            .expect("max jump length exceeded");
+        let offset = new_ip - old_ip;
 
-        let [x, y] = u16::to_le_bytes(offset);
-        self.code[position+1] = x;
-        self.code[position+2] = y;
+        match u16::try_from(offset) {
+            // `u16::MAX` is reserved for placeholders
+            Ok(offset) if offset != u16::MAX => {
+                let [x, y] = u16::to_le_bytes(offset);
+                self.code[position+1] = x;
+                self.code[position+2] = y;
+            }
+            _ => self.widen_jumps(position),
+        }
+    }
+
+    /// Lays out the code again so that the placeholder jump at `position` jumps to the end of the
+    /// chunk
+    ///
+    /// Jumps which don't fit into a 16 bit offset are switched to their `*Long` forms. Every
+    /// widening moves the code after it by two bytes which may push other jumps out of range, so
+    /// the layout is repeated until no more jumps need to be widened.
+    fn widen_jumps(&mut self, position: usize) {
+        let end = self.code.len();
+
+        // (original position, original length, opcode)
+        let mut instructions = Vec::new();
+        let mut scan = self.code.as_slice();
+        while let Some((opcode, rest)) = OpCode::decode(scan) {
+            let start = end - scan.len();
+            instructions.push((start, scan.len() - rest.len(), opcode));
+            scan = rest;
+        }
+
+        // Jump targets as original positions, `None` for placeholders which get patched later.
+        let targets: Vec<Option<usize>> = instructions.iter()
+            .map(|&(start, len, opcode)| match opcode {
+                _ if start == position => Some(end),
+                OpCode::Jump { offset: u16::MAX } |
+                OpCode::JumpIfTrue { offset: u16::MAX } |
+                OpCode::JumpIfFalse { offset: u16::MAX } => None,
+                OpCode::Jump { offset } |
+                OpCode::JumpIfTrue { offset } |
+                OpCode::JumpIfFalse { offset } => Some(start + len + offset as usize),
+                OpCode::JumpLong { offset } |
+                OpCode::JumpIfTrueLong { offset } |
+                OpCode::JumpIfFalseLong { offset } => Some(start + len + offset as usize),
+                OpCode::Loop { offset } => Some(start + len - offset as usize),
+                _ => None,
+            })
+            .collect();
+
+        let mut widen = vec![false; instructions.len()];
+        let mut starts = Vec::with_capacity(instructions.len() + 1);
+        let relocate = |starts: &[usize], original: usize| {
+            match instructions.binary_search_by_key(&original, |&(start, _, _)| start) {
+                Ok(index) => starts[index],
+                Err(index) if original == end => starts[index],
+                Err(_) => unreachable!("jump target is not an instruction boundary"),
+            }
+        };
+        loop {
+            starts.clear();
+            let mut scan = 0;
+            for (&(_, len, _), &widen) in instructions.iter().zip(&widen) {
+                starts.push(scan);
+                scan += len + if widen { 2 } else { 0 };
+            }
+            starts.push(scan);
+
+            let mut changed = false;
+            for (index, &(_, _, opcode)) in instructions.iter().enumerate() {
+                let short_forward = matches!(
+                    opcode,
+                    OpCode::Jump { .. } | OpCode::JumpIfTrue { .. } | OpCode::JumpIfFalse { .. },
+                );
+                if let (true, false, Some(target)) = (short_forward, widen[index], targets[index]) {
+                    // `u16::MAX` is reserved for placeholders
+                    if relocate(&starts, target) - starts[index + 1] >= u16::MAX as usize {
+                        widen[index] = true;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut code = Vec::with_capacity(starts[instructions.len()]);
+        for (index, &(_, _, opcode)) in instructions.iter().enumerate() {
+            let next = starts[index + 1];
+            let forward = |target| -> u32 {
+                (relocate(&starts, target) - next).try_into().expect("max jump length exceeded")
+            };
+            let opcode = match (opcode, targets[index]) {
+                (OpCode::Jump { .. }, Some(target)) if widen[index] => OpCode::JumpLong { offset: forward(target) },
+                (OpCode::JumpIfTrue { .. }, Some(target)) if widen[index] => OpCode::JumpIfTrueLong { offset: forward(target) },
+                (OpCode::JumpIfFalse { .. }, Some(target)) if widen[index] => OpCode::JumpIfFalseLong { offset: forward(target) },
+                (OpCode::Jump { .. }, Some(target)) => OpCode::Jump { offset: forward(target) as u16 },
+                (OpCode::JumpIfTrue { .. }, Some(target)) => OpCode::JumpIfTrue { offset: forward(target) as u16 },
+                (OpCode::JumpIfFalse { .. }, Some(target)) => OpCode::JumpIfFalse { offset: forward(target) as u16 },
+                (OpCode::JumpLong { .. }, Some(target)) => OpCode::JumpLong { offset: forward(target) },
+                (OpCode::JumpIfTrueLong { .. }, Some(target)) => OpCode::JumpIfTrueLong { offset: forward(target) },
+                (OpCode::JumpIfFalseLong { .. }, Some(target)) => OpCode::JumpIfFalseLong { offset: forward(target) },
+                (OpCode::Loop { .. }, Some(target)) => {
+                    let offset = (next - relocate(&starts, target)).try_into()
+                        .expect("loop body too large");
+                    OpCode::Loop { offset }
+                }
+                (opcode, _) => opcode,
+            };
+            opcode.encode(&mut code);
+        }
+        self.code = code;
+
+        // record the widenings as if they happened one after another
+        let widened = instructions.iter()
+            .zip(&widen)
+            .filter(|(_, &widen)| widen)
+            .enumerate()
+            .map(|(nth, (&(start, _, _), _))| start + 2 * nth);
+        self.widened.extend(widened);
+    }
+
+    /// Finds where code originally at `position` is after the widenings since `widened`
+    fn rebase(&self, mut position: usize, widened: usize) -> usize {
+        for &at in &self.widened[widened..] {
+            if position > at {
+                position += 2;
+            }
+        }
+        position
     }
 
     pub fn loop_point(&self) -> LoopPoint {
         LoopPoint {
             position: self.code.len(),
+            widened: self.widened.len(),
         }
     }
 
     pub fn emit_loop(&mut self, loop_point: LoopPoint, span: FreeSpan) {
-        let LoopPoint { position } = loop_point;
+        let LoopPoint { position, widened } = loop_point;
 
         // LOOP offset is subtracted after the IP has been advanced past it.
         // Add 3 for the encoded size.
         let old_ip = self.code.len() + 3;
 
         // We want to set the IP back to where `loop_point` has been created.
-        let new_ip = position;
+        let new_ip = self.rebase(position, widened);
 
         // We want `new_ip = old_ip - offset`.
         let offset = (old_ip - new_ip).try_into()
@@ -173,15 +653,449 @@ impl Debug for ConstKey {
     }
 }
 
+/// Constant key operand of `opcode`, if it has one
+fn constant_key(opcode: OpCode) -> Option<ConstKey> {
+    match opcode {
+        OpCode::Constant { key } |
+        OpCode::GetGlobal { name_key: key } |
+        OpCode::DefGlobal { name_key: key } |
+        OpCode::SetGlobal { name_key: key } |
+        OpCode::AssertBecause { reason_key: key } => Some(key),
+        _ => None,
+    }
+}
+
 impl<'alloc> Chunk<'alloc> {
+    /// Returns the key of `value` in the constant pool, inserting it if needed
+    ///
+    /// A [relocatable](Chunk::set_relocatable) chunk returns a placeholder instead, instructions
+    /// emitted with it refer to `value` until the chunk is [linked](Chunk::link).
     pub fn insert_constant(&mut self, value: Value<'alloc>) -> ConstKey {
-        let (index, _) = self.constants.insert_full(value);
+        let pool = match self.relocations {
+            Some(_) => &mut self.symbols,
+            None => &mut self.constants,
+        };
+        let (index, _) = pool.insert_full(PoolKey(value));
         let index = index.try_into().expect("constant pool size limit reached");
         ConstKey { index }
     }
 
+    /// Inserts `value` into the constant pool and emits a `Constant` instruction loading it
+    pub fn emit_constant(&mut self, value: Value<'alloc>, span: FreeSpan) -> ConstKey {
+        let key = self.insert_constant(value);
+        self.emit(OpCode::Constant { key }, span);
+        key
+    }
+
     pub fn get_constant(&self, key: ConstKey) -> Option<Value<'alloc>> {
         let ConstKey { index } = key;
-        self.constants.get_index(index as usize).copied()
+        self.constants.get_index(index as usize).map(|&PoolKey(value)| value)
+    }
+
+    /// Removes constants no instruction refers to and renumbers the remaining ones
+    ///
+    /// Surviving constants keep their relative order. Returns how many constants were removed.
+    /// Panics if the chunk is [relocatable](Chunk::set_relocatable), link it first.
+    pub fn gc_constants(&mut self) -> usize {
+        assert!(!self.is_relocatable(), "relocatable chunk has to be linked first");
+        let operands: Vec<(usize, ConstKey)> = self.steps()
+            .filter_map(|(offset, opcode, _)| Some((offset, constant_key(opcode)?)))
+            .collect();
+
+        let mut used = vec![false; self.constants.len()];
+        for (_, ConstKey { index }) in &operands {
+            used[*index as usize] = true;
+        }
+
+        let mut remap = vec![0; self.constants.len()];
+        let mut constants = IndexSet::with_capacity(self.constants.len());
+        for (index, value) in self.constants.drain(..).enumerate() {
+            if used[index] {
+                remap[index] = constants.len() as u16;
+                constants.insert(value);
+            }
+        }
+        let removed = used.len() - constants.len();
+        self.constants = constants;
+
+        // keys are fixed width, renumbering never moves any code
+        for (offset, ConstKey { index }) in operands {
+            let key = ConstKey { index: remap[index as usize] };
+            self.code[(offset + 1)..(offset + 3)].copy_from_slice(&key.to_le_bytes());
+        }
+        removed
+    }
+
+    /// Makes the chunk refer to its constants by value until it's [linked](Chunk::link)
+    ///
+    /// Relocatable chunks compiled independently can be [appended](Chunk::append) to each other
+    /// without their keys colliding, keys are only assigned when the whole program is linked. The
+    /// chunk can't run before that. Has to be called before anything is emitted.
+    pub fn set_relocatable(&mut self) {
+        assert!(self.code.is_empty(), "chunk already contains code");
+        self.relocations = Some(Vec::new());
+    }
+
+    /// Whether the chunk is [relocatable](Chunk::set_relocatable) and hasn't been linked yet
+    pub fn is_relocatable(&self) -> bool {
+        self.relocations.is_some()
+    }
+
+    /// Appends the code of `other` to this chunk, both have to be relocatable and complete,
+    /// without jumps left to patch
+    ///
+    /// Constant references are moved over as they are, by value. Global slots of `other` are
+    /// resolved through their names and its coverage ids follow the ones of this chunk. The source
+    /// of `other` is expected to start at `source_offset` in the source of this chunk, its spans
+    /// are moved there.
+    pub fn append(&mut self, other: &Chunk<'alloc>, source_offset: usize) {
+        let (relocations, other_relocations) = match (&mut self.relocations, &other.relocations) {
+            (Some(relocations), Some(other_relocations)) => (relocations, other_relocations),
+            _ => panic!("only relocatable chunks can be appended"),
+        };
+        let (position, widened) = (self.code.len(), self.widened.len());
+        relocations.extend(other_relocations.iter().map(|relocation| Relocation {
+            position: position + other.rebase(relocation.position, relocation.widened),
+            widened,
+            value: relocation.value,
+        }));
+
+        let slots: Vec<u16> = other.global_names.iter()
+            .map(|name| match self.global_names.iter().position(|own| own == name) {
+                Some(slot) => slot as u16,
+                None => self.add_global_name(name),
+            })
+            .collect();
+        let slot = |slot: u16| slots[slot as usize];
+        let shift = u32::try_from(source_offset).expect("out of span range");
+        let move_span = |span: FreeSpan| FreeSpan { start: span.start + shift, end: span.end + shift };
+        let hits = self.coverage.len() as u16;
+        self.coverage.extend(other.coverage.iter().copied().map(move_span));
+
+        let start = self.instruction_count();
+        for (_, opcode, span) in other.steps() {
+            let opcode = match opcode {
+                OpCode::GetGlobalSlot { slot: s } => OpCode::GetGlobalSlot { slot: slot(s) },
+                OpCode::DefGlobalSlot { slot: s } => OpCode::DefGlobalSlot { slot: slot(s) },
+                OpCode::SetGlobalSlot { slot: s } => OpCode::SetGlobalSlot { slot: slot(s) },
+                OpCode::Hit { id } => OpCode::Hit { id: hits + id },
+                // jumps are relative, they don't depend on where the code ends up
+                _ => opcode,
+            };
+            opcode.encode(&mut self.code);
+            if !self.stripped {
+                self.spans.push(move_span(span));
+            }
+        }
+        if self.stripped {
+            return;
+        }
+
+        let shift = |range: &Range<usize>| (range.start + start)..(range.end + start);
+        self.synthetic.extend(other.synthetic.iter().map(shift));
+        self.locals_debug_info.extend(other.locals_debug_info.iter().map(|info| LocalDebugInfo {
+            name: move_span(info.name),
+            live: shift(&info.live),
+            ..*info
+        }));
+    }
+
+    /// Inserts the constants of a relocatable chunk into its pool and patches the keys of the
+    /// instructions referring to them, after which the chunk can run
+    ///
+    /// Constants get their keys in the order of the code.
+    pub fn link(&mut self) {
+        let relocations = self.relocations.take().expect("chunk is not relocatable");
+        for Relocation { position, widened, value } in relocations {
+            let position = self.rebase(position, widened);
+            let key = self.insert_constant(value);
+            // keys are fixed width, patching never moves any code
+            self.code[(position + 1)..(position + 3)].copy_from_slice(&key.to_le_bytes());
+        }
+        self.symbols = IndexSet::new();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Chunk;
+    use crate::opcode::OpCode;
+    use crate::span::FreeSpan;
+    use crate::value::Value;
+
+    #[test]
+    fn test_with_capacity() {
+        let mut chunk = Chunk::with_capacity(64, 16);
+        let (code, constants) = (chunk.code.capacity(), chunk.constants.capacity());
+        for n in 0..16 {
+            chunk.emit_constant(Value::new_float(n as f64), FreeSpan::default());
+        }
+        assert_eq!(chunk.code.capacity(), code);
+        assert_eq!(chunk.constants.capacity(), constants);
+    }
+
+    #[test]
+    fn test_steps() {
+        let a = FreeSpan { start: 0, end: 1 };
+        let b = FreeSpan { start: 2, end: 5 };
+        let mut chunk = Chunk::default();
+        let key = chunk.emit_constant(Value::new_float(1.0), a);
+        chunk.emit(OpCode::GetLocal { slot: 0 }, b);
+        chunk.emit(OpCode::Pop, a);
+
+        let steps: Vec<_> = chunk.steps()
+            .map(|(offset, opcode, span)| (offset, opcode, span.range()))
+            .collect();
+        assert_eq!(steps, [
+            (0, OpCode::Constant { key }, 0..1),
+            (3, OpCode::GetLocal { slot: 0 }, 2..5),
+            (6, OpCode::Pop, 0..1),
+        ]);
+    }
+
+    #[test]
+    fn test_emit_constant() {
+        let span = FreeSpan::default();
+        let mut chunk = Chunk::default();
+
+        let key = chunk.emit_constant(Value::new_float(1.0), span);
+        assert_eq!(chunk.opcodes().collect::<Vec<_>>(), [OpCode::Constant { key }]);
+
+        let other = chunk.emit_constant(Value::new_float(2.0), span);
+        let repeated = chunk.emit_constant(Value::new_float(1.0), span);
+        assert_eq!(key, repeated);
+        assert_ne!(key, other);
+        assert_eq!(chunk.constants().count(), 2);
+        assert_eq!(chunk.opcodes().count(), 3);
+    }
+
+    #[test]
+    fn test_append() {
+        use crate::compiler::{compile_with, Options};
+        use crate::object::Alloc;
+        use crate::parser::parse;
+        use crate::vm::{RuntimeErrorKind, VmError, VM};
+
+        let alloc = Alloc::new();
+        let options = Options { resolve_globals: true, relocatable: true, ..Options::default() };
+        let compile = |src| compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap().0;
+
+        let sources = [
+            r#"print "z";"#,
+            r#"let a = "x"; let b = 1.5; print a; print b;"#,
+            r#"let b = 2.5; print "y"; print b; print 1.5; assert b < 1;"#,
+        ];
+        let mut linked = compile(sources[0]);
+        assert!(linked.is_relocatable());
+        linked.append(&compile(sources[1]), sources[0].len());
+        linked.append(&compile(sources[2]), sources[0].len() + sources[1].len());
+        linked.link();
+
+        let source = sources.concat();
+        let mut output = Vec::new();
+        let result = VM::new(&linked, &source, &alloc).with_output(&mut output).run();
+        assert_eq!(String::from_utf8(output).unwrap(), "\"z\"\n\"x\"\n1.5\n\"y\"\n2.5\n1.5\n");
+        // spans of the appended code point into its part of the source
+        assert!(matches!(
+            result,
+            Err(VmError::RuntimeError { kind: RuntimeErrorKind::AssertionError(None), span })
+                if span.as_str() == "assert b < 1;"
+        ));
+        // equal constants and globals of the same name are shared, "z", "x", 1.5, "y", 2.5 and 1
+        assert_eq!(linked.constants().count(), 6);
+        assert_eq!(linked.global_names(), ["a".into(), "b".into()]);
+        assert_eq!(linked.instruction_offsets().len(), linked.spans().len());
+    }
+
+    #[test]
+    fn test_simulate_stack_depth() {
+        use crate::compiler::compile;
+        use crate::object::Alloc;
+        use crate::parser::parse;
+
+        let alloc = Alloc::new();
+        let src = "print 1 + 2;";
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let depths = chunk.simulate_stack_depth();
+        assert_eq!(depths.max, 2);
+        assert!(depths.underflows.is_empty());
+        assert_eq!(depths.depths.values().copied().collect::<Vec<_>>(), [0, 1, 2, 1, 0]);
+
+        // loops and both branches of an `if` are followed, the block leaves nothing behind
+        let src = "{ let mut n = 0; while n < 3 { if n == 1 { n = n + 2; } else { n = n + 1; } } }";
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let depths = chunk.simulate_stack_depth();
+        assert_eq!(depths.depths.len(), chunk.opcodes().count());
+        assert!(depths.underflows.is_empty());
+        assert!(depths.depths.values().all(|depth| *depth >= 0));
+
+        let mut chunk = Chunk::default();
+        chunk.emit(OpCode::True, FreeSpan::default());
+        chunk.emit(OpCode::Add, FreeSpan::default());
+        chunk.emit(OpCode::Pop, FreeSpan::default());
+        let depths = chunk.simulate_stack_depth();
+        assert_eq!(depths.underflows, [1, 2]);
+    }
+
+    #[test]
+    fn test_instruction_offsets() {
+        use crate::compiler::compile;
+        use crate::object::Alloc;
+        use crate::parser::parse;
+
+        let alloc = Alloc::new();
+        let src = "{
+            let mut n = 0;
+            while n < 3 {
+                if n == 1 and true { n = n + 2; } else { n = n + 1; }
+            }
+            for i in 0..n { print i; }
+        }";
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let offsets = chunk.instruction_offsets();
+        assert_eq!(offsets.len(), chunk.opcodes().count());
+
+        let mut jumps = 0;
+        for (index, opcode) in chunk.opcodes().enumerate() {
+            let next = offsets.get(index + 1).copied().unwrap_or(chunk.code().len());
+            let target = match opcode {
+                OpCode::Jump { offset } |
+                OpCode::JumpIfTrue { offset } |
+                OpCode::JumpIfFalse { offset } => next + offset as usize,
+                OpCode::Loop { offset } => next - offset as usize,
+                _ => continue,
+            };
+            assert!(offsets.contains(&target), "jump at {} into an instruction", offsets[index]);
+            jumps += 1;
+        }
+        assert!(jumps > 5);
+    }
+
+    #[test]
+    fn test_write_listing() {
+        use crate::compiler::compile;
+        use crate::fmt::SourceDebug;
+        use crate::object::Alloc;
+        use crate::parser::parse;
+
+        let source = "1;\n2;";
+        let mut chunk = Chunk::default();
+        chunk.emit_constant(Value::new_float(1.0), FreeSpan { start: 0, end: 1 });
+        chunk.emit(OpCode::Pop, FreeSpan { start: 1, end: 2 });
+        chunk.emit_constant(Value::new_float(2.0), FreeSpan { start: 3, end: 4 });
+        chunk.emit(OpCode::Pop, FreeSpan { start: 4, end: 5 });
+
+        let mut listing = String::new();
+        chunk.write_listing(source, &mut listing).unwrap();
+        assert_eq!(listing, chunk.disassemble(source));
+        assert_eq!(listing, format!("{:?}", chunk.wrap(source)));
+        assert_eq!(listing.lines().count(), 6);
+        assert!(listing.contains("0004    2    Constant { key: #1 } = 2.0 "));
+
+        // constants, globals and jump targets are resolved
+        let alloc = Alloc::new();
+        let source = "let mut n = 0;\nwhile n < 3 { n = n + 1; }";
+        let listing = compile(source, parse(source).unwrap(), &alloc).unwrap().disassemble(source);
+        assert!(listing.contains("0003    |    DefGlobal { name_key: #1 } = \"n\" "));
+        assert!(listing.contains("0013    |    JumpIfFalse { offset: 15 } -> 0031 "));
+        assert!(listing.contains("0028    |    Loop { offset: 25 } -> 0006 "));
+    }
+
+    #[test]
+    fn test_constant_pool_bitwise() {
+        let mut chunk = Chunk::default();
+        let zero = chunk.insert_constant(Value::new_float(0.0));
+        let negative_zero = chunk.insert_constant(Value::new_float(-0.0));
+        assert_ne!(zero, negative_zero);
+        assert_eq!(chunk.get_constant(negative_zero).and_then(|c| c.to_float()).map(f64::to_bits), Some((-0.0f64).to_bits()));
+
+        let one = chunk.insert_constant(Value::new_float(1.0));
+        assert_ne!(one, chunk.insert_constant(Value::new_float(1.0 + f64::EPSILON)));
+
+        let nan = chunk.insert_constant(Value::new_float(f64::NAN));
+        assert_eq!(nan, chunk.insert_constant(Value::new_float(f64::NAN)));
+        assert_eq!(chunk.constants().count(), 5);
+    }
+
+    #[test]
+    fn test_gc_constants() {
+        let span = FreeSpan::default();
+        let mut chunk = Chunk::default();
+
+        // operands of a folded `2 * 3`
+        chunk.insert_constant(Value::new_float(2.0));
+        chunk.insert_constant(Value::new_float(3.0));
+        chunk.emit_constant(Value::new_float(6.0), span);
+        let name_key = chunk.insert_constant(Value::new_float(10.0));
+        chunk.emit(OpCode::DefGlobal { name_key }, span);
+        chunk.emit(OpCode::GetGlobal { name_key }, span);
+
+        assert_eq!(chunk.gc_constants(), 2);
+        let constants: Vec<_> = chunk.constants().filter_map(|c| c.to_float()).collect();
+        assert_eq!(constants, [6.0, 10.0]);
+
+        let keys: Vec<_> = chunk.opcodes()
+            .map(|opcode| match opcode {
+                OpCode::Constant { key } => key,
+                OpCode::DefGlobal { name_key } | OpCode::GetGlobal { name_key } => name_key,
+                _ => unreachable!(),
+            })
+            .map(|key| chunk.get_constant(key).and_then(|c| c.to_float()))
+            .collect();
+        assert_eq!(keys, [Some(6.0), Some(10.0), Some(10.0)]);
+
+        // nothing left to collect
+        assert_eq!(chunk.gc_constants(), 0);
+        assert_eq!(chunk.constants().count(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "linked first")]
+    fn test_gc_constants_unlinked() {
+        use crate::compiler::{compile_with, Options};
+        use crate::object::Alloc;
+        use crate::parser::parse;
+
+        let alloc = Alloc::new();
+        let src = "print 1;";
+        let options = Options { relocatable: true, ..Options::default() };
+        let (mut chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+        chunk.gc_constants();
+    }
+
+    #[test]
+    fn test_long_jump() {
+        let span = FreeSpan::default();
+        let mut chunk = Chunk::default();
+
+        let jump = chunk.emit(OpCode::JumpIfFalse { offset: u16::MAX }, span);
+        for _ in 0..65536 {
+            chunk.emit(OpCode::Pop, span);
+        }
+        chunk.patch_jump(jump);
+
+        assert_eq!(chunk.opcodes().next(), Some(OpCode::JumpIfFalseLong { offset: 65536 }));
+        assert_eq!(chunk.code().len(), 5 + 65536);
+    }
+
+    #[test]
+    fn test_long_jump_crossing() {
+        let span = FreeSpan::default();
+        let mut chunk = Chunk::default();
+
+        // if/else with a large else branch
+        let then_jump = chunk.emit(OpCode::JumpIfFalse { offset: u16::MAX }, span);
+        chunk.emit(OpCode::Pop, span);
+        let else_jump = chunk.emit(OpCode::Jump { offset: u16::MAX }, span);
+        chunk.patch_jump(then_jump);
+        for _ in 0..70000 {
+            chunk.emit(OpCode::Pop, span);
+        }
+        chunk.patch_jump(else_jump);
+
+        // then jump has to skip over the two bytes longer else jump
+        let opcodes = chunk.opcodes().collect::<Vec<_>>();
+        assert_eq!(opcodes[0], OpCode::JumpIfFalse { offset: 1 + 5 });
+        assert_eq!(opcodes[2], OpCode::JumpLong { offset: 70000 });
     }
 }
@@ -0,0 +1,476 @@
+//! Portable bytecode files
+//!
+//! A file is the magic `ROXC` and a version byte followed by a flags byte, the constant pool,
+//! the global names, the code and, unless the chunk has been [stripped](Chunk::strip_debug), one
+//! span per instruction. Integers are little endian, counts and lengths are `u32`s. Desugaring
+//! ranges, locals debug info, the coverage map and the attached source aren't kept. A function
+//! constant is its name and arity followed by its own chunk, nested in the same format.
+
+use super::{constant_key, Chunk};
+use crate::object::function::Function as ObjFunction;
+use crate::object::string::String as ObjString;
+use crate::object::Alloc;
+use crate::opcode::OpCode;
+use crate::span::FreeSpan;
+use crate::value::Value;
+use std::fmt;
+
+
+pub const MAGIC: &[u8; 4] = b"ROXC";
+/// Bumped whenever the tags of the opcodes change
+pub const VERSION: u8 = 2;
+
+const FLAG_CATCH_ASSERTS: u8 = 1 << 0;
+const FLAG_STRIPPED: u8 = 1 << 1;
+
+const TAG_UNIT: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+
+/// Most function chunks nested in each other, the loader recurses once per level
+pub const MAX_NESTING: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    /// Input ends in the middle of the file
+    Truncated,
+    BadMagic,
+    UnsupportedVersion {
+        version: u8,
+    },
+    InvalidConstantTag {
+        tag: u8,
+    },
+    /// Same constant twice in the pool, a serialized pool never has duplicates
+    DuplicateConstant,
+    InvalidUtf8,
+    /// Code doesn't decode to the end, `offset` is where decoding fails
+    InvalidCode {
+        offset: usize,
+    },
+    InvalidConstantKey {
+        offset: usize,
+    },
+    InvalidGlobalSlot {
+        offset: usize,
+    },
+    /// Jump or loop landing outside the code or inside an instruction
+    InvalidJumpTarget {
+        offset: usize,
+    },
+    /// Local slot not on the stack when the instruction runs
+    InvalidLocalSlot {
+        offset: usize,
+    },
+    /// Functions nested more than [`MAX_NESTING`] deep
+    TooDeeplyNested,
+    /// Number of spans differs from the number of instructions
+    SpanCountMismatch,
+    TrailingBytes,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Truncated => f.write_str("unexpected end of bytecode file"),
+            LoadError::BadMagic => f.write_str("not a bytecode file"),
+            LoadError::UnsupportedVersion { version } => {
+                write!(f, "bytecode version {} is not supported, expected {}", version, VERSION)
+            }
+            LoadError::InvalidConstantTag { tag } => write!(f, "invalid constant tag {}", tag),
+            LoadError::DuplicateConstant => f.write_str("duplicate constant in the pool"),
+            LoadError::InvalidUtf8 => f.write_str("string is not valid UTF-8"),
+            LoadError::InvalidCode { offset } => write!(f, "invalid instruction at offset {}", offset),
+            LoadError::InvalidConstantKey { offset } => {
+                write!(f, "instruction at offset {} refers to a missing constant", offset)
+            }
+            LoadError::InvalidGlobalSlot { offset } => {
+                write!(f, "instruction at offset {} refers to a missing global", offset)
+            }
+            LoadError::InvalidJumpTarget { offset } => {
+                write!(f, "instruction at offset {} jumps outside of the code", offset)
+            }
+            LoadError::InvalidLocalSlot { offset } => {
+                write!(f, "instruction at offset {} refers to a missing local", offset)
+            }
+            LoadError::TooDeeplyNested => {
+                write!(f, "functions are nested more than {} deep", MAX_NESTING)
+            }
+            LoadError::SpanCountMismatch => f.write_str("number of spans differs from the number of instructions"),
+            LoadError::TrailingBytes => f.write_str("unexpected bytes after the end of the file"),
+        }
+    }
+}
+
+impl<'alloc> Chunk<'alloc> {
+    /// Encodes the chunk into the [file format](self)
+    ///
+    /// Panics if the constant pool holds anything but `()`, Bools, Numbers, Strings and
+    /// Functions, which is all the compiler puts in it, or if the chunk is
+    /// [relocatable](Chunk::set_relocatable) and hasn't been linked.
+    pub fn serialize(&self) -> Vec<u8> {
+        assert!(!self.is_relocatable(), "relocatable chunk has to be linked first");
+        let mut out = Vec::with_capacity(self.code.len() * 3);
+        out.extend(MAGIC);
+        out.push(VERSION);
+        let mut flags = 0;
+        if self.catch_asserts {
+            flags |= FLAG_CATCH_ASSERTS;
+        }
+        if self.stripped {
+            flags |= FLAG_STRIPPED;
+        }
+        out.push(flags);
+
+        write_len(&mut out, self.constants.len());
+        for value in self.constants() {
+            if value.is_unit() {
+                out.push(TAG_UNIT);
+            } else if let Some(b) = value.to_bool() {
+                out.push(if b { TAG_TRUE } else { TAG_FALSE });
+            } else if let Some(n) = value.to_float() {
+                out.push(TAG_NUMBER);
+                out.extend(n.to_le_bytes());
+            } else if let Some(string) = value.downcast::<ObjString>() {
+                out.push(TAG_STRING);
+                write_str(&mut out, string.as_str());
+            } else if let Some(function) = value.downcast::<ObjFunction>() {
+                out.push(TAG_FUNCTION);
+                write_str(&mut out, &function.name);
+                out.extend(function.arity.to_le_bytes());
+                let chunk = function.chunk.serialize();
+                write_len(&mut out, chunk.len());
+                out.extend(chunk);
+            } else {
+                panic!("constant {:?} can't be serialized", value);
+            }
+        }
+
+        write_len(&mut out, self.global_names.len());
+        for name in &self.global_names {
+            write_str(&mut out, name);
+        }
+
+        write_len(&mut out, self.code.len());
+        out.extend(&self.code);
+
+        if !self.stripped {
+            write_len(&mut out, self.spans.len());
+            for span in &self.spans {
+                out.extend(span.start.to_le_bytes());
+                out.extend(span.end.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// Decodes a chunk encoded by [`serialize`](Chunk::serialize), its strings are allocated
+    /// into `alloc`
+    ///
+    /// The code is checked to decode to its end, to only refer to constants, globals and locals
+    /// that exist and to only jump to the start of an instruction, so running it can't fail with
+    /// a [`CodeError`](crate::vm::CodeError) about them or jump out of the code.
+    pub fn deserialize(bytes: &[u8], alloc: &'alloc Alloc) -> Result<Chunk<'alloc>, LoadError> {
+        Chunk::deserialize_nested(bytes, alloc, 0, 0)
+    }
+
+    /// Decodes a chunk starting with `locals` slots on the stack, `nesting` functions deep
+    fn deserialize_nested(
+        bytes: &[u8],
+        alloc: &'alloc Alloc,
+        locals: usize,
+        nesting: usize,
+    ) -> Result<Chunk<'alloc>, LoadError> {
+        if nesting > MAX_NESTING {
+            return Err(LoadError::TooDeeplyNested);
+        }
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(LoadError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(LoadError::UnsupportedVersion { version });
+        }
+        let flags = reader.u8()?;
+
+        let mut chunk = Chunk {
+            catch_asserts: flags & FLAG_CATCH_ASSERTS != 0,
+            ..Chunk::default()
+        };
+
+        for index in 0..reader.len()? {
+            let value = match reader.u8()? {
+                TAG_UNIT => Value::new_unit(),
+                TAG_FALSE => Value::new_bool(false),
+                TAG_TRUE => Value::new_bool(true),
+                TAG_NUMBER => {
+                    let bytes = reader.take(8)?.try_into().unwrap();
+                    Value::new_float(f64::from_le_bytes(bytes))
+                }
+                TAG_STRING => Value::new_object(ObjString::new(reader.str()?, alloc)),
+                TAG_FUNCTION => {
+                    let name = reader.str()?;
+                    let arity = reader.u32()?;
+                    let len = reader.len()?;
+                    // the callee and the arguments are on the stack when the body starts
+                    let locals = arity as usize + 1;
+                    let bytes = reader.take(len)?;
+                    let chunk = Chunk::deserialize_nested(bytes, alloc, locals, nesting + 1)?;
+                    Value::new_object(ObjFunction::new(chunk, arity, name, alloc))
+                }
+                tag => return Err(LoadError::InvalidConstantTag { tag }),
+            };
+            if usize::from(chunk.insert_constant(value).index) != index {
+                return Err(LoadError::DuplicateConstant);
+            }
+        }
+
+        for _ in 0..reader.len()? {
+            let name = reader.str()?;
+            chunk.add_global_name(name);
+        }
+
+        let code_len = reader.len()?;
+        chunk.code = reader.take(code_len)?.to_vec();
+        let instructions = chunk.validate_code(locals)?;
+
+        if flags & FLAG_STRIPPED != 0 {
+            chunk.stripped = true;
+        } else {
+            let count = reader.len()?;
+            if count != instructions {
+                return Err(LoadError::SpanCountMismatch);
+            }
+            for _ in 0..count {
+                let start = reader.u32()?;
+                let end = reader.u32()?;
+                chunk.spans.push(FreeSpan { start, end });
+            }
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(LoadError::TrailingBytes);
+        }
+        Ok(chunk)
+    }
+
+    /// Decodes the whole code checking its operands, returns the number of instructions
+    ///
+    /// Local slots are checked against the stack depth found by
+    /// [`simulate_stack_depth`](Chunk::simulate_stack_depth) plus the `locals` the chunk starts
+    /// with. The jumps are checked first so the simulation only follows valid ones.
+    fn validate_code(&self, locals: usize) -> Result<usize, LoadError> {
+        let mut instructions = Vec::new();
+        let mut code = self.code.as_slice();
+        while !code.is_empty() {
+            let offset = self.code.len() - code.len();
+            let (opcode, rest) = OpCode::decode(code).ok_or(LoadError::InvalidCode { offset })?;
+            let next = self.code.len() - rest.len();
+            instructions.push((offset, opcode, next));
+            code = rest;
+        }
+
+        // jumping right past the last instruction ends the program
+        let is_start = |target: usize| {
+            target == self.code.len()
+                || instructions.binary_search_by_key(&target, |&(start, ..)| start).is_ok()
+        };
+        for &(offset, opcode, next) in &instructions {
+            let target = match opcode {
+                OpCode::Jump { offset } |
+                OpCode::JumpIfTrue { offset } |
+                OpCode::JumpIfFalse { offset } => Some(next + usize::from(offset)),
+                OpCode::JumpLong { offset } |
+                OpCode::JumpIfTrueLong { offset } |
+                OpCode::JumpIfFalseLong { offset } => Some(next + offset as usize),
+                OpCode::Loop { offset } => Some(next.checked_sub(usize::from(offset))
+                    .ok_or(LoadError::InvalidJumpTarget { offset: next })?),
+                _ => None,
+            };
+            if matches!(target, Some(target) if !is_start(target)) {
+                return Err(LoadError::InvalidJumpTarget { offset });
+            }
+        }
+
+        let depths = self.simulate_stack_depth().depths;
+        for &(offset, opcode, _) in &instructions {
+            if matches!(constant_key(opcode), Some(key) if self.get_constant(key).is_none()) {
+                return Err(LoadError::InvalidConstantKey { offset });
+            }
+            match opcode {
+                OpCode::GetGlobalSlot { slot } |
+                OpCode::DefGlobalSlot { slot } |
+                OpCode::SetGlobalSlot { slot } if usize::from(slot) >= self.global_names.len() => {
+                    return Err(LoadError::InvalidGlobalSlot { offset });
+                }
+                // unreachable instructions have no depth and never run
+                OpCode::GetLocal { slot } |
+                OpCode::SetLocal { slot } if matches!(
+                    depths.get(&offset),
+                    Some(&depth) if usize::from(slot) >= locals + depth.max(0) as usize,
+                ) => {
+                    return Err(LoadError::InvalidLocalSlot { offset });
+                }
+                _ => {}
+            }
+        }
+        Ok(instructions.len())
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    let len: u32 = len.try_into().expect("too long to serialize");
+    out.extend(len.to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, string: &str) {
+    write_len(out, string.len());
+    out.extend(string.as_bytes());
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8], LoadError> {
+        if self.bytes.len() < len {
+            return Err(LoadError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, LoadError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn str(&mut self) -> Result<&'b str, LoadError> {
+        let len = self.len()?;
+        std::str::from_utf8(self.take(len)?).map_err(|_| LoadError::InvalidUtf8)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LoadError, MAGIC, VERSION};
+    use crate::chunk::Chunk;
+    use crate::object::function::Function as ObjFunction;
+    use crate::compiler::{compile_with, Options};
+    use crate::object::Alloc;
+    use crate::opcode::OpCode;
+    use crate::parser::parse;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_round_trip() {
+        let alloc = Alloc::new();
+        let src = "let s = \"a\" + \"b\"; let n = -0.0; assert s == \"ab\" because \"concat\"; print n; fn f(a) { return a; }";
+        let options = Options { resolve_globals: true, ..Options::default() };
+        let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+        let bytes = chunk.serialize();
+        assert_eq!(&bytes[..MAGIC.len()], MAGIC);
+
+        let loaded = Chunk::deserialize(&bytes, &alloc).unwrap();
+        assert_eq!(loaded.code(), chunk.code());
+        assert_eq!(loaded.global_names(), chunk.global_names());
+        assert_eq!(loaded.spans().len(), chunk.spans().len());
+        assert_eq!(loaded.disassemble(src), chunk.disassemble(src));
+        let res = VM::new(&loaded, src, &alloc).with_output(std::io::sink()).run();
+        assert!(res.is_ok());
+        let original = chunk.constants().find_map(|value| value.downcast::<ObjFunction>()).unwrap();
+        let function = loaded.constants().find_map(|value| value.downcast::<ObjFunction>()).unwrap();
+        assert_eq!(format!("{:?}", function), "<fn f>");
+        assert_eq!(function.arity(), 1);
+        assert_eq!(function.chunk().code(), original.chunk().code());
+
+        let mut stripped = loaded;
+        stripped.strip_debug();
+        let stripped_bytes = stripped.serialize();
+        assert!(stripped_bytes.len() < bytes.len());
+        assert!(Chunk::deserialize(&stripped_bytes, &alloc).unwrap().is_stripped());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let alloc = Alloc::new();
+        let src = "print 1;";
+        let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &Options::default()).unwrap();
+        let bytes = chunk.serialize();
+        let load = |bytes: &[u8]| Chunk::deserialize(bytes, &alloc).err();
+
+        assert_eq!(load(&bytes[..bytes.len() - 1]), Some(LoadError::Truncated));
+        assert_eq!(load(b"ROX"), Some(LoadError::Truncated));
+        assert_eq!(load(b"NOPE\x01\x00"), Some(LoadError::BadMagic));
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        assert_eq!(load(&newer), Some(LoadError::UnsupportedVersion { version: VERSION + 1 }));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(load(&trailing), Some(LoadError::TrailingBytes));
+
+        // `Constant #0` loading a missing constant, the pool count is right after the flags
+        let mut bytes = bytes;
+        bytes[MAGIC.len() + 2..][..4].copy_from_slice(&0u32.to_le_bytes());
+        bytes.drain(MAGIC.len() + 6..MAGIC.len() + 15);
+        assert_eq!(load(&bytes), Some(LoadError::InvalidConstantKey { offset: 0 }));
+    }
+
+    #[test]
+    #[should_panic(expected = "linked first")]
+    fn test_serialize_unlinked() {
+        let alloc = Alloc::new();
+        let src = "print 1;";
+        let options = Options { relocatable: true, ..Options::default() };
+        let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+        chunk.serialize();
+    }
+
+    #[test]
+    fn test_corrupted_jump() {
+        let alloc = Alloc::new();
+        let src = "let x = true; if x { print 1; }";
+        let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &Options::default()).unwrap();
+        let bytes = chunk.serialize();
+        let code_start = bytes.windows(chunk.code().len())
+            .position(|window| window == chunk.code())
+            .unwrap();
+        let mut starts = Vec::new();
+        let mut code = chunk.code();
+        while let Some((opcode, rest)) = OpCode::decode(code) {
+            starts.push((chunk.code().len() - code.len(), opcode));
+            code = rest;
+        }
+        let jump = starts.iter()
+            .position(|(_, opcode)| matches!(opcode, OpCode::JumpIfFalse { .. }))
+            .unwrap();
+        let (jump_start, _) = starts[jump];
+        let patch = |offset: u16| {
+            let mut corrupted = bytes.clone();
+            corrupted[code_start + jump_start + 1..][..2].copy_from_slice(&offset.to_le_bytes());
+            Chunk::deserialize(&corrupted, &alloc).err()
+        };
+
+        assert_eq!(patch(0xFFFF), Some(LoadError::InvalidJumpTarget { offset: jump_start }));
+        // landing on the operand of the first instruction after the jump that has one
+        let operand = starts[jump + 1..].windows(2)
+            .find(|pair| pair[1].0 - pair[0].0 > 1)
+            .map(|pair| pair[0].0 + 1)
+            .unwrap();
+        let offset = operand - (jump_start + 3);
+        assert_eq!(patch(offset as u16), Some(LoadError::InvalidJumpTarget { offset: jump_start }));
+    }
+}
@@ -1,12 +1,21 @@
-use crate::chunk::{Chunk, ConstKey};
-use crate::lexer::TokenKind;
+use crate::chunk::{Chunk, ConstKey, LocalDebugInfo, LoopPoint, PatchPlace};
+use crate::lexer::{string_contents, unescape_char, Token, TokenKind};
+use crate::object::function::Function as ObjFunction;
 use crate::object::string::String as ObjString;
 use crate::object::Alloc;
 use crate::opcode::OpCode;
 use crate::parser::ast::*;
+use crate::parser::{self, parse_expression};
+use crate::source_map::SourceMap;
 use crate::span::{FreeSpan, Spanned};
+use crate::stats::Stats;
 use crate::value::Value;
+use fxhash::FxHashMap as HashMap;
+use std::fmt;
 use std::num::ParseFloatError;
+use std::time::Instant;
+
+pub mod register;
 
 
 #[derive(Debug)]
@@ -25,49 +34,486 @@ pub enum Error {
     InvalidAssignmentTarget {
         span: FreeSpan,
     },
+    /// Cannot assign to a parenthesized expression, `span` includes the parentheses
+    AssignToGroup {
+        span: FreeSpan,
+    },
     AssignImmutableBinding {
         let_span: FreeSpan,
         assign_span: FreeSpan,
     },
+    /// `break` outside of a loop
+    BreakOutsideLoop {
+        span: FreeSpan,
+    },
+    /// `continue` outside of a loop
+    ContinueOutsideLoop {
+        span: FreeSpan,
+    },
+    /// `return` in top-level code, outside of any function body
+    ReturnOutsideFunction {
+        span: FreeSpan,
+    },
+    /// Function declares more than [`MAX_PARAMETERS`], `span` is the first one over
+    TooManyParameters {
+        span: FreeSpan,
+    },
+    /// Call passes more than [`MAX_PARAMETERS`] arguments, `span` is the first one over
+    TooManyArguments {
+        span: FreeSpan,
+    },
+    /// `break` or `continue` label doesn't name any enclosing loop
+    UndefinedLabel {
+        span: FreeSpan,
+    },
+    /// Local declared without initializer read before it's assigned, with
+    /// [`Options::deny_uninitialized`]
+    UseOfUninitialized {
+        span: FreeSpan,
+    },
+    /// String or char token without both quotes, only if the lexer misbehaves
+    MalformedStringLiteral {
+        span: FreeSpan,
+    },
+    /// Global never defined by a top-level `let`, with [`Options::resolve_globals`]
+    UndefinedGlobal {
+        span: FreeSpan,
+    },
+    /// Number of `{}` in a `format` template differs from the number of arguments, `span` is the
+    /// template
+    FormatArgMismatch {
+        span: FreeSpan,
+        placeholders: usize,
+        arguments: usize,
+    },
+    /// [`ErrorItem`] left by [`parse_resilient`](parser::parse_resilient)
+    Unparsed {
+        span: FreeSpan,
+    },
+    /// Emitted more than [`Options::max_instructions`], `span` is the item being compiled
+    ProgramTooLarge {
+        span: FreeSpan,
+        max: usize,
+    },
+    /// Code may hold more values on the stack than [`Options::max_stack_depth`], `span` is the
+    /// first instruction going over it
+    StackOverflow {
+        span: FreeSpan,
+        max: usize,
+    },
+}
+
+impl Error {
+    /// Returns where the error happened, for errors involving two places the later one
+    pub fn span(&self) -> FreeSpan {
+        match *self {
+            Error::TooManyLocals { span } |
+            Error::InvalidNumberLiteral { span, .. } |
+            Error::InvalidAssignmentTarget { span } |
+            Error::AssignToGroup { span } |
+            Error::BreakOutsideLoop { span } |
+            Error::ContinueOutsideLoop { span } |
+            Error::ReturnOutsideFunction { span } |
+            Error::TooManyParameters { span } |
+            Error::TooManyArguments { span } |
+            Error::UndefinedLabel { span } |
+            Error::UseOfUninitialized { span } |
+            Error::MalformedStringLiteral { span } |
+            Error::UndefinedGlobal { span } |
+            Error::FormatArgMismatch { span, .. } |
+            Error::Unparsed { span } |
+            Error::ProgramTooLarge { span, .. } |
+            Error::StackOverflow { span, .. } => span,
+            Error::Shadowing { shadowing_span, .. } => shadowing_span,
+            Error::AssignImmutableBinding { assign_span, .. } => assign_span,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TooManyLocals { .. } => f.write_str("too many locals in scope"),
+            Error::Shadowing { .. } => f.write_str("binding shadows another one in the same scope"),
+            Error::InvalidNumberLiteral { cause, .. } => write!(f, "invalid number literal: {}", cause),
+            Error::InvalidAssignmentTarget { .. } => f.write_str("invalid assignment target"),
+            Error::AssignToGroup { .. } => f.write_str("cannot assign to a parenthesized expression"),
+            Error::AssignImmutableBinding { .. } => f.write_str("cannot assign to an immutable binding"),
+            Error::BreakOutsideLoop { .. } => f.write_str("`break` outside of a loop"),
+            Error::ContinueOutsideLoop { .. } => f.write_str("`continue` outside of a loop"),
+            Error::ReturnOutsideFunction { .. } => f.write_str("`return` outside of a function"),
+            Error::TooManyParameters { .. } => {
+                write!(f, "function has more than {} parameters", MAX_PARAMETERS)
+            }
+            Error::TooManyArguments { .. } => {
+                write!(f, "call passes more than {} arguments", MAX_PARAMETERS)
+            }
+            Error::UndefinedLabel { .. } => f.write_str("no enclosing loop has this label"),
+            Error::UseOfUninitialized { .. } => f.write_str("local used before it's assigned"),
+            Error::MalformedStringLiteral { .. } => f.write_str("malformed string literal"),
+            Error::UndefinedGlobal { .. } => f.write_str("global is never defined"),
+            Error::FormatArgMismatch { placeholders, arguments, .. } => {
+                write!(f, "template has {} placeholders but {} arguments are given", placeholders, arguments)
+            }
+            Error::Unparsed { .. } => f.write_str("item failed to parse"),
+            Error::ProgramTooLarge { max, .. } => {
+                write!(f, "program compiles to more than {} instructions", max)
+            }
+            Error::StackOverflow { max, .. } => {
+                write!(f, "expression needs more than {} stack slots", max)
+            }
+        }
+    }
+}
+
+/// Either a parser or a compiler error, returned by [`compile_source`]
+#[derive(Debug)]
+pub enum CompileError {
+    Parse(parser::Error),
+    Compile(Error),
+}
+
+impl From<parser::Error> for CompileError {
+    fn from(error: parser::Error) -> CompileError {
+        CompileError::Parse(error)
+    }
+}
+
+impl From<Error> for CompileError {
+    fn from(error: Error) -> CompileError {
+        CompileError::Compile(error)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Parse(error) => error.fmt(f),
+            CompileError::Compile(error) => error.fmt(f),
+        }
+    }
+}
+
+impl CompileError {
+    pub fn span(&self) -> Option<FreeSpan> {
+        match self {
+            CompileError::Parse(error) => error.span(),
+            CompileError::Compile(error) => Some(error.span()),
+        }
+    }
+
+    /// Formats the error message followed by the source line it happened on, `source` has to be
+    /// the source the error comes from
+    ///
+    /// ```text
+    /// error: missing `;` after statement
+    ///    1 | print 1
+    ///      |        ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        self.render_mapped(source, &SourceMap::default())
+    }
+
+    /// Same as [`render`](CompileError::render) with the line number and file declared by the line
+    /// directives of `map`
+    ///
+    /// ```text
+    /// error: missing `;` after statement
+    ///   --> gen.lox
+    ///   40 | print 1
+    ///      |        ^
+    /// ```
+    pub fn render_mapped(&self, source: &str, map: &SourceMap) -> String {
+        let mut out = format!("error: {}\n", self);
+        let span = self.span()
+            .map(|span| span.first_line(source).anchor(source))
+            .and_then(|span| Some((span.location(map), span.line_parts()?)));
+        if let Some((location, (before, this, after))) = span {
+            if let Some(file) = location.file {
+                out += &format!("  --> {}\n", file);
+            }
+            out += &format!("{:>4} | {}{}{}\n", location.line, before, this, after);
+            let carets = "^".repeat(this.chars().count().max(1));
+            out += &format!("     | {:width$}{}\n", "", carets, width = before.chars().count());
+        }
+        out
+    }
+}
+
+/// Parses and compiles `source` with the default options of both
+pub fn compile_source<'alloc>(
+    source: &str,
+    alloc: &'alloc Alloc,
+) -> std::result::Result<Chunk<'alloc>, CompileError> {
+    let ast = parser::parse(source)?;
+    Ok(compile(source, ast, alloc)?)
+}
+
+/// Same as [`compile_source`] but also records the line directives into `map`, to report errors
+/// with [`CompileError::render_mapped`] and [`VmError::location`](crate::vm::VmError::location)
+pub fn compile_source_mapped<'alloc>(
+    source: &str,
+    alloc: &'alloc Alloc,
+    map: &mut SourceMap,
+) -> std::result::Result<Chunk<'alloc>, CompileError> {
+    let ast = parser::parse_with_source_map(source, map)?;
+    Ok(compile(source, ast, alloc)?)
+}
+
+/// Compiler configuration
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Most instructions a chunk may contain, 16 Mi by default
+    pub max_instructions: usize,
+    /// Reject reading a local declared without initializer before an assignment to it, instead of
+    /// reading `()`. Assignments count in source order regardless of control flow, globals are
+    /// never checked.
+    pub deny_uninitialized: bool,
+    /// Compile `assert`s without a `because` reason to `DebugAssert`, which reports the stack on
+    /// failure
+    pub debug_asserts: bool,
+    /// Warn about `and` and `or` mixed without parentheses, like `a or b and c`
+    pub warn_logical_precedence: bool,
+    /// Make failed assertions not stop execution, see [`Chunk::catch_asserts`]
+    pub catch_asserts: bool,
+    /// Resolve globals to slots at compile time in a first pass over the top-level `let`s. Globals
+    /// can then be used before their definition in the source, and a name which is never defined
+    /// is an error.
+    pub resolve_globals: bool,
+    /// Keep a copy of the source in the chunk, see [`Chunk::source`]
+    pub attach_source: bool,
+    /// Reject code which may need more stack slots at once, found by
+    /// [`Chunk::simulate_stack_depth`], the chunks of functions counting their callee and
+    /// arguments. The VM's stack grows as needed so there is no limit by default.
+    pub max_stack_depth: Option<usize>,
+    /// Instrument the program, every block and the code after an `if`, a loop or a jump with a
+    /// `Hit`, see [`Chunk::coverage_map`]. Function bodies are counted in the coverage map of
+    /// their own chunk.
+    pub coverage: bool,
+    /// Refer to constants by value so the chunk can be [appended](Chunk::append) to others, it
+    /// has to be [linked](Chunk::link) before it runs
+    pub relocatable: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            max_instructions: 16 << 20,
+            deny_uninitialized: false,
+            debug_asserts: false,
+            warn_logical_precedence: false,
+            catch_asserts: false,
+            resolve_globals: false,
+            attach_source: false,
+            max_stack_depth: None,
+            coverage: false,
+            relocatable: false,
+        }
+    }
+}
+
+/// Non-fatal diagnostics, code is still emitted for them
+#[derive(Debug)]
+pub enum Warning {
+    /// Operation is always going to fail with a type error at runtime
+    TypeMismatch {
+        span: FreeSpan,
+        message: &'static str,
+    },
+    /// Expression statement without side effects, its result is just popped
+    UnusedExpressionResult {
+        span: FreeSpan,
+    },
+    /// `and` operand of an `or` or the other way around without parentheses, with
+    /// [`Options::warn_logical_precedence`]. `span` is the whole chain of the outer operator.
+    AmbiguousLogicalPrecedence {
+        span: FreeSpan,
+    },
+    /// Whole `if` or `while` condition is an assignment, `if x = 1 {}`, most likely meant to be
+    /// `==`. Parenthesizing the assignment silences it.
+    AssignmentInCondition {
+        span: FreeSpan,
+    },
+    /// `while` condition is a truthy constant and nothing in the body leaves the loop, `span` is
+    /// the `while` and its condition
+    InfiniteLoop {
+        span: FreeSpan,
+    },
+    /// `else` branch of an `if` whose condition is a truthy constant, `span` is the `else` and its
+    /// block
+    UnreachableBranch {
+        span: FreeSpan,
+    },
 }
 
 struct Emitter<'src, 'alloc> {
     source: &'src str,
     alloc: &'alloc Alloc,
     chunk: Chunk<'alloc>,
+    warnings: Vec<Warning>,
+    options: Options,
 
     locals: Vec<Local>,
     scope_depth: i32,
+
+    /// Enclosing loops, innermost last
+    loops: Vec<Loop<'src>>,
+    /// Compiling a function body, the only place `return` is allowed
+    in_function: bool,
+
+    /// Slots of the globals, with [`Options::resolve_globals`]
+    global_slots: Option<HashMap<&'src str, u16>>,
+}
+
+struct Loop<'src> {
+    /// Label name without the colon
+    label: Option<&'src str>,
+    /// Locals deeper than this belong to the loop body
+    depth: i32,
+    /// Where `continue` loops back to, `None` if it jumps forward to the increment of a `for`
+    continue_point: Option<LoopPoint>,
+    break_jumps: Vec<Option<PatchPlace>>,
+    continue_jumps: Vec<Option<PatchPlace>>,
 }
 
 struct Local {
-    name: Identifier,
+    /// `None` for compiler temporaries that can't be referred to from the source
+    name: Option<Identifier>,
     let_span: FreeSpan,
     mutable: bool,
     depth: i32,
+    /// Declared with an initializer or assigned to since
+    initialized: bool,
+    /// Index of the first instruction during which the local is in scope
+    live_from: usize,
 }
 
 type Result = std::result::Result<(), Error>;
 
 pub fn compile<'alloc>(source: &str, ast: Program, alloc: &'alloc Alloc) -> std::result::Result<Chunk<'alloc>, Error> {
+    compile_with_warnings(source, ast, alloc).map(|(chunk, _)| chunk)
+}
+
+/// Same as [`compile`] but also returns the warnings
+pub fn compile_with_warnings<'alloc>(
+    source: &str,
+    ast: Program,
+    alloc: &'alloc Alloc,
+) -> std::result::Result<(Chunk<'alloc>, Vec<Warning>), Error> {
+    compile_with(source, ast, alloc, &Options::default())
+}
+
+/// Same as [`compile_with_warnings`] but with non-default `options`
+pub fn compile_with<'alloc>(
+    source: &str,
+    ast: Program,
+    alloc: &'alloc Alloc,
+    options: &Options,
+) -> std::result::Result<(Chunk<'alloc>, Vec<Warning>), Error> {
     let mut emitter = Emitter {
         source,
         alloc,
-        chunk: Chunk::default(),
+        // rough estimates from typical programs, avoids most regrowing of big chunks
+        chunk: Chunk::with_capacity(source.len() / 2, source.len() / 32),
+        warnings: Vec::default(),
+        options: options.clone(),
         locals: Vec::default(),
         scope_depth: 0,
+        loops: Vec::default(),
+        in_function: false,
+        global_slots: None,
     };
+    emitter.chunk.set_catch_asserts(options.catch_asserts);
+    if options.relocatable {
+        emitter.chunk.set_relocatable();
+    }
+    if options.attach_source {
+        emitter.chunk.attach_source(source);
+    }
 
-    for d in &ast {
-        emitter.item(d)?
+    if options.resolve_globals {
+        // first pass, only top-level `let`s and `fn`s define globals
+        let mut slots = HashMap::default();
+        for item in &ast {
+            let name = match item {
+                Item::Let(let_item) => let_item.name,
+                Item::Fn(fn_item) => fn_item.function.name,
+                _ => continue,
+            };
+            let name = name.token.span.anchor(source).as_str();
+            let chunk = &mut emitter.chunk;
+            slots.entry(name).or_insert_with(|| chunk.add_global_name(name));
+        }
+        emitter.global_slots = Some(slots);
     }
 
-    Ok(emitter.chunk)
+    emitter.hit(FreeSpan::from(0..source.len()));
+    emitter.items(&ast)?;
+    // the VM's output may be buffered
+    let end = FreeSpan::from(source.len()..source.len());
+    emitter.chunk.emit(OpCode::Flush, end);
+
+    check_stack_depth(&emitter.chunk, 0, options)?;
+
+    Ok((emitter.chunk, emitter.warnings))
+}
+
+/// Rejects `chunk` if it may hold more values than [`Options::max_stack_depth`], on top of the
+/// `base` values its frame starts with
+fn check_stack_depth(chunk: &Chunk, base: usize, options: &Options) -> Result {
+    let max = match options.max_stack_depth {
+        Some(max) => max,
+        None => return Ok(()),
+    };
+    let depths = chunk.simulate_stack_depth();
+    let limit = max as i32 - base as i32;
+    if depths.max > limit {
+        let overflow = chunk.steps().find(|(offset, opcode, _)| {
+            let (pops, pushes) = opcode.stack_effect();
+            matches!(depths.depths.get(offset), Some(depth)
+                if depth - i32::from(pops) + i32::from(pushes) > limit)
+        });
+        let (_, _, span) = overflow.expect("some instruction reaches the max depth");
+        return Err(Error::StackOverflow { span, max });
+    }
+    Ok(())
+}
+
+/// Same as [`compile`] but also records the instruction and constant counts into `stats`.
+pub fn compile_with_stats<'alloc>(
+    source: &str,
+    ast: Program,
+    alloc: &'alloc Alloc,
+    stats: &mut Stats,
+) -> std::result::Result<Chunk<'alloc>, Error> {
+    let start = Instant::now();
+    let chunk = compile(source, ast, alloc)?;
+    stats.instructions = chunk.opcodes().count();
+    stats.constants = chunk.constants().count();
+    stats.compile_time = start.elapsed();
+    Ok(chunk)
 }
 
 const DUMMY: u16 = u16::MAX;
 
+/// Most parameters a function may declare, arguments are counted in a byte
+pub const MAX_PARAMETERS: usize = 255;
+
 impl<'src, 'alloc> Emitter<'src, 'alloc> {
+    /// Returns the slot of global `ident` if globals are resolved at compile time
+    fn global_slot(&self, ident: Identifier) -> std::result::Result<Option<u16>, Error> {
+        match &self.global_slots {
+            Some(slots) => {
+                let name = ident.token.span.anchor(self.source).as_str();
+                match slots.get(name) {
+                    Some(&slot) => Ok(Some(slot)),
+                    None => Err(Error::UndefinedGlobal { span: ident.span() }),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
     fn identifier_constant(&mut self, ident: Identifier) -> ConstKey {
         let span_str = ident.token.span.anchor(self.source).as_str();
         let value = Value::new_object(ObjString::new(span_str, self.alloc));
@@ -75,34 +521,53 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
     }
 
     fn add_local(&mut self, let_item: &LetItem) -> Result {
+        let slot = self.declare_local(Some(let_item.name), let_item.span(), let_item.mut_tok.is_some())?;
+        self.locals[slot as usize].initialized = let_item.init.is_some();
+        Ok(())
+    }
+
+    /// Declares the value on top of the stack as a local, returns its stack slot
+    fn declare_local(
+        &mut self,
+        name: Option<Identifier>,
+        let_span: FreeSpan,
+        mutable: bool,
+    ) -> std::result::Result<u16, Error> {
         if self.locals.len() >= (u16::MAX as usize) {
-            return Err(Error::TooManyLocals { span: let_item.name.span() });
+            return Err(Error::TooManyLocals { span: name.map_or(let_span, |name| name.span()) });
         }
-        let ident_slice = |ident: Identifier| ident.token.span.anchor(self.source).as_str();
-        let shadowing = self.locals.iter()
-            .rev()
-            .take_while(|loc| loc.depth == self.scope_depth)
-            .find(|loc| ident_slice(loc.name) == ident_slice(let_item.name));
-        if let Some(local) = shadowing {
-            return Err(Error::Shadowing {
-                shadowing_span: let_item.name.span(),
-                shadowed_span: local.name.span(),
-            });
+        if let Some(name) = name {
+            let ident_slice = |ident: Identifier| ident.token.span.anchor(self.source).as_str();
+            let shadowing = self.locals.iter()
+                .rev()
+                .take_while(|loc| loc.depth == self.scope_depth)
+                .filter_map(|loc| loc.name)
+                .find(|&shadowed| ident_slice(shadowed) == ident_slice(name));
+            if let Some(shadowed) = shadowing {
+                return Err(Error::Shadowing {
+                    shadowing_span: name.span(),
+                    shadowed_span: shadowed.span(),
+                });
+            }
         }
+        let slot = self.locals.len() as u16;
         self.locals.push(Local {
-            name: let_item.name,
-            let_span: let_item.span(),
-            mutable: let_item.mut_tok.is_some(),
+            name,
+            let_span,
+            mutable,
             depth: self.scope_depth,
+            initialized: true,
+            live_from: self.chunk.spans().len(),
         });
-        Ok(())
+        Ok(slot)
     }
 
-    fn resolve_local(&mut self, name: Identifier) -> Option<(u16, &Local)> {
+    fn resolve_local(&self, name: Identifier) -> Option<(u16, &Local)> {
         let ident_slice = |ident: Identifier| ident.token.span.anchor(self.source).as_str();
+        // slots count from the bottom of the stack, search from the top for the innermost binding
         self.locals.iter()
-            .rev().enumerate()
-            .find(|(_, loc)| ident_slice(loc.name) == ident_slice(name))
+            .enumerate().rev()
+            .find(|(_, loc)| loc.name.map(ident_slice) == Some(ident_slice(name)))
             .map(|(slot, loc)| (slot as u16, loc))
     }
 
@@ -110,6 +575,58 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         self.scope_depth += 1;
     }
 
+    fn begin_loop(&mut self, label: Option<Token>, continue_point: Option<LoopPoint>) {
+        let label = label.map(|label| {
+            label.span.anchor(self.source).as_str()
+                .strip_suffix(':').unwrap()
+        });
+        self.loops.push(Loop {
+            label,
+            depth: self.scope_depth,
+            continue_point,
+            break_jumps: Vec::new(),
+            continue_jumps: Vec::new(),
+        });
+    }
+
+    fn end_loop(&mut self) -> Loop<'src> {
+        self.loops.pop().expect("unbalanced loops")
+    }
+
+    /// Finds the loop a `break` or `continue` exits, `outside_loop` is the error if there is none
+    fn target_loop(&self, label: Option<Identifier>, outside_loop: Error) -> std::result::Result<usize, Error> {
+        match label {
+            Some(label) => {
+                let name = label.token.span.anchor(self.source).as_str();
+                self.loops.iter()
+                    .rposition(|lp| lp.label == Some(name))
+                    .ok_or(Error::UndefinedLabel { span: label.span() })
+            }
+            None => self.loops.len().checked_sub(1)
+                .ok_or(outside_loop),
+        }
+    }
+
+    /// Counts entering the code of `span`, with [`Options::coverage`]
+    fn hit(&mut self, span: FreeSpan) {
+        if self.options.coverage {
+            let id = self.chunk.add_coverage_block(span);
+            self.chunk.emit(OpCode::Hit { id }, span);
+        }
+    }
+
+    /// Pops the locals of the body of loop `index` without ending their scopes
+    fn pop_loop_locals(&mut self, index: usize, span: FreeSpan) {
+        let depth = self.loops[index].depth;
+        let count = self.locals.iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .count();
+        for _ in 0..count {
+            self.chunk.emit(OpCode::Pop, span);
+        }
+    }
+
     fn end_scope(&mut self, span: FreeSpan) {
         assert!(self.scope_depth > 0);
         self.scope_depth -= 1;
@@ -117,6 +634,14 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
             if local.depth <= self.scope_depth {
                 break
             }
+            if let Some(name) = local.name {
+                let info = LocalDebugInfo {
+                    slot: (self.locals.len() - 1) as u16,
+                    name: name.span(),
+                    live: local.live_from..self.chunk.spans().len(),
+                };
+                self.chunk.add_local_debug_info(info);
+            }
             self.locals.pop();
             self.chunk.emit(OpCode::Pop, span);
         }
@@ -124,21 +649,110 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
 }
 
 impl<'src, 'alloc> Emitter<'src, 'alloc> {
+    /// Compiles a sequence of items
+    ///
+    /// With coverage the items after an `if`, a loop or a jump are a block of their own, they
+    /// don't always run when the items before them do.
+    fn items(&mut self, items: &[Item]) -> Result {
+        for (index, item) in items.iter().enumerate() {
+            self.item(item)?;
+            let merges = matches!(
+                item,
+                Item::Statement(
+                    Statement::If(_) | Statement::While(_) | Statement::For(_) |
+                    Statement::Break(_) | Statement::Continue(_) | Statement::Return(_)
+                ),
+            );
+            if let (true, Some(next), Some(last)) = (merges, items.get(index + 1), items.last()) {
+                self.hit(FreeSpan::join(next.span(), last.span()));
+            }
+        }
+        Ok(())
+    }
+
     fn item(&mut self, item: &Item) -> Result {
         match item {
             Item::Class(class_item) => self.class_item(class_item),
             Item::Fn(fn_item) => self.fn_item(fn_item),
             Item::Let(let_item) => self.let_item(let_item),
             Item::Statement(stmt) => self.statement(stmt),
+            Item::Error(error_item) => Err(Error::Unparsed { span: error_item.span }),
+        }?;
+
+        // checked per item, so nested blocks are caught before they finish
+        if self.chunk.spans().len() > self.options.max_instructions {
+            return Err(Error::ProgramTooLarge {
+                span: item.span(),
+                max: self.options.max_instructions,
+            });
         }
+        Ok(())
     }
 
     fn class_item(&mut self, _class_item: &ClassItem) -> Result {
         todo!("class")
     }
 
-    fn fn_item(&mut self, _fun_item: &FnItem) -> Result {
-        todo!("function")
+    fn fn_item(&mut self, fn_item: &FnItem) -> Result {
+        let span = fn_item.span();
+        let function = self.function(&fn_item.function)?;
+        self.chunk.emit_constant(function, span);
+
+        // bound like an immutable `let`
+        let name = fn_item.function.name;
+        if self.scope_depth == 0 {
+            if let Some(slot) = self.global_slot(name)? {
+                self.chunk.emit(OpCode::DefGlobalSlot { slot }, span);
+            } else {
+                let name_key = self.identifier_constant(name);
+                self.chunk.emit(OpCode::DefGlobal { name_key }, span);
+            }
+        } else {
+            self.declare_local(Some(name), span, false)?;
+        }
+        Ok(())
+    }
+
+    /// Compiles `function` into a chunk of its own, returns the function object
+    ///
+    /// The body has a locals stack of its own, slot 0 holds the function being called and the
+    /// parameters follow it. Locals of the enclosing scopes aren't visible in the body, their
+    /// names resolve to globals there. Falling off the end of the body returns `()`.
+    fn function(&mut self, function: &Function) -> std::result::Result<Value<'alloc>, Error> {
+        let parameters = &function.parameters.items;
+        if let Some(param) = parameters.get(MAX_PARAMETERS) {
+            return Err(Error::TooManyParameters { span: param.name.span() });
+        }
+
+        let mut emitter = Emitter {
+            source: self.source,
+            alloc: self.alloc,
+            chunk: Chunk::default(),
+            warnings: Vec::default(),
+            options: self.options.clone(),
+            locals: Vec::default(),
+            scope_depth: 1,
+            loops: Vec::default(),
+            in_function: true,
+            global_slots: self.global_slots.clone(),
+        };
+        emitter.chunk.set_catch_asserts(self.options.catch_asserts);
+        emitter.declare_local(None, function.name.span(), false)?;
+        for param in parameters {
+            emitter.declare_local(Some(param.name), param.name.span(), param.mut_tok.is_some())?;
+        }
+        emitter.hit(function.body.span());
+        emitter.items(&function.body.body)?;
+        let end = function.body.right_brace_tok.span;
+        emitter.chunk.emit(OpCode::Unit, end);
+        emitter.chunk.emit(OpCode::Return, end);
+        // the callee and the arguments are pushed by the caller
+        check_stack_depth(&emitter.chunk, parameters.len() + 1, &self.options)?;
+        self.warnings.append(&mut emitter.warnings);
+
+        let name = function.name.token.span.anchor(self.source).as_str();
+        let arity = parameters.len() as u32;
+        Ok(Value::new_object(ObjFunction::new(emitter.chunk, arity, name, self.alloc)))
     }
 
     fn let_item(&mut self, let_item: &LetItem) -> Result {
@@ -153,8 +767,12 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
 
         if self.scope_depth == 0 {
             // global variable
-            let name_key = self.identifier_constant(let_item.name);
-            self.chunk.emit(OpCode::DefGlobal { name_key }, span);
+            if let Some(slot) = self.global_slot(let_item.name)? {
+                self.chunk.emit(OpCode::DefGlobalSlot { slot }, span);
+            } else {
+                let name_key = self.identifier_constant(let_item.name);
+                self.chunk.emit(OpCode::DefGlobal { name_key }, span);
+            }
         } else {
             // local variable
             self.add_local(let_item)?;
@@ -172,23 +790,155 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
             Statement::Print(print_stmt) => self.print_stmt(print_stmt),
             Statement::Return(return_stmt) => self.return_stmt(return_stmt),
             Statement::While(while_stmt) => self.while_stmt(while_stmt),
+            Statement::Break(break_stmt) => self.break_stmt(break_stmt),
+            Statement::Continue(continue_stmt) => self.continue_stmt(continue_stmt),
             Statement::Block(block) => self.block(block),
         }
     }
 
     fn expr_stmt(&mut self, expr_stmt: &ExprStmt) -> Result {
+        if !has_side_effects(&expr_stmt.expr) {
+            self.warnings.push(Warning::UnusedExpressionResult {
+                span: expr_stmt.expr.span(),
+            });
+        }
         self.expression(&expr_stmt.expr)?;
         self.chunk.emit(OpCode::Pop, expr_stmt.semicolon_tok.span);
         Ok(())
     }
 
-    fn for_stmt(&mut self, _for_stmt: &ForStmt) -> Result {
-        todo!()
+    fn for_stmt(&mut self, for_stmt: &ForStmt) -> Result {
+        let range = match &for_stmt.iter {
+            Expression::Binary(binary) if binary.operator.kind == TokenKind::DotDot => binary,
+            _ => return self.for_iterable(for_stmt),
+        };
+
+        // literal ranges are counted through directly, without building the Range object
+        let span = FreeSpan::join(for_stmt.for_tok.span, range.span());
+        let end_span = for_stmt.body.right_brace_tok.span;
+
+        // hidden locals for the counter and the exclusive end, both evaluated once
+        self.begin_scope();
+        self.expression(&range.lhs)?;
+        let counter = self.declare_local(None, span, true)?;
+        self.expression(&range.rhs)?;
+        let end = self.declare_local(None, span, false)?;
+
+        // while counter < end
+        let loop_start = self.chunk.loop_point();
+        let synthetic = self.chunk.instruction_count();
+        self.chunk.emit(OpCode::GetLocal { slot: counter }, span);
+        self.chunk.emit(OpCode::GetLocal { slot: end }, span);
+        self.chunk.emit(OpCode::Less, span);
+        self.chunk.mark_synthetic(synthetic..self.chunk.instruction_count());
+        let exit_jump = self.chunk.emit(OpCode::JumpIfFalse { offset: DUMMY }, span);
+        self.chunk.emit(OpCode::Pop, span);
+
+        // the element is an immutable copy of the counter, scoped to a single iteration
+        self.begin_loop(for_stmt.label, None);
+        self.begin_scope();
+        self.chunk.emit(OpCode::GetLocal { slot: counter }, for_stmt.elem.span());
+        let elem_span = FreeSpan::join(for_stmt.for_tok.span, for_stmt.elem.span());
+        self.declare_local(Some(for_stmt.elem), elem_span, false)?;
+        self.block(&for_stmt.body)?;
+        self.end_scope(end_span);
+        let lp = self.end_loop();
+
+        // counter = counter + 1, stepping is what the range stands for
+        for continue_jump in lp.continue_jumps {
+            self.chunk.patch_jump(continue_jump);
+        }
+        let synthetic = self.chunk.instruction_count();
+        let step_span = range.span();
+        self.chunk.emit(OpCode::GetLocal { slot: counter }, step_span);
+        self.chunk.emit_constant(Value::new_float(1.0), step_span);
+        self.chunk.emit(OpCode::Add, step_span);
+        self.chunk.emit(OpCode::SetLocal { slot: counter }, step_span);
+        self.chunk.emit(OpCode::Pop, step_span);
+        self.chunk.mark_synthetic(synthetic..self.chunk.instruction_count());
+        self.chunk.emit_loop(loop_start, end_span);
+
+        // end, pops the condition
+        self.chunk.patch_jump(exit_jump);
+        self.chunk.emit(OpCode::Pop, span);
+        for break_jump in lp.break_jumps {
+            self.chunk.patch_jump(break_jump);
+        }
+        self.end_scope(end_span);
+
+        Ok(())
+    }
+
+    /// Compiles a `for` over any iterable value by indexing it
+    fn for_iterable(&mut self, for_stmt: &ForStmt) -> Result {
+        let span = FreeSpan::join(for_stmt.for_tok.span, for_stmt.iter.span());
+        let end_span = for_stmt.body.right_brace_tok.span;
+
+        // hidden locals for the iterable and the current index
+        self.begin_scope();
+        self.expression(&for_stmt.iter)?;
+        let iterable = self.declare_local(None, span, false)?;
+        self.chunk.emit_constant(Value::new_float(0.0), span);
+        let index = self.declare_local(None, span, true)?;
+
+        // while index < len(iterable)
+        let loop_start = self.chunk.loop_point();
+        let synthetic = self.chunk.instruction_count();
+        self.chunk.emit(OpCode::GetLocal { slot: index }, span);
+        self.chunk.emit(OpCode::GetLocal { slot: iterable }, span);
+        self.chunk.emit(OpCode::Len, span);
+        self.chunk.emit(OpCode::Less, span);
+        self.chunk.mark_synthetic(synthetic..self.chunk.instruction_count());
+        let exit_jump = self.chunk.emit(OpCode::JumpIfFalse { offset: DUMMY }, span);
+        self.chunk.emit(OpCode::Pop, span);
+
+        // elem = iterable[index]
+        self.begin_loop(for_stmt.label, None);
+        self.begin_scope();
+        self.chunk.emit(OpCode::GetLocal { slot: iterable }, for_stmt.elem.span());
+        self.chunk.emit(OpCode::GetLocal { slot: index }, for_stmt.elem.span());
+        self.chunk.emit(OpCode::GetIndex, for_stmt.elem.span());
+        let elem_span = FreeSpan::join(for_stmt.for_tok.span, for_stmt.elem.span());
+        self.declare_local(Some(for_stmt.elem), elem_span, false)?;
+        self.block(&for_stmt.body)?;
+        self.end_scope(end_span);
+        let lp = self.end_loop();
+
+        // index = index + 1
+        for continue_jump in lp.continue_jumps {
+            self.chunk.patch_jump(continue_jump);
+        }
+        let synthetic = self.chunk.instruction_count();
+        let step_span = for_stmt.iter.span();
+        self.chunk.emit(OpCode::GetLocal { slot: index }, step_span);
+        self.chunk.emit_constant(Value::new_float(1.0), step_span);
+        self.chunk.emit(OpCode::Add, step_span);
+        self.chunk.emit(OpCode::SetLocal { slot: index }, step_span);
+        self.chunk.emit(OpCode::Pop, step_span);
+        self.chunk.mark_synthetic(synthetic..self.chunk.instruction_count());
+        self.chunk.emit_loop(loop_start, end_span);
+
+        // end, pops the condition
+        self.chunk.patch_jump(exit_jump);
+        self.chunk.emit(OpCode::Pop, span);
+        for break_jump in lp.break_jumps {
+            self.chunk.patch_jump(break_jump);
+        }
+        self.end_scope(end_span);
+
+        Ok(())
     }
 
     fn if_stmt(&mut self, if_stmt: &IfStmt) -> Result {
         // if <pred>
+        self.check_condition(&if_stmt.pred);
         self.expression(&if_stmt.pred)?;
+        if let Some(else_branch) = &if_stmt.else_branch {
+            if matches!(self.constant(&if_stmt.pred), Some(pred) if !pred.is_falsy()) {
+                let span = FreeSpan::join(else_branch.else_tok.span, else_branch.body.span());
+                self.warnings.push(Warning::UnreachableBranch { span });
+            }
+        }
         let then_jump = self.chunk.emit(OpCode::JumpIfFalse { offset: DUMMY }, if_stmt.if_tok.span);
 
         // then
@@ -209,9 +959,109 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         Ok(())
     }
 
+    fn check_condition(&mut self, pred: &Expression) {
+        if let Expression::Binary(binary) = pred {
+            if binary.operator.kind == TokenKind::Equal {
+                self.warnings.push(Warning::AssignmentInCondition { span: pred.span() });
+            }
+        }
+    }
+
+    /// Returns whether `items` contain a `return` or a `break` leaving the loop they are the body
+    /// of, `inner_labels` is the labels of the loops nested in it around `items`
+    fn exits_loop(&self, items: &[Item], inner_labels: &mut Vec<&'src str>, nested: bool) -> bool {
+        items.iter().any(|item| {
+            let stmt = match item {
+                Item::Statement(stmt) => stmt,
+                // functions and classes can't leave the loop they're declared in
+                _ => return false,
+            };
+            match stmt {
+                Statement::Return(_) => true,
+                Statement::Break(BreakStmt { label: None, .. }) => !nested,
+                Statement::Break(BreakStmt { label: Some(label), .. }) => {
+                    let label = label.span().anchor(self.source).as_str();
+                    !inner_labels.contains(&label)
+                }
+                Statement::If(if_stmt) => {
+                    self.exits_loop(&if_stmt.body.body, inner_labels, nested)
+                        || matches!(&if_stmt.else_branch, Some(branch)
+                            if self.exits_loop(&branch.body.body, inner_labels, nested))
+                }
+                Statement::Block(block) => self.exits_loop(&block.body, inner_labels, nested),
+                Statement::While(WhileStmt { label, body, .. }) |
+                Statement::For(ForStmt { label, body, .. }) => {
+                    let pushed = label.map(|label| {
+                        let label = label.span.anchor(self.source).as_str();
+                        inner_labels.push(label.strip_suffix(':').unwrap_or(label));
+                    });
+                    let exits = self.exits_loop(&body.body, inner_labels, true);
+                    if pushed.is_some() {
+                        inner_labels.pop();
+                    }
+                    exits
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Compiles an `assert`, a condition chaining `and`s is asserted one operand at a time
+    ///
+    /// Each operand of `assert a and b and c;` gets its own assertion, spanning only that
+    /// operand, so a failure points at the first false one. The operands must then all be Bools.
+    /// A chunk which [catches assertions](Options::catch_asserts) would go on evaluating the rest
+    /// of the chain after a failure, so there the whole condition is asserted at once.
     fn assert_stmt(&mut self, assert_stmt: &AssertStmt) -> Result {
-        self.expression(&assert_stmt.expr)?;
-        self.chunk.emit(OpCode::Assert, assert_stmt.span());
+        let opcode = if let Some(because) = &assert_stmt.because {
+            let slice = self.string_contents(because.reason.span)?;
+            let reason = Value::new_object(ObjString::new(slice, self.alloc));
+            let reason_key = self.chunk.insert_constant(reason);
+            OpCode::AssertBecause { reason_key }
+        } else if self.options.debug_asserts {
+            OpCode::DebugAssert
+        } else {
+            OpCode::Assert
+        };
+
+        let mut conjuncts = vec![&assert_stmt.expr];
+        if !self.options.catch_asserts {
+            // operators are left associative, split from the last operand backwards
+            while let Expression::Binary(binary) = conjuncts[0] {
+                if binary.operator.kind != TokenKind::And {
+                    break;
+                }
+                conjuncts[0] = &binary.rhs;
+                conjuncts.insert(0, &binary.lhs);
+            }
+        }
+
+        if let [expr] = conjuncts[..] {
+            self.expression(expr)?;
+            self.chunk.emit(opcode, assert_stmt.span());
+            return Ok(());
+        }
+        // a falsy operand jumps to an assert of its own, so it fails where the whole condition
+        // would and with the same error
+        let (last, rest) = conjuncts.split_last().unwrap();
+        let mut fail_jumps = Vec::with_capacity(rest.len());
+        for expr in rest {
+            self.expression(expr)?;
+            fail_jumps.push((self.chunk.emit(OpCode::JumpIfFalse { offset: DUMMY }, expr.span()), expr));
+            self.chunk.emit(OpCode::Pop, expr.span());
+        }
+        self.expression(last)?;
+        self.chunk.emit(opcode, last.span());
+
+        let mut end_jumps = Vec::with_capacity(fail_jumps.len());
+        for (fail_jump, expr) in fail_jumps {
+            end_jumps.push(self.chunk.emit(OpCode::Jump { offset: DUMMY }, expr.span()));
+            self.chunk.patch_jump(fail_jump);
+            self.chunk.emit(opcode, expr.span());
+        }
+        for end_jump in end_jumps {
+            self.chunk.patch_jump(end_jump);
+        }
         Ok(())
     }
 
@@ -221,35 +1071,78 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         Ok(())
     }
 
-    fn return_stmt(&mut self, _return_stmt: &ReturnStmt) -> Result {
-        todo!()
+    fn return_stmt(&mut self, return_stmt: &ReturnStmt) -> Result {
+        let span = return_stmt.span();
+        if !self.in_function {
+            return Err(Error::ReturnOutsideFunction { span });
+        }
+        match &return_stmt.expr {
+            Some(expr) => self.expression(expr)?,
+            None => {
+                self.chunk.emit(OpCode::Unit, span);
+            }
+        }
+        self.chunk.emit(OpCode::Return, span);
+        Ok(())
     }
 
     fn while_stmt(&mut self, while_stmt: &WhileStmt) -> Result {
         let loop_start = self.chunk.loop_point();
 
         // while <pred>
+        self.check_condition(&while_stmt.pred);
         self.expression(&while_stmt.pred)?;
         let span = FreeSpan::join(while_stmt.while_tok.span, while_stmt.pred.span());
+        let forever = matches!(self.constant(&while_stmt.pred), Some(pred) if !pred.is_falsy());
+        if forever && !self.exits_loop(&while_stmt.body.body, &mut Vec::new(), false) {
+            self.warnings.push(Warning::InfiniteLoop { span });
+        }
         let exit_jump = self.chunk.emit(OpCode::JumpIfFalse { offset: DUMMY }, span);
 
-        // then
-        self.chunk.emit(OpCode::Pop, while_stmt.body.left_brace_tok.span);
+        // then, popping the condition is attributed to the condition
+        let pred_span = while_stmt.pred.span();
+        self.chunk.emit(OpCode::Pop, pred_span);
+        self.begin_loop(while_stmt.label, Some(loop_start));
         self.block(&while_stmt.body)?;
+        let lp = self.end_loop();
         self.chunk.emit_loop(loop_start, while_stmt.body.right_brace_tok.span);
 
         // end
         self.chunk.patch_jump(exit_jump);
-        self.chunk.emit(OpCode::Pop, while_stmt.body.right_brace_tok.span);
+        self.chunk.emit(OpCode::Pop, pred_span);
+        for break_jump in lp.break_jumps {
+            self.chunk.patch_jump(break_jump);
+        }
+
+        Ok(())
+    }
 
+    fn break_stmt(&mut self, break_stmt: &BreakStmt) -> Result {
+        let span = break_stmt.span();
+        let index = self.target_loop(break_stmt.label, Error::BreakOutsideLoop { span })?;
+        self.pop_loop_locals(index, span);
+        let break_jump = self.chunk.emit(OpCode::Jump { offset: DUMMY }, span);
+        self.loops[index].break_jumps.push(break_jump);
+        Ok(())
+    }
+
+    fn continue_stmt(&mut self, continue_stmt: &ContinueStmt) -> Result {
+        let span = continue_stmt.span();
+        let index = self.target_loop(continue_stmt.label, Error::ContinueOutsideLoop { span })?;
+        self.pop_loop_locals(index, span);
+        if let Some(continue_point) = self.loops[index].continue_point {
+            self.chunk.emit_loop(continue_point, span);
+        } else {
+            let continue_jump = self.chunk.emit(OpCode::Jump { offset: DUMMY }, span);
+            self.loops[index].continue_jumps.push(continue_jump);
+        }
         Ok(())
     }
 
     fn block(&mut self, block: &Block) -> Result {
         self.begin_scope();
-        for i in &block.body {
-            self.item(i)?;
-        }
+        self.hit(block.span());
+        self.items(&block.body)?;
         self.end_scope(block.right_brace_tok.span);
         Ok(())
     }
@@ -261,6 +1154,8 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
             Expression::Field(field_expr) => self.field_expr(field_expr),
             Expression::Group(group_expr) => self.group_expr(group_expr),
             Expression::Call(call_expr) => self.call_expr(call_expr),
+            Expression::Dbg(dbg_expr) => self.dbg_expr(dbg_expr),
+            Expression::Format(format_expr) => self.format_expr(format_expr),
             Expression::Primary(primary_expr) => self.primary_expr(primary_expr),
         }
     }
@@ -281,14 +1176,23 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
                                 assign_span: binary_expr.span(),
                             });
                         }
+                        self.locals[slot as usize].initialized = true;
                         self.chunk.emit(OpCode::SetLocal { slot }, binary_expr.span());
+                    } else if let Some(slot) = self.global_slot(ident)? {
+                        // undefined global errors underline the name only
+                        self.chunk.emit(OpCode::SetGlobalSlot { slot }, ident.span());
                     } else {
                         let name_key = self.identifier_constant(ident);
-                        self.chunk.emit(OpCode::SetGlobal { name_key }, binary_expr.span());
+                        self.chunk.emit(OpCode::SetGlobal { name_key }, ident.span());
                     }
                     return Ok(())
                 }
             }
+            if let Expression::Group(group) = &*binary_expr.lhs {
+                return Err(Error::AssignToGroup {
+                    span: group.span(),
+                });
+            }
             // TODO more complex assignment target
             return Err(Error::InvalidAssignmentTarget {
                 span: binary_expr.lhs.span(),
@@ -296,11 +1200,44 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         }
 
         if op == TokenKind::Or {
-            return self.or(binary_expr);
+            // if lhs is true, short-circuit, jump over rhs
+            return self.short_circuit(binary_expr, OpCode::JumpIfTrue { offset: DUMMY });
         }
 
         if op == TokenKind::And {
-            return self.and(binary_expr);
+            // if lhs is false, short-circuit, jump over rhs
+            return self.short_circuit(binary_expr, OpCode::JumpIfFalse { offset: DUMMY });
+        }
+
+        if op == TokenKind::QuestionQuestion {
+            return self.unit_coalesce(binary_expr);
+        }
+
+        if let (
+            TokenKind::EqualEqual | TokenKind::NotEqual | TokenKind::StrictEqual | TokenKind::StrictNotEqual,
+            Some(lhs),
+            Some(rhs),
+        ) = (op, self.constant(&binary_expr.lhs), self.constant(&binary_expr.rhs)) {
+            // both operands are known, fold the comparison
+            let equal = match op {
+                TokenKind::StrictEqual | TokenKind::StrictNotEqual => lhs.strict_eq(&rhs),
+                _ => lhs == rhs,
+            };
+            let equal = equal == matches!(op, TokenKind::EqualEqual | TokenKind::StrictEqual);
+            let opcode = if equal { OpCode::True } else { OpCode::False };
+            self.chunk.emit(opcode, binary_expr.span());
+            return Ok(());
+        }
+
+        if let (TokenKind::Mul, Some(lhs), Some(rhs)) =
+            (op, self.string_literal(&binary_expr.lhs), self.constant(&binary_expr.rhs))
+        {
+            // repeating a literal, invalid counts and too long results are left to fail at runtime
+            let string = ObjString::new(lhs, self.alloc);
+            if let Some(repeated) = rhs.to_float().and_then(|times| string.repeat(times, self.alloc).ok()) {
+                self.chunk.emit_constant(Value::new_object(repeated), binary_expr.span());
+                return Ok(());
+            }
         }
 
         // normal binary operations with eagerly evaluated operands
@@ -308,7 +1245,8 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         self.expression(&binary_expr.lhs)?;
         self.expression(&binary_expr.rhs)?;
 
-        let span = binary_expr.span();
+        // runtime errors point at the operator, the operands may span several lines
+        let span = binary_expr.operator.span;
         match op {
             TokenKind::NotEqual => {
                 self.chunk.emit(OpCode::Equal, span);
@@ -317,6 +1255,13 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
             TokenKind::EqualEqual => {
                 self.chunk.emit(OpCode::Equal, span);
             }
+            TokenKind::StrictNotEqual => {
+                self.chunk.emit(OpCode::StrictEqual, span);
+                self.chunk.emit(OpCode::Not, span);
+            }
+            TokenKind::StrictEqual => {
+                self.chunk.emit(OpCode::StrictEqual, span);
+            }
             TokenKind::Greater => {
                 self.chunk.emit(OpCode::Greater, span);
             }
@@ -343,40 +1288,79 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
             TokenKind::Div => {
                 self.chunk.emit(OpCode::Divide, span);
             }
+            TokenKind::DotDot => {
+                self.chunk.emit(OpCode::BuildRange, span);
+            }
             _ => unreachable!()
         }
         Ok(())
     }
 
-    fn and(&mut self, binary_expr: &BinaryExpr) -> Result {
+    /// Compiles `lhs ?? rhs`, `rhs` is only evaluated if `lhs` is `()`
+    fn unit_coalesce(&mut self, binary_expr: &BinaryExpr) -> Result {
+        let span = binary_expr.operator.span;
         self.expression(&binary_expr.lhs)?;
+        self.chunk.emit(OpCode::Dup, span);
+        self.chunk.emit(OpCode::IsUnit, span);
+        let keep_jump = self.chunk.emit(OpCode::JumpIfFalse { offset: DUMMY }, span);
 
-        // if lhs is false, short-circuit, jump over rhs
-        // span both lhs and the `and` operator
-        let span = FreeSpan::join(binary_expr.lhs.span(), binary_expr.operator.span);
-        let end_jump = self.chunk.emit(OpCode::JumpIfFalse { offset: DUMMY }, span);
-
-        // pop lhs result, span of the `and` operator
-        self.chunk.emit(OpCode::Pop, binary_expr.operator.span);
+        // pop the check and the `()`
+        self.chunk.emit(OpCode::Pop, span);
+        self.chunk.emit(OpCode::Pop, span);
         self.expression(&binary_expr.rhs)?;
+        let end_jump = self.chunk.emit(OpCode::Jump { offset: DUMMY }, span);
 
+        // pop the check, keep lhs
+        self.chunk.patch_jump(keep_jump);
+        self.chunk.emit(OpCode::Pop, span);
         self.chunk.patch_jump(end_jump);
         Ok(())
     }
 
-    fn or(&mut self, binary_expr: &BinaryExpr) -> Result {
-        self.expression(&binary_expr.lhs)?;
+    /// Compiles `and`/`or` with `jump` skipping the rest of the chain
+    ///
+    /// A chain of the same operator, `a and b and c`, is compiled flat with every jump going to
+    /// the end of the whole chain instead of to the next operator.
+    fn short_circuit(&mut self, binary_expr: &BinaryExpr, jump: OpCode) -> Result {
+        // operators are left associative, the first operand is the innermost lhs
+        let mut links = vec![binary_expr];
+        let mut first = &*binary_expr.lhs;
+        while let Expression::Binary(inner) = first {
+            if inner.operator.kind != binary_expr.operator.kind {
+                break;
+            }
+            links.push(inner);
+            first = &inner.lhs;
+        }
 
-        // if lhs is true, short-circuit, jump over rhs
-        // span both lhs and the `or` operator
-        let span = FreeSpan::join(binary_expr.lhs.span(), binary_expr.operator.span);
-        let end_jump = self.chunk.emit(OpCode::JumpIfTrue { offset: DUMMY }, span);
+        if self.options.warn_logical_precedence {
+            let other = |expr: &Expression| matches!(
+                expr,
+                Expression::Binary(inner) if matches!(inner.operator.kind, TokenKind::And | TokenKind::Or)
+                    && inner.operator.kind != binary_expr.operator.kind,
+            );
+            if other(first) || links.iter().any(|link| other(&link.rhs)) {
+                self.warnings.push(Warning::AmbiguousLogicalPrecedence {
+                    span: binary_expr.span(),
+                });
+            }
+        }
 
-        // pop lhs result, span of the `or` operator
-        self.chunk.emit(OpCode::Pop, binary_expr.operator.span);
-        self.expression(&binary_expr.rhs)?;
+        self.expression(first)?;
+        let mut end_jumps = Vec::with_capacity(links.len());
+        for link in links.iter().rev() {
+            // span both lhs and the operator
+            let span = FreeSpan::join(link.lhs.span(), link.operator.span);
+            end_jumps.push(self.chunk.emit(jump, span));
 
-        self.chunk.patch_jump(end_jump);
+            // pop lhs result, span of the operator
+            self.chunk.emit(OpCode::Pop, link.operator.span);
+            self.expression(&link.rhs)?;
+        }
+
+        for end_jump in end_jumps {
+            self.chunk.patch_jump(end_jump);
+        }
         Ok(())
     }
 
@@ -386,6 +1370,16 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         self.expression(&unary_expr.expr)?;
 
         let span = unary_expr.span();
+
+        // `not` accepts values of any type, only negation can be known to fail
+        let operand = self.literal_type(&unary_expr.expr);
+        if op == TokenKind::Minus && operand.is_some() && operand != Some("Number") {
+            self.warnings.push(Warning::TypeMismatch {
+                span,
+                message: "negation only supported on Numbers",
+            });
+        }
+
         match op {
             TokenKind::Not => {
                 self.chunk.emit(OpCode::Not, span);
@@ -412,8 +1406,55 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         }
     }
 
-    fn call_expr(&mut self, _call_expr: &CallExpr) -> Result {
-        todo!()
+    fn call_expr(&mut self, call_expr: &CallExpr) -> Result {
+        let arguments = &call_expr.arguments.items;
+        if let Some(argument) = arguments.get(MAX_PARAMETERS) {
+            return Err(Error::TooManyArguments { span: argument.span() });
+        }
+        self.expression(&call_expr.fun)?;
+        for argument in arguments {
+            self.expression(argument)?;
+        }
+        let arity = arguments.len() as u8;
+        self.chunk.emit(OpCode::Call { arity }, call_expr.span());
+        Ok(())
+    }
+
+    fn dbg_expr(&mut self, dbg_expr: &DbgExpr) -> Result {
+        self.expression(&dbg_expr.expr)?;
+        // the VM prints the source text of the span next to the value
+        self.chunk.emit(OpCode::Dbg, dbg_expr.expr.span());
+        Ok(())
+    }
+
+    /// Concatenates the pieces of the template and the stringified arguments in order
+    fn format_expr(&mut self, format_expr: &FormatExpr) -> Result {
+        let span = format_expr.span();
+        let template = self.string_contents(format_expr.template.span)?;
+        let arguments = &format_expr.arguments.items;
+        let placeholders = template.matches("{}").count();
+        if placeholders != arguments.len() {
+            return Err(Error::FormatArgMismatch {
+                span: format_expr.template.span,
+                placeholders,
+                arguments: arguments.len(),
+            });
+        }
+
+        let mut pieces = template.split("{}");
+        // always start with a String even if the template starts with a placeholder
+        let first = pieces.next().unwrap_or("");
+        self.chunk.emit_constant(Value::new_object(ObjString::new(first, self.alloc)), span);
+        for (argument, piece) in arguments.iter().zip(pieces) {
+            self.expression(argument)?;
+            self.chunk.emit(OpCode::Stringify, argument.span());
+            self.chunk.emit(OpCode::Add, span);
+            if !piece.is_empty() {
+                self.chunk.emit_constant(Value::new_object(ObjString::new(piece, self.alloc)), span);
+                self.chunk.emit(OpCode::Add, span);
+            }
+        }
+        Ok(())
     }
 
     fn primary_expr(&mut self, primary_expr: &PrimaryExpr) -> Result {
@@ -439,6 +1480,9 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
             TokenKind::String => {
                 self.string(primary_expr)?;
             }
+            TokenKind::Char => {
+                self.char(primary_expr)?;
+            }
             TokenKind::Identifier => {
                 self.identifier(primary_expr)?;
             }
@@ -447,14 +1491,43 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         Ok(())
     }
 
+    /// Returns the value of `expr` if it's a constant of an inline type, see [`const_value`]
+    fn constant(&self, expr: &Expression) -> Option<Value<'static>> {
+        const_value(self.source, expr)
+    }
+
+    /// Returns the contents of `expr` if it's a string literal
+    fn string_literal(&self, expr: &Expression) -> Option<&'src str> {
+        match expr {
+            Expression::Primary(PrimaryExpr { token }) if token.kind == TokenKind::String => {
+                self.string_contents(token.span).ok()
+            }
+            Expression::Group(GroupExpr { expr: Some(expr), .. }) => self.string_literal(expr),
+            _ => None,
+        }
+    }
+
+    /// Returns the type name of `expr` if it's a literal
+    fn literal_type(&self, expr: &Expression) -> Option<&'static str> {
+        match expr {
+            Expression::Primary(PrimaryExpr { token }) => match token.kind {
+                TokenKind::True | TokenKind::False => Some("Bool"),
+                TokenKind::Number => Some("Number"),
+                TokenKind::String | TokenKind::Char => Some("String"),
+                _ => None,
+            },
+            Expression::Group(GroupExpr { expr: None, .. }) => Some("Unit"),
+            Expression::Group(GroupExpr { expr: Some(expr), .. }) => self.literal_type(expr),
+            _ => None,
+        }
+    }
+
     fn float(&mut self, primary: &PrimaryExpr) -> Result {
         let span = primary.token.span;
         let slice = span.anchor(self.source).as_str();
         match slice.parse() {
             Ok(float) => {
-                let value = Value::new_float(float);
-                let key = self.chunk.insert_constant(value);
-                self.chunk.emit(OpCode::Constant { key }, span);
+                self.chunk.emit_constant(Value::new_float(float), span);
             }
             Err(cause) => {
                 return Err(Error::InvalidNumberLiteral { cause, span });
@@ -463,22 +1536,44 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         Ok(())
     }
 
+    /// Contents of the string literal at `span`, without the quotes
+    ///
+    /// Checked instead of trusting the lexer, a span without both quotes or not on char boundaries
+    /// is an error.
+    fn string_contents(&self, span: FreeSpan) -> std::result::Result<&'src str, Error> {
+        self.source.get(span.range())
+            .and_then(string_contents)
+            .ok_or(Error::MalformedStringLiteral { span })
+    }
+
     fn string(&mut self, primary: &PrimaryExpr) -> Result {
         let span = primary.token.span;
-        let slice = span.anchor(self.source).as_str()
-            .strip_prefix('"').unwrap()
-            .strip_suffix('"').unwrap();
+        let slice = self.string_contents(span)?;
         let string = ObjString::new(slice, self.alloc);
-        let value = Value::new_object(string);
-        let key = self.chunk.insert_constant(value);
-        self.chunk.emit(OpCode::Constant { key }, span);
+        self.chunk.emit_constant(Value::new_object(string), span);
+        Ok(())
+    }
+
+    /// Char literals are one character Strings, there is no char type
+    fn char(&mut self, primary: &PrimaryExpr) -> Result {
+        let span = primary.token.span;
+        let c = self.source.get(span.range())
+            .and_then(unescape_char)
+            .ok_or(Error::MalformedStringLiteral { span })?;
+        let string = ObjString::new(c.encode_utf8(&mut [0; 4]), self.alloc);
+        self.chunk.emit_constant(Value::new_object(string), span);
         Ok(())
     }
 
     fn identifier(&mut self, primary: &PrimaryExpr) -> Result {
         let ident = Identifier { token: primary.token };
-        if let Some((slot, _)) = self.resolve_local(ident) {
+        if let Some((slot, local)) = self.resolve_local(ident) {
+            if self.options.deny_uninitialized && !local.initialized {
+                return Err(Error::UseOfUninitialized { span: ident.span() });
+            }
             self.chunk.emit(OpCode::GetLocal { slot }, ident.span());
+        } else if let Some(slot) = self.global_slot(ident)? {
+            self.chunk.emit(OpCode::GetGlobalSlot { slot }, ident.span());
         } else {
             let name_key = self.identifier_constant(ident);
             self.chunk.emit(OpCode::GetGlobal { name_key }, ident.span());
@@ -486,3 +1581,769 @@ impl<'src, 'alloc> Emitter<'src, 'alloc> {
         Ok(())
     }
 }
+
+/// Parses `source` as a single expression and evaluates it if it's constant
+///
+/// Only expressions of literals and operators producing an inline type are constant, identifiers,
+/// strings and operations that would fail at runtime are not.
+pub fn eval_const(source: &str) -> Option<Value<'static>> {
+    let expr = parse_expression(source).ok()?;
+    const_value(source, &expr)
+}
+
+/// Evaluates `expr` if it's made of literals and operators only and has an inline type
+///
+/// Operations which fail at runtime, like `-true`, are never constant so they still fail at
+/// runtime.
+fn const_value(source: &str, expr: &Expression) -> Option<Value<'static>> {
+    match expr {
+        Expression::Primary(PrimaryExpr { token }) => match token.kind {
+            TokenKind::True => Some(Value::new_bool(true)),
+            TokenKind::False => Some(Value::new_bool(false)),
+            TokenKind::Number => {
+                let slice = token.span.anchor(source).as_str();
+                slice.parse().ok().map(Value::new_float)
+            }
+            _ => None,
+        },
+        Expression::Group(GroupExpr { expr: None, .. }) => Some(Value::new_unit()),
+        Expression::Group(GroupExpr { expr: Some(expr), .. }) => const_value(source, expr),
+        Expression::Unary(unary) => {
+            let operand = const_value(source, &unary.expr)?;
+            match unary.operator.kind {
+                TokenKind::Not => Some(Value::new_bool(operand.is_falsy())),
+                TokenKind::Minus => operand.to_float().map(|n| Value::new_float(-n)),
+                _ => None,
+            }
+        }
+        Expression::Binary(binary) => {
+            let lhs = const_value(source, &binary.lhs)?;
+            let rhs = const_value(source, &binary.rhs)?;
+            let op = binary.operator.kind;
+            match op {
+                TokenKind::And => return Some(if lhs.is_falsy() { lhs } else { rhs }),
+                TokenKind::Or => return Some(if lhs.is_falsy() { rhs } else { lhs }),
+                TokenKind::QuestionQuestion => return Some(if lhs.is_unit() { rhs } else { lhs }),
+                TokenKind::EqualEqual => return Some(Value::new_bool(lhs == rhs)),
+                TokenKind::NotEqual => return Some(Value::new_bool(lhs != rhs)),
+                TokenKind::StrictEqual => return Some(Value::new_bool(lhs.strict_eq(&rhs))),
+                TokenKind::StrictNotEqual => return Some(Value::new_bool(!lhs.strict_eq(&rhs))),
+                _ => {}
+            }
+            let (lhs, rhs) = (lhs.to_float()?, rhs.to_float()?);
+            match op {
+                TokenKind::Plus => Some(Value::new_float(lhs + rhs)),
+                TokenKind::Minus => Some(Value::new_float(lhs - rhs)),
+                TokenKind::Mul => Some(Value::new_float(lhs * rhs)),
+                TokenKind::Div => Some(Value::new_float(lhs / rhs)),
+                TokenKind::Greater => Some(Value::new_bool(lhs > rhs)),
+                TokenKind::GreaterEqual => Some(Value::new_bool(lhs >= rhs)),
+                TokenKind::Less => Some(Value::new_bool(lhs < rhs)),
+                TokenKind::LessEqual => Some(Value::new_bool(lhs <= rhs)),
+                // assignment and ranges
+                _ => None,
+            }
+        }
+        Expression::Field(_) | Expression::Call(_) | Expression::Dbg(_) | Expression::Format(_) => None,
+    }
+}
+
+/// Returns whether evaluating `expr` can do more than produce a value
+///
+/// Runtime type errors don't count, `1 + true;` is still considered unused.
+fn has_side_effects(expr: &Expression) -> bool {
+    match expr {
+        Expression::Binary(binary) => {
+            binary.operator.kind == TokenKind::Equal ||
+                has_side_effects(&binary.lhs) ||
+                has_side_effects(&binary.rhs)
+        }
+        Expression::Unary(unary) => has_side_effects(&unary.expr),
+        Expression::Field(field) => has_side_effects(&field.expr),
+        Expression::Group(group) => matches!(group.expr.as_deref(), Some(expr) if has_side_effects(expr)),
+        Expression::Format(format) => format.arguments.items.iter().any(has_side_effects),
+        Expression::Call(_) | Expression::Dbg(_) => true,
+        Expression::Primary(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile, compile_with, compile_with_warnings, Error, Options, Warning, MAX_PARAMETERS};
+    use crate::object::function::Function as ObjFunction;
+    use crate::object::string::String as ObjString;
+    use crate::object::Alloc;
+    use crate::opcode::OpCode;
+    use crate::parser::parse;
+    use std::assert_matches::assert_matches;
+
+    /// Opcodes of the program without the final `Flush`
+    fn opcodes(src: &str) -> Vec<OpCode> {
+        let alloc = Alloc::new();
+        let ast = parse(src).unwrap();
+        let chunk = compile(src, ast, &alloc).unwrap();
+        let mut opcodes: Vec<_> = chunk.opcodes().collect();
+        assert_eq!(opcodes.pop(), Some(OpCode::Flush));
+        opcodes
+    }
+
+    #[test]
+    fn test_fold_equality() {
+        assert_eq!(opcodes("true == true;"), [OpCode::True, OpCode::Pop]);
+        // no coercion between booleans and numbers
+        assert_eq!(opcodes("true == 1;"), [OpCode::False, OpCode::Pop]);
+        assert_eq!(opcodes("(false) /= ();"), [OpCode::True, OpCode::Pop]);
+        assert_eq!(opcodes("1 === 1.0;"), [OpCode::True, OpCode::Pop]);
+        assert_eq!(opcodes("1 /== true;"), [OpCode::True, OpCode::Pop]);
+    }
+
+    fn warnings(src: &str) -> Vec<Warning> {
+        let alloc = Alloc::new();
+        let ast = parse(src).unwrap();
+        let (_, warnings) = compile_with_warnings(src, ast, &alloc).unwrap();
+        warnings
+    }
+
+    #[test]
+    fn test_unreachable_branch() {
+        let src = "if true {} else { print dead; }";
+        let span = match warnings(src)[..] {
+            [Warning::UnreachableBranch { span }] => span,
+            ref warnings => panic!("expected UnreachableBranch, got {:?}", warnings),
+        };
+        assert_eq!(span.anchor(src).as_str(), "else { print dead; }");
+
+        // the `else` of a chained `if` is checked on its own
+        let src = "let x = 1; if x == 1 {} else { if not false { print 1; } else { print 2; } }";
+        assert_matches!(warnings(src)[..], [Warning::UnreachableBranch { .. }]);
+        assert_matches!(warnings("if 1 > 2 {} else { print 1; }")[..], []);
+        assert_matches!(warnings("if true { print 1; }")[..], []);
+    }
+
+    #[test]
+    fn test_infinite_loop() {
+        assert_matches!(warnings("while true {}")[..], [Warning::InfiniteLoop { .. }]);
+        assert_matches!(warnings("while (1) { print 1; }")[..], [Warning::InfiniteLoop { .. }]);
+        assert_matches!(warnings("while true { break; }")[..], []);
+        assert_matches!(warnings("while true { if x { break; } }")[..], []);
+        assert_matches!(warnings("while false {}")[..], []);
+        assert_matches!(warnings("while x {}")[..], []);
+
+        // breaks of nested loops stay inside the outer one
+        assert_matches!(warnings("while true { while x { break; } }")[..], [Warning::InfiniteLoop { .. }]);
+        let src = "outer: while true { inner: while x { break inner; } }";
+        assert_matches!(warnings(src)[..], [Warning::InfiniteLoop { .. }]);
+        let src = "outer: while true { for i in 0..3 { break outer; } }";
+        assert_matches!(warnings(src)[..], []);
+    }
+
+    #[test]
+    fn test_assignment_in_condition() {
+        assert_matches!(warnings("if x = 1 {}")[..], [Warning::AssignmentInCondition { .. }]);
+        assert_matches!(warnings("while x = false {}")[..], [Warning::AssignmentInCondition { .. }]);
+        assert_matches!(warnings("if x == 1 {}")[..], []);
+        // only the whole condition counts
+        assert_matches!(warnings("if (x = 1) == 1 {}")[..], []);
+        assert_matches!(warnings("if (x = true) {}")[..], []);
+    }
+
+    #[test]
+    fn test_negate_type_mismatch() {
+        assert_matches!(warnings(r#"print -"x";"#)[..], [Warning::TypeMismatch { .. }]);
+        assert_matches!(warnings("print -(true);")[..], [Warning::TypeMismatch { .. }]);
+        assert_matches!(warnings("let y = 1; print -y;")[..], []);
+        assert_matches!(warnings("print -1; print not 1;")[..], []);
+    }
+
+    #[test]
+    fn test_unused_expression_result() {
+        assert_matches!(warnings("1;")[..], [Warning::UnusedExpressionResult { .. }]);
+        assert_matches!(warnings("let x = 1; x + 1;")[..], [Warning::UnusedExpressionResult { .. }]);
+        assert_matches!(warnings("let mut x; x = 1;")[..], []);
+        assert_matches!(warnings("let mut x; (x = 1) == ();")[..], []);
+    }
+
+    fn compile_error(src: &str) -> Error {
+        let alloc = Alloc::new();
+        let ast = parse(src).unwrap();
+        compile(src, ast, &alloc).err().expect("compile error")
+    }
+
+    /// Returns the source spans of the `Pop`s right after the loop exit `JumpIfFalse` and at its target
+    fn loop_pop_spans(src: &str) -> (&str, &str) {
+        let alloc = Alloc::new();
+        let ast = parse(src).unwrap();
+        let chunk = compile(src, ast, &alloc).unwrap();
+        let steps: Vec<_> = chunk.steps().collect();
+        let (index, exit) = steps.iter()
+            .enumerate()
+            .find_map(|(index, (offset, opcode, _))| match opcode {
+                OpCode::JumpIfFalse { offset: jump } => Some((index, offset + 3 + *jump as usize)),
+                _ => None,
+            })
+            .expect("loop exit jump");
+        let (_, then_pop, then_span) = steps[index + 1];
+        let (_, exit_pop, exit_span) = *steps.iter().find(|(offset, _, _)| *offset == exit).unwrap();
+        assert_eq!((then_pop, exit_pop), (OpCode::Pop, OpCode::Pop));
+        (then_span.anchor(src).as_str(), exit_span.anchor(src).as_str())
+    }
+
+    #[test]
+    fn test_loop_condition_spans() {
+        let src = "let mut a = 3; while a > 0 { a = a - 1; }";
+        assert_eq!(loop_pop_spans(src), ("a > 0", "a > 0"));
+        let src = "for i in 0..3 { print i; }";
+        assert_eq!(loop_pop_spans(src), ("for i in 0..3", "for i in 0..3"));
+        let src = "let r = 0..3; for i in r { print i; }";
+        assert_eq!(loop_pop_spans(src), ("for i in r", "for i in r"));
+    }
+
+    #[test]
+    fn test_synthetic_spans() {
+        let alloc = Alloc::new();
+        let src = "for i in 0..3 { print i; }";
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let steps: Vec<_> = chunk.steps().collect();
+        // the step is the `Add` after the body's `Print`
+        let print = steps.iter().position(|(_, opcode, _)| *opcode == OpCode::Print).unwrap();
+        let add = print + steps[print..].iter().position(|(_, opcode, _)| *opcode == OpCode::Add).unwrap();
+        assert!(chunk.is_synthetic(add));
+        assert_eq!(steps[add].2.anchor(src).as_str(), "0..3");
+        assert!(!chunk.is_synthetic(print));
+        assert!(chunk.disassemble(src).contains("Add (desugared)"));
+    }
+
+    #[test]
+    fn test_assert_because() {
+        assert_matches!(opcodes("assert true;")[..], [OpCode::True, OpCode::Assert]);
+        assert_matches!(
+            opcodes(r#"assert true because "reason";"#)[..],
+            [OpCode::True, OpCode::AssertBecause { .. }],
+        );
+    }
+
+    #[test]
+    fn test_unit_coalesce() {
+        assert_matches!(opcodes("let a = (); print a ?? 1;")[..], [
+            .., OpCode::GetGlobal { .. }, OpCode::Dup, OpCode::IsUnit, OpCode::JumpIfFalse { .. },
+            OpCode::Pop, OpCode::Pop, OpCode::Constant { .. }, OpCode::Jump { .. }, OpCode::Pop,
+            OpCode::Print,
+        ]);
+    }
+
+    #[test]
+    fn test_assert_conjuncts() {
+        assert_matches!(
+            opcodes("assert true and false and true;")[..],
+            [
+                OpCode::True, OpCode::JumpIfFalse { .. }, OpCode::Pop,
+                OpCode::False, OpCode::JumpIfFalse { .. }, OpCode::Pop,
+                OpCode::True, OpCode::Assert,
+                OpCode::Jump { .. }, OpCode::Assert,
+                OpCode::Jump { .. }, OpCode::Assert,
+            ],
+        );
+        // anything else is asserted whole
+        let opcodes = opcodes("assert true or false and true;");
+        assert_eq!(opcodes.iter().filter(|opcode| **opcode == OpCode::Assert).count(), 1);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let src = r#"let a = "x"; let b = 1.5; print a + "y"; print b + 1.5 + 2;"#;
+        let alloc = Alloc::new();
+        let first = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let second = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        assert_eq!(first.code(), second.code());
+        assert!(first.constants().eq(second.constants()));
+    }
+
+    #[test]
+    fn test_locals_debug_info() {
+        let src = "{ let a = 1; let b = 2; print a + b; }";
+        let alloc = Alloc::new();
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let info: Vec<_> = chunk.locals_debug_info().iter()
+            .map(|info| (info.slot, info.name.anchor(src).as_str(), info.live.clone()))
+            .collect();
+        // Constant, Constant, GetLocal, GetLocal, Add, Print, Pop, Pop
+        assert_eq!(info, [(1, "b", 2..6), (0, "a", 1..7)]);
+    }
+
+    #[test]
+    fn test_short_circuit_chain() {
+        let src = "let a; let b; let c; print a and b and c; print a and b or c;";
+        let alloc = Alloc::new();
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+
+        let mut targets = Vec::new();
+        let mut code = chunk.code();
+        while let Some((opcode, rest)) = OpCode::decode(code) {
+            let after = chunk.code().len() - rest.len();
+            match opcode {
+                OpCode::JumpIfFalse { offset } => targets.push(("and", after + offset as usize)),
+                OpCode::JumpIfTrue { offset } => targets.push(("or", after + offset as usize)),
+                _ => {}
+            }
+            code = rest;
+        }
+
+        // the `and` chain shares one exit, mixed operators don't
+        assert_eq!(targets.len(), 4);
+        assert_eq!(targets[0], targets[1]);
+        assert_ne!(targets[2].1, targets[3].1);
+    }
+
+    #[test]
+    fn test_fold_string_repeat() {
+        let src = r#"print "ab" * 3;"#;
+        let alloc = Alloc::new();
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        assert_matches!(chunk.opcodes().collect::<Vec<_>>()[..], [OpCode::Constant { .. }, OpCode::Print, OpCode::Flush]);
+        let folded = chunk.constants().find_map(|value| value.downcast::<ObjString>()).unwrap();
+        assert_eq!(folded.as_str(), "ababab");
+
+        // invalid counts and too long results are not folded
+        assert_matches!(opcodes(r#"print "ab" * -1;"#)[..], [.., OpCode::Multiply, OpCode::Print]);
+        assert_matches!(
+            opcodes(r#"print "ab" * (1000000000 * 1000000000);"#)[..],
+            [.., OpCode::Multiply, OpCode::Print],
+        );
+    }
+
+    #[test]
+    fn test_max_instructions() {
+        let src = "print 1; print 2;";
+        let options = Options { max_instructions: 3, ..Options::default() };
+        let alloc = Alloc::new();
+        let result = compile_with(src, parse(src).unwrap(), &alloc, &options);
+        assert_matches!(result.err(), Some(Error::ProgramTooLarge { max: 3, .. }));
+
+        let options = Options { max_instructions: 4, ..Options::default() };
+        assert!(compile_with(src, parse(src).unwrap(), &alloc, &options).is_ok());
+    }
+
+    #[test]
+    fn test_max_stack_depth() {
+        let src = "print a + (a + (a + (a + a)));";
+        let alloc = Alloc::new();
+        let compile_capped = |max| {
+            let options = Options { max_stack_depth: Some(max), ..Options::default() };
+            compile_with(src, parse(src).unwrap(), &alloc, &options)
+        };
+        // the fourth `a` is the first value over the cap
+        let span = match compile_capped(3).err() {
+            Some(Error::StackOverflow { span, max: 3 }) => span,
+            error => panic!("expected StackOverflow, got {:?}", error),
+        };
+        assert_eq!(span.range(), 21..22);
+        assert!(compile_capped(5).is_ok());
+        assert!(compile(src, parse(src).unwrap(), &alloc).is_ok());
+
+        // function chunks are checked too
+        let src = "fn f() { print a + (a + (a + (a + a))); }";
+        let options = Options { max_stack_depth: Some(3), ..Options::default() };
+        assert_matches!(
+            compile_with(src, parse(src).unwrap(), &alloc, &options).err(),
+            Some(Error::StackOverflow { max: 3, .. }),
+        );
+    }
+
+    #[test]
+    fn test_deny_uninitialized() {
+        let options = Options { deny_uninitialized: true, ..Options::default() };
+        let alloc = Alloc::new();
+        let compile_strict = |src| compile_with(src, parse(src).unwrap(), &alloc, &options);
+
+        let result = compile_strict("{ let x; print x; }");
+        assert_matches!(result.err(), Some(Error::UseOfUninitialized { .. }));
+        let result = compile_strict("{ let mut x; x = x; }");
+        assert_matches!(result.err(), Some(Error::UseOfUninitialized { .. }));
+        assert!(compile_strict("{ let mut x; x = 1; print x; }").is_ok());
+        assert!(compile_strict("let x; print x;").is_ok());
+        assert!(compile("{ let x; print x; }", parse("{ let x; print x; }").unwrap(), &alloc).is_ok());
+    }
+
+    #[test]
+    fn test_debug_asserts() {
+        let options = Options { debug_asserts: true, ..Options::default() };
+        let alloc = Alloc::new();
+        let src = r#"assert true; assert true because "reason";"#;
+        let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+        assert_matches!(chunk.opcodes().collect::<Vec<_>>()[..], [
+            OpCode::True, OpCode::DebugAssert, OpCode::True, OpCode::AssertBecause { .. }, OpCode::Flush,
+        ]);
+        assert_matches!(opcodes("assert true;")[..], [OpCode::True, OpCode::Assert]);
+    }
+
+    #[test]
+    fn test_malformed_string_literal() {
+        use crate::lexer::{Token, TokenKind};
+        use crate::parser::ast::{Expression, Item, PrimaryExpr, PrintStmt, Statement};
+        use crate::span::FreeSpan;
+
+        // hand-built as the lexer never produces these tokens
+        let print = |start, end| {
+            let token = |kind, start, end| Token { kind, span: FreeSpan { start, end } };
+            vec![Item::Statement(Statement::Print(PrintStmt {
+                print_tok: token(TokenKind::Print, 0, 0),
+                expr: Expression::Primary(PrimaryExpr { token: token(TokenKind::String, start, end) }),
+                semicolon_tok: token(TokenKind::Semicolon, 0, 0),
+            }))]
+        };
+        let alloc = Alloc::new();
+        let src = r#""añb""#;
+        assert!(compile(src, print(0, 6), &alloc).is_ok());
+        // missing the closing quote
+        assert_matches!(compile(src, print(0, 5), &alloc).err(), Some(Error::MalformedStringLiteral { .. }));
+        // ends inside `ñ`
+        assert_matches!(compile(src, print(0, 3), &alloc).err(), Some(Error::MalformedStringLiteral { .. }));
+    }
+
+    #[test]
+    fn test_logical_precedence() {
+        let options = Options { warn_logical_precedence: true, ..Options::default() };
+        let alloc = Alloc::new();
+        let warnings = |src| compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap().1;
+
+        let src = "let a; let b; let c; print a or b and c;";
+        assert_matches!(warnings(src)[..], [Warning::AmbiguousLogicalPrecedence { .. }]);
+        let src = "let a; let b; let c; print a and b or c or a;";
+        assert_matches!(warnings(src)[..], [Warning::AmbiguousLogicalPrecedence { .. }]);
+        let src = "let a; let b; let c; print a or (b and c); print (a and b) or c;";
+        assert_matches!(warnings(src)[..], []);
+        let src = "let a; let b; let c; print a or b or c; print a and b and c;";
+        assert_matches!(warnings(src)[..], []);
+        // opt-in
+        assert_matches!(self::warnings("let a; let b; let c; print a or b and c;")[..], []);
+    }
+
+    #[test]
+    fn test_resolve_globals() {
+        let options = Options { resolve_globals: true, ..Options::default() };
+        let alloc = Alloc::new();
+        let compile_resolved = |src| compile_with(src, parse(src).unwrap(), &alloc, &options);
+
+        let (chunk, _) = compile_resolved("let a = 1; let mut b = a; b = 2;").unwrap();
+        assert_eq!(chunk.global_names().len(), 2);
+        assert!(!chunk.opcodes().any(|op| matches!(op, OpCode::GetGlobal { .. } | OpCode::SetGlobal { .. })));
+        assert_matches!(chunk.opcodes().collect::<Vec<_>>()[..], [
+            _, OpCode::DefGlobalSlot { slot: 0 },
+            OpCode::GetGlobalSlot { slot: 0 }, OpCode::DefGlobalSlot { slot: 1 },
+            _, OpCode::SetGlobalSlot { slot: 1 }, OpCode::Pop, OpCode::Flush,
+        ]);
+
+        // used before its definition
+        assert!(compile_resolved("{ print later; } let later = 1;").is_ok());
+        // a typo, and globals aren't defined by block locals
+        assert_matches!(compile_resolved("let count = 1; print cuont;").err(), Some(Error::UndefinedGlobal { .. }));
+        assert_matches!(compile_resolved("{ let a = 1; } a = 2;").err(), Some(Error::UndefinedGlobal { .. }));
+        assert!(compile("print never;", parse("print never;").unwrap(), &alloc).is_ok());
+    }
+
+    #[test]
+    fn test_compile_source() {
+        use super::{compile_source, CompileError};
+        use crate::parser;
+
+        let alloc = Alloc::new();
+        assert!(compile_source("print 1;", &alloc).is_ok());
+
+        let error = compile_source("print 1", &alloc).err().unwrap();
+        assert_matches!(error, CompileError::Parse(parser::Error::MissingSemicolon { .. }));
+        assert_eq!(error.render("print 1"), concat!(
+            "error: missing `;` after statement\n",
+            "   1 | print 1\n",
+            "     |        ^\n",
+        ));
+
+        let src = "print 1;\n{ let a = 1; a = 2; }";
+        let error = compile_source(src, &alloc).err().unwrap();
+        assert_matches!(error, CompileError::Compile(Error::AssignImmutableBinding { .. }));
+        assert_eq!(error.render(src), concat!(
+            "error: cannot assign to an immutable binding\n",
+            "   2 | { let a = 1; a = 2; }\n",
+            "     |              ^^^^^\n",
+        ));
+    }
+
+    #[test]
+    fn test_compile_source_mapped() {
+        use super::{compile_source_mapped, SourceMap};
+
+        let alloc = Alloc::new();
+        let src = "print 1;\n@line 40 \"gen.lox\"\n{ let a = 1; a = 2; }";
+        let mut map = SourceMap::default();
+        let error = compile_source_mapped(src, &alloc, &mut map).err().unwrap();
+        assert_eq!(error.render_mapped(src, &map), concat!(
+            "error: cannot assign to an immutable binding\n",
+            "  --> gen.lox\n",
+            "  40 | { let a = 1; a = 2; }\n",
+            "     |              ^^^^^\n",
+        ));
+        // the physical line without the map
+        assert!(error.render(src).contains("   3 | "));
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        use super::{compile_source, CompileError};
+
+        // targets are checked once the whole expression is parsed
+        let alloc = Alloc::new();
+        let src = "print 0; 1 = 2;";
+        let error = compile_source(src, &alloc).err().unwrap();
+        assert_matches!(error, CompileError::Compile(Error::InvalidAssignmentTarget { .. }));
+        assert_eq!(error.span().unwrap().anchor(src).as_str(), "1");
+        let src = "a + b = 2;";
+        let span = compile_source(src, &alloc).err().and_then(|error| error.span()).unwrap();
+        assert_eq!(span.anchor(src).as_str(), "a + b");
+    }
+
+    #[test]
+    fn test_global_spans() {
+        let alloc = Alloc::new();
+        let src = "print foo; foo = 1 + 2; print -foo;";
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let spans: Vec<_> = chunk.steps()
+            .filter(|(_, opcode, _)| matches!(opcode, OpCode::GetGlobal { .. } | OpCode::SetGlobal { .. }))
+            .map(|(_, _, span)| (span.start, span.anchor(src).as_str()))
+            .collect();
+        assert_eq!(spans, [(6, "foo"), (11, "foo"), (31, "foo")]);
+    }
+
+    #[test]
+    fn test_shared_constants() {
+        let alloc = Alloc::new();
+        let constants = |src| compile(src, parse(src).unwrap(), &alloc).unwrap().constants().count();
+        assert_eq!(constants(r#"print "x"; print "x";"#), 1);
+        // the name of `a` and `1`
+        assert_eq!(constants("let a = 1; a = a + 1; print a; print a + 1;"), 2);
+    }
+
+    #[test]
+    fn test_char() {
+        let alloc = Alloc::new();
+        let src = r"print '\n';";
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        assert_eq!(chunk.constants().count(), 1);
+        let string = chunk.constants().next().unwrap().downcast::<ObjString>().unwrap();
+        assert_eq!(string.as_str(), "\n");
+    }
+
+    #[test]
+    fn test_format() {
+        assert_matches!(opcodes(r#"print format("{} + {} = {}", 1, 2, 3);"#)[..], [
+            OpCode::Constant { .. },
+            OpCode::Constant { .. }, OpCode::Stringify, OpCode::Add,
+            OpCode::Constant { .. }, OpCode::Add,
+            OpCode::Constant { .. }, OpCode::Stringify, OpCode::Add,
+            OpCode::Constant { .. }, OpCode::Add,
+            OpCode::Constant { .. }, OpCode::Stringify, OpCode::Add,
+            OpCode::Print,
+        ]);
+
+        let alloc = Alloc::new();
+        let src = r#"print format("{} and {}", 1);"#;
+        assert_matches!(
+            compile(src, parse(src).unwrap(), &alloc).err(),
+            Some(Error::FormatArgMismatch { placeholders: 2, arguments: 1, .. })
+        );
+        let src = r#"print format("none", 1);"#;
+        assert_matches!(
+            compile(src, parse(src).unwrap(), &alloc).err(),
+            Some(Error::FormatArgMismatch { placeholders: 0, arguments: 1, .. })
+        );
+    }
+
+    #[test]
+    fn test_dbg() {
+        // the value stays on the stack for the enclosing expression
+        assert_matches!(opcodes("let x = dbg(1 + 2);")[..], [
+            .., OpCode::Add, OpCode::Dbg, OpCode::DefGlobal { .. },
+        ]);
+        let src = "print dbg(true) and dbg(x);";
+        let alloc = Alloc::new();
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let spans: Vec<_> = chunk.steps()
+            .filter(|(_, opcode, _)| *opcode == OpCode::Dbg)
+            .map(|(_, _, span)| span.anchor(src).as_str())
+            .collect();
+        assert_eq!(spans, ["true", "x"]);
+    }
+
+    #[test]
+    fn test_eval_const() {
+        use super::eval_const;
+
+        let number = |src| eval_const(src).and_then(|value| value.to_float());
+        assert_eq!(number("2 * (3 + 4)"), Some(14.0));
+        assert_eq!(number("-(1 - 3) / 4"), Some(0.5));
+        assert_eq!(number("() or 2"), Some(2.0));
+        assert_eq!(number("() ?? 2"), Some(2.0));
+        assert_eq!(eval_const("false ?? 2").and_then(|value| value.to_bool()), Some(false));
+        assert_eq!(eval_const("1 < 2 and not false").and_then(|value| value.to_bool()), Some(true));
+        assert_eq!(eval_const("1 === 1.0").and_then(|value| value.to_bool()), Some(true));
+        assert_matches!(eval_const("()"), Some(value) if value.is_unit());
+
+        assert!(eval_const("x + 1").is_none());
+        assert!(eval_const(r#""a" + "b""#).is_none());
+        assert!(eval_const("-true").is_none());
+        assert!(eval_const("1 +").is_none());
+        assert!(eval_const("1; 2").is_none());
+    }
+
+    #[test]
+    fn test_fold_comparison() {
+        // nested constant operands are folded too
+        assert_eq!(opcodes("(1 + 2) == 3;"), [OpCode::True, OpCode::Pop]);
+        assert_eq!(opcodes("not (1 < 2) /= true;"), [OpCode::True, OpCode::Pop]);
+    }
+
+    #[test]
+    fn test_for_iterable() {
+        // literal ranges skip building the Range object
+        assert!(!opcodes("for x in 0..3 { }").contains(&OpCode::BuildRange));
+        assert!(opcodes("let r = 0..3; for x in r { }").contains(&OpCode::GetIndex));
+
+        assert_matches!(compile_error("for x in 0..3 { x = 1; }"), Error::AssignImmutableBinding { .. });
+        assert_matches!(compile_error("for x in (0..3) { x = 1; }"), Error::AssignImmutableBinding { .. });
+    }
+
+    #[test]
+    fn test_loop_labels() {
+        assert_matches!(compile_error("break;"), Error::BreakOutsideLoop { .. });
+        assert_matches!(compile_error("if true { continue; }"), Error::ContinueOutsideLoop { .. });
+        assert_matches!(compile_error("while true { break nope; }"), Error::UndefinedLabel { .. });
+        assert_matches!(compile_error("a: while true { } while true { continue a; }"), Error::UndefinedLabel { .. });
+    }
+
+    #[test]
+    fn test_return_outside_function() {
+        assert_matches!(compile_error("return 1;"), Error::ReturnOutsideFunction { .. });
+        let src = "while true { { return; } }";
+        assert_matches!(
+            compile_error(src),
+            Error::ReturnOutsideFunction { span } if span.anchor(src).as_str() == "return;"
+        );
+    }
+
+    #[test]
+    fn test_fn_item() {
+        let src = "fn add(a, b) { return a + b; } { fn unit() { } }";
+        let alloc = Alloc::new();
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        assert_matches!(
+            chunk.opcodes().collect::<Vec<_>>()[..],
+            [OpCode::Constant { .. }, OpCode::DefGlobal { .. }, OpCode::Constant { .. }, OpCode::Pop, OpCode::Flush],
+        );
+
+        let functions: Vec<_> = chunk.constants().filter_map(|value| value.downcast::<ObjFunction>()).collect();
+        assert_eq!(functions.len(), 2);
+        assert_eq!(format!("{:?}", functions[0]), "<fn add>");
+        assert_eq!(functions[0].arity(), 2);
+        // slot 0 is the function itself
+        assert_eq!(functions[0].chunk().opcodes().collect::<Vec<_>>(), [
+            OpCode::GetLocal { slot: 1 },
+            OpCode::GetLocal { slot: 2 },
+            OpCode::Add,
+            OpCode::Return,
+            OpCode::Unit,
+            OpCode::Return,
+        ]);
+        assert_eq!(functions[1].chunk().opcodes().collect::<Vec<_>>(), [OpCode::Unit, OpCode::Return]);
+
+        let params: Vec<_> = (0..=MAX_PARAMETERS).map(|i| format!("p{}", i)).collect();
+        let src = format!("fn f({}) {{ }}", params.join(", "));
+        assert_matches!(
+            compile_error(&src),
+            Error::TooManyParameters { span } if span.anchor(&src).as_str() == "p255"
+        );
+        assert_matches!(compile_error("fn f(a, a) { }"), Error::Shadowing { .. });
+    }
+
+    #[test]
+    fn test_call_expr() {
+        assert_matches!(
+            opcodes("print a + f(b);")[..],
+            [
+                OpCode::GetGlobal { .. },
+                OpCode::GetGlobal { .. },
+                OpCode::GetGlobal { .. },
+                OpCode::Call { arity: 1 },
+                OpCode::Add,
+                OpCode::Print,
+            ],
+        );
+        assert_matches!(
+            opcodes("-f()(1, 2);")[..],
+            [
+                OpCode::GetGlobal { .. },
+                OpCode::Call { arity: 0 },
+                OpCode::Constant { .. },
+                OpCode::Constant { .. },
+                OpCode::Call { arity: 2 },
+                OpCode::Negate,
+                OpCode::Pop,
+            ],
+        );
+
+        let arguments = vec!["1"; MAX_PARAMETERS + 1];
+        let src = format!("f({});", arguments.join(", "));
+        assert_matches!(
+            compile_error(&src),
+            Error::TooManyArguments { span } if span.range().start == src.len() - 3
+        );
+    }
+
+    #[test]
+    fn test_break_pops_locals() {
+        let alloc = Alloc::new();
+        let src = "{
+            let c = true;
+            let d = false;
+            while c {
+                let a = 1;
+                let b = 2;
+                if d { break; }
+                continue;
+            }
+        }";
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let by_span = |text: &str| -> Vec<_> {
+            chunk.steps()
+                .filter(|(_, _, span)| span.anchor(src).as_str() == text)
+                .collect()
+        };
+        let depths = chunk.simulate_stack_depth();
+        assert!(depths.underflows.is_empty());
+
+        // `a` and `b` are popped before jumping out, back at the two locals the loop started with
+        let breaks = by_span("break;");
+        assert_matches!(breaks[..], [(_, OpCode::Pop, _), (_, OpCode::Pop, _), (_, OpCode::Jump { .. }, _)]);
+        assert_eq!(depths.depths[&breaks[2].0], 2);
+        let continues = by_span("continue;");
+        assert_matches!(continues[..], [(_, OpCode::Pop, _), (_, OpCode::Pop, _), (_, OpCode::Loop { .. }, _)]);
+        assert_eq!(depths.depths[&continues[2].0], 2);
+
+        // nothing to pop when the loop body has no locals of its own
+        let src = "{ let a = 1; while true { break; } }";
+        let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+        let breaks: Vec<_> = chunk.steps()
+            .filter(|(_, _, span)| span.anchor(src).as_str() == "break;")
+            .map(|(_, opcode, _)| opcode)
+            .collect();
+        assert_matches!(breaks[..], [OpCode::Jump { .. }]);
+    }
+
+    #[test]
+    fn test_assign_to_group() {
+        let src = "let mut a; (a) = 1;";
+        let span = match compile_error(src) {
+            Error::AssignToGroup { span } => span,
+            error => panic!("expected AssignToGroup, got {:?}", error),
+        };
+        assert_eq!(span.anchor(src).as_str(), "(a)");
+        assert_matches!(compile_error("1 = 1;"), Error::InvalidAssignmentTarget { .. });
+    }
+}
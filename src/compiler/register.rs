@@ -0,0 +1,298 @@
+//! Experimental register based backend
+//!
+//! Instructions name their operand and destination registers instead of passing values on the
+//! stack, `1 + 2` is two loads and a single `Add` with no pushes or pops. Only `print` and
+//! expression statements over literals compile so far and there is no VM running the result yet,
+//! the stack backend in [`compiler`](super) stays the default.
+
+use crate::chunk::PoolKey;
+use crate::lexer::{string_contents, TokenKind};
+use crate::object::string::String as ObjString;
+use crate::object::Alloc;
+use crate::parser::ast::*;
+use crate::span::{FreeSpan, Spanned};
+use crate::value::Value;
+use indexmap::IndexSet;
+use std::num::ParseFloatError;
+
+pub type Register = u8;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Construct the register backend doesn't compile yet
+    Unsupported {
+        span: FreeSpan,
+    },
+    /// Expression needs more than 256 registers at once
+    TooManyRegisters {
+        span: FreeSpan,
+    },
+    InvalidNumberLiteral {
+        cause: ParseFloatError,
+        span: FreeSpan,
+    },
+    /// String token without both quotes or not on char boundaries of the source
+    MalformedStringLiteral {
+        span: FreeSpan,
+    },
+}
+
+type Result<T = Register> = std::result::Result<T, Error>;
+
+/// Register instructions, operands are read before `dst` is written so it may alias them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterOp {
+    /// Loads constant `index` of the [`RegisterChunk`]
+    LoadConst { dst: Register, index: u16 },
+    LoadUnit { dst: Register },
+    LoadBool { dst: Register, value: bool },
+    /// Same semantics as [`OpCode::Equal`](crate::opcode::OpCode::Equal)
+    Equal { dst: Register, lhs: Register, rhs: Register },
+    /// Same semantics as [`OpCode::StrictEqual`](crate::opcode::OpCode::StrictEqual)
+    StrictEqual { dst: Register, lhs: Register, rhs: Register },
+    Greater { dst: Register, lhs: Register, rhs: Register },
+    Less { dst: Register, lhs: Register, rhs: Register },
+    Add { dst: Register, lhs: Register, rhs: Register },
+    Subtract { dst: Register, lhs: Register, rhs: Register },
+    Multiply { dst: Register, lhs: Register, rhs: Register },
+    Divide { dst: Register, lhs: Register, rhs: Register },
+    Not { dst: Register, src: Register },
+    Negate { dst: Register, src: Register },
+    Print { src: Register },
+}
+
+/// Register bytecode, instructions aren't packed into bytes unlike in [`Chunk`](crate::chunk::Chunk)
+#[derive(Default)]
+pub struct RegisterChunk<'alloc> {
+    code: Vec<RegisterOp>,
+    /// Origin span of each instruction
+    spans: Vec<FreeSpan>,
+    /// Numbers are deduplicated only if they are bitwise identical, like in a `Chunk`
+    constants: IndexSet<PoolKey<'alloc>>,
+}
+
+impl<'alloc> RegisterChunk<'alloc> {
+    pub fn code(&self) -> &[RegisterOp] {
+        &self.code
+    }
+
+    pub fn spans(&self) -> &[FreeSpan] {
+        &self.spans
+    }
+
+    pub fn get_constant(&self, index: u16) -> Option<Value<'alloc>> {
+        self.constants.get_index(index as usize).map(|&PoolKey(value)| value)
+    }
+
+    fn emit(&mut self, op: RegisterOp, span: FreeSpan) {
+        self.code.push(op);
+        self.spans.push(span);
+    }
+
+    fn insert_constant(&mut self, value: Value<'alloc>) -> u16 {
+        let (index, _) = self.constants.insert_full(PoolKey(value));
+        index.try_into().expect("constant pool size limit reached")
+    }
+}
+
+/// Compiles `ast` into register bytecode, see the [module docs](self) for what's supported
+pub fn compile_register<'alloc>(
+    source: &str,
+    ast: Program,
+    alloc: &'alloc Alloc,
+) -> Result<RegisterChunk<'alloc>> {
+    let mut emitter = Emitter {
+        source,
+        alloc,
+        chunk: RegisterChunk::default(),
+        next: 0,
+    };
+    for item in &ast {
+        match item {
+            Item::Statement(Statement::Expr(expr_stmt)) => {
+                // the result stays in its register, nothing to pop
+                emitter.expression(&expr_stmt.expr)?;
+            }
+            Item::Statement(Statement::Print(print_stmt)) => {
+                let src = emitter.expression(&print_stmt.expr)?;
+                emitter.chunk.emit(RegisterOp::Print { src }, print_stmt.span());
+            }
+            _ => return Err(Error::Unsupported { span: item.span() }),
+        }
+        // statements don't keep any values around
+        emitter.next = 0;
+    }
+    Ok(emitter.chunk)
+}
+
+struct Emitter<'src, 'alloc> {
+    source: &'src str,
+    alloc: &'alloc Alloc,
+    chunk: RegisterChunk<'alloc>,
+    /// Lowest free register, registers are allocated like a stack
+    next: u16,
+}
+
+impl<'src, 'alloc> Emitter<'src, 'alloc> {
+    fn allocate(&mut self, span: FreeSpan) -> Result {
+        let register = self.next.try_into()
+            .map_err(|_| Error::TooManyRegisters { span })?;
+        self.next += 1;
+        Ok(register)
+    }
+
+    /// Compiles `expr` into the lowest free register and returns it
+    fn expression(&mut self, expr: &Expression) -> Result {
+        match expr {
+            Expression::Binary(binary_expr) => self.binary_expr(binary_expr),
+            Expression::Unary(unary_expr) => self.unary_expr(unary_expr),
+            Expression::Group(GroupExpr { expr: Some(expr), .. }) => self.expression(expr),
+            Expression::Group(group_expr) => {
+                let dst = self.allocate(group_expr.span())?;
+                self.chunk.emit(RegisterOp::LoadUnit { dst }, group_expr.span());
+                Ok(dst)
+            }
+            Expression::Primary(primary_expr) => self.primary_expr(primary_expr),
+            Expression::Field(_) | Expression::Call(_) | Expression::Dbg(_) | Expression::Format(_) => Err(Error::Unsupported { span: expr.span() }),
+        }
+    }
+
+    fn binary_expr(&mut self, binary_expr: &BinaryExpr) -> Result {
+        let span = binary_expr.span();
+        let op = binary_expr.operator.kind;
+        let negated = matches!(
+            op,
+            TokenKind::NotEqual | TokenKind::StrictNotEqual | TokenKind::GreaterEqual | TokenKind::LessEqual,
+        );
+
+        let lhs = self.expression(&binary_expr.lhs)?;
+        let rhs = self.expression(&binary_expr.rhs)?;
+        // the result replaces the left operand, the right one is free again
+        let dst = lhs;
+        self.next = u16::from(dst) + 1;
+
+        let op = match op {
+            TokenKind::EqualEqual | TokenKind::NotEqual => RegisterOp::Equal { dst, lhs, rhs },
+            TokenKind::StrictEqual | TokenKind::StrictNotEqual => RegisterOp::StrictEqual { dst, lhs, rhs },
+            TokenKind::Greater | TokenKind::LessEqual => RegisterOp::Greater { dst, lhs, rhs },
+            TokenKind::Less | TokenKind::GreaterEqual => RegisterOp::Less { dst, lhs, rhs },
+            TokenKind::Plus => RegisterOp::Add { dst, lhs, rhs },
+            TokenKind::Minus => RegisterOp::Subtract { dst, lhs, rhs },
+            TokenKind::Mul => RegisterOp::Multiply { dst, lhs, rhs },
+            TokenKind::Div => RegisterOp::Divide { dst, lhs, rhs },
+            // assignment, ranges and short-circuiting
+            _ => return Err(Error::Unsupported { span }),
+        };
+        self.chunk.emit(op, span);
+        if negated {
+            self.chunk.emit(RegisterOp::Not { dst, src: dst }, span);
+        }
+        Ok(dst)
+    }
+
+    fn unary_expr(&mut self, unary_expr: &UnaryExpr) -> Result {
+        let span = unary_expr.span();
+        let src = self.expression(&unary_expr.expr)?;
+        let op = match unary_expr.operator.kind {
+            TokenKind::Not => RegisterOp::Not { dst: src, src },
+            TokenKind::Minus => RegisterOp::Negate { dst: src, src },
+            _ => unreachable!(),
+        };
+        self.chunk.emit(op, span);
+        Ok(src)
+    }
+
+    fn primary_expr(&mut self, primary_expr: &PrimaryExpr) -> Result {
+        let span = primary_expr.span();
+        let dst = self.allocate(span)?;
+        let op = match primary_expr.token.kind {
+            TokenKind::True => RegisterOp::LoadBool { dst, value: true },
+            TokenKind::False => RegisterOp::LoadBool { dst, value: false },
+            TokenKind::Number => {
+                let float = span.anchor(self.source).as_str().parse()
+                    .map_err(|cause| Error::InvalidNumberLiteral { cause, span })?;
+                let index = self.chunk.insert_constant(Value::new_float(float));
+                RegisterOp::LoadConst { dst, index }
+            }
+            TokenKind::String => {
+                let slice = self.source.get(span.range())
+                    .and_then(string_contents)
+                    .ok_or(Error::MalformedStringLiteral { span })?;
+                let value = Value::new_object(ObjString::new(slice, self.alloc));
+                let index = self.chunk.insert_constant(value);
+                RegisterOp::LoadConst { dst, index }
+            }
+            // variables live on the stack backend only
+            _ => return Err(Error::Unsupported { span }),
+        };
+        self.chunk.emit(op, span);
+        Ok(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile_register, Error, RegisterOp};
+    use crate::object::string::String as ObjString;
+    use crate::object::Alloc;
+    use crate::parser::parse;
+    use std::assert_matches::assert_matches;
+
+    fn code(src: &str) -> Vec<RegisterOp> {
+        let alloc = Alloc::new();
+        let ast = parse(src).unwrap();
+        compile_register(src, ast, &alloc).unwrap().code().to_vec()
+    }
+
+    #[test]
+    fn test_add() {
+        assert_eq!(code("1 + 2;"), [
+            RegisterOp::LoadConst { dst: 0, index: 0 },
+            RegisterOp::LoadConst { dst: 1, index: 1 },
+            RegisterOp::Add { dst: 0, lhs: 0, rhs: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_register_reuse() {
+        assert_eq!(code("print (1 + 2) * -(3 - 1);"), [
+            RegisterOp::LoadConst { dst: 0, index: 0 },
+            RegisterOp::LoadConst { dst: 1, index: 1 },
+            RegisterOp::Add { dst: 0, lhs: 0, rhs: 1 },
+            RegisterOp::LoadConst { dst: 1, index: 2 },
+            RegisterOp::LoadConst { dst: 2, index: 0 },
+            RegisterOp::Subtract { dst: 1, lhs: 1, rhs: 2 },
+            RegisterOp::Negate { dst: 1, src: 1 },
+            RegisterOp::Multiply { dst: 0, lhs: 0, rhs: 1 },
+            RegisterOp::Print { src: 0 },
+        ]);
+        // registers are free again after each statement
+        assert_matches!(code("1 /= 2; true;")[..], [
+            .., RegisterOp::Not { dst: 0, src: 0 }, RegisterOp::LoadBool { dst: 0, value: true },
+        ]);
+    }
+
+    #[test]
+    fn test_constants() {
+        let alloc = Alloc::new();
+        let src = r#"print "a"; 1; 1.0000000000000002; 1;"#;
+        let chunk = compile_register(src, parse(src).unwrap(), &alloc).unwrap();
+        let (string, close) = (chunk.get_constant(0), chunk.get_constant(2));
+        let string = string.and_then(|value| value.downcast::<ObjString>()).unwrap();
+        assert_eq!(string.as_str(), "a");
+        // close Numbers get constants of their own
+        assert_eq!(close.and_then(|value| value.to_float()), Some(1.0000000000000002));
+        assert_matches!(chunk.code()[..], [
+            .., RegisterOp::LoadConst { index: 1, .. }, RegisterOp::LoadConst { index: 2, .. },
+            RegisterOp::LoadConst { index: 1, .. },
+        ]);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let alloc = Alloc::new();
+        let src = "let a = 1;";
+        let res = compile_register(src, parse(src).unwrap(), &alloc);
+        assert_matches!(res.err(), Some(Error::Unsupported { .. }));
+    }
+}
@@ -0,0 +1,78 @@
+//! Presets configuring the whole front end at once
+//!
+//! A [`Dialect`] bundles the [parser](parser::Options) and [compiler](compiler::Options) options,
+//! the default one is the strict language with every option at its default.
+
+use crate::chunk::Chunk;
+use crate::compiler::{self, CompileError, Warning};
+use crate::object::Alloc;
+use crate::parser::{self, ast::Program};
+
+
+#[derive(Debug, Clone, Default)]
+pub struct Dialect {
+    pub parser: parser::Options,
+    pub compiler: compiler::Options,
+}
+
+impl Dialect {
+    /// Closest to Lox, negation is spelled `!` and `not` isn't an operator
+    pub fn lox() -> Dialect {
+        let parser = parser::Options {
+            symbolic_not: true,
+            word_not: false,
+            ..parser::Options::default()
+        };
+        Dialect { parser, ..Dialect::default() }
+    }
+
+    /// Every syntax extension enabled, the strict language is a subset of it
+    pub fn extended() -> Dialect {
+        let parser = parser::Options {
+            lenient_semicolons: true,
+            symbolic_not: true,
+            word_not: true,
+            ..parser::Options::default()
+        };
+        Dialect { parser, ..Dialect::default() }
+    }
+
+    pub fn parse(&self, source: &str) -> Result<Program, parser::Error> {
+        parser::parse_with(source, &self.parser)
+    }
+
+    /// Same as [`compile_source`](compiler::compile_source) with the options of this dialect
+    pub fn compile<'alloc>(
+        &self,
+        source: &str,
+        alloc: &'alloc Alloc,
+    ) -> Result<(Chunk<'alloc>, Vec<Warning>), CompileError> {
+        let ast = self.parse(source)?;
+        Ok(compiler::compile_with(source, ast, alloc, &self.compiler)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Dialect;
+    use crate::compiler::CompileError;
+    use crate::object::Alloc;
+    use crate::parser::Error;
+    use std::assert_matches::assert_matches;
+
+    #[test]
+    fn test_presets() {
+        let alloc = Alloc::new();
+        let src = "let x = true; print not x;";
+        assert_matches!(
+            Dialect::lox().compile(src, &alloc).err(),
+            Some(CompileError::Parse(Error::ExpectedExpressionStart { .. })),
+        );
+        assert!(Dialect::extended().compile(src, &alloc).is_ok());
+        assert!(Dialect::default().compile(src, &alloc).is_ok());
+
+        assert!(Dialect::lox().parse("print !x;").is_ok());
+        assert!(Dialect::default().parse("print !x;").is_err());
+        assert!(Dialect::extended().parse("{ print !x }").is_ok());
+    }
+}
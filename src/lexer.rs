@@ -1,5 +1,8 @@
+use crate::source_map::SourceMap;
 use crate::span::FreeSpan;
 use logos::{self, skip, Logos};
+use std::collections::BTreeMap;
+use std::ops::Range;
 
 
 #[derive(Logos, Debug, PartialEq, Eq, Clone, Copy)]
@@ -16,33 +19,64 @@ pub enum TokenKind {
     #[token(";")] Semicolon,
     #[token("/")] Div,
     #[token("*")] Mul,
+    /// Only with [`symbolic_not`](crate::parser::Options::symbolic_not)
+    #[token("!")] Bang,
 
     // One or two character tokens
     #[token("/=")] NotEqual,
     #[token("=")] Equal,
     #[token("==")] EqualEqual,
+    /// Equality without the float tolerance of `==`, see [`OpCode::StrictEqual`]. There is a
+    /// single Number type, so `1 === 1.0` is `true`.
+    ///
+    /// [`OpCode::StrictEqual`]: crate::opcode::OpCode::StrictEqual
+    #[token("===")] StrictEqual,
+    /// Negated `===`, spelled like `/=` rather than `!==`
+    #[token("/==")] StrictNotEqual,
+    /// `lhs ?? rhs` is `rhs` if `lhs` is `()`, otherwise `lhs` without evaluating `rhs`
+    #[token("??")] QuestionQuestion,
     #[token(">")] Greater,
     #[token(">=")] GreaterEqual,
     #[token("<")] Less,
     #[token("<=")] LessEqual,
+    #[token("..")] DotDot,
 
     // Literals
-    #[regex(r"[a-zA-Z_][a-zA-Z_0-9]*")]
+    #[cfg_attr(not(feature = "unicode-identifiers"), regex(r"[a-zA-Z_][a-zA-Z_0-9]*"))]
+    #[cfg_attr(feature = "unicode-identifiers", regex(r"[\p{XID_Start}_]\p{XID_Continue}*"))]
     Identifier,
+    /// Loop label, `outer:`
+    #[cfg_attr(not(feature = "unicode-identifiers"), regex(r"[a-zA-Z_][a-zA-Z_0-9]*:"))]
+    #[cfg_attr(feature = "unicode-identifiers", regex(r"[\p{XID_Start}_]\p{XID_Continue}*:"))]
+    Label,
     #[regex(r#""[^"]*""#)]
     String,
-    #[regex(r"[0-9]+(\.[0-9]*)?")]
+    /// Single character in single quotes, `'a'` or an escape like `'\n'`, anything else between
+    /// the quotes is an `Error`
+    #[regex(r"'([^'\\\n]|\\[^\n])*'", |lex| unescape_char(lex.slice()).is_some())]
+    Char,
+    #[regex(r"[0-9]+(\.[0-9]+)?")]
     Number,
+    /// Number directly followed by identifier characters, `123abc`, almost always a typo
+    #[cfg_attr(not(feature = "unicode-identifiers"), regex(r"[0-9]+(\.[0-9]+)?[a-zA-Z_][a-zA-Z_0-9]*"))]
+    #[cfg_attr(feature = "unicode-identifiers", regex(r"[0-9]+(\.[0-9]+)?[\p{XID_Start}_]\p{XID_Continue}*"))]
+    InvalidNumber,
 
     // Keywords
     #[token("and")] And,
     #[token("assert")] Assert,
+    #[token("because")] Because,
+    #[token("break")] Break,
     #[token("class")] Class,
+    #[token("continue")] Continue,
+    #[token("dbg")] Dbg,
     #[token("else")] Else,
     #[token("false")] False,
     #[token("for")] For,
+    #[token("format")] Format,
     #[token("fn")] Fn,
     #[token("if")] If,
+    #[token("in")] In,
     #[token("mut")] Mut,
     #[token("not")] Not,
     #[token("or")] Or,
@@ -55,49 +89,499 @@ pub enum TokenKind {
     #[token("let")] Let,
     #[token("while")] While,
 
+    // Comments never reach the parser, the `Lexer` skips them
+    #[regex(r"//[^\n]*")]
+    Comment,
+    // Recorded into the `SourceMap` and skipped like comments
+    #[regex(r#"@line[ \t]+[0-9]+([ \t]+"[^"\n]*")?"#)]
+    LineDirective,
+
     Eof,
 
     #[error]
     #[regex(r"[ \t\n\r]+", skip)] // whitespace
-    #[regex(r"//[^\n]*", skip)] // comments
     Error,
 }
 
+/// Returns the character of a char literal including its quotes, `None` unless there is exactly
+/// one character or one escape between the quotes
+///
+/// The escapes are `\n`, `\r`, `\t`, `\0`, `\\`, `\'` and `\"`.
+pub fn unescape_char(literal: &str) -> Option<char> {
+    let inner = literal.strip_prefix('\'')?.strip_suffix('\'')?;
+    let mut chars = inner.chars();
+    let c = match chars.next()? {
+        '\\' => match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            c @ ('\\' | '\'' | '"') => c,
+            _ => return None,
+        },
+        '\'' => return None,
+        c => c,
+    };
+    chars.next().is_none().then_some(c)
+}
+
+/// Returns the contents of a string literal without its quotes, `None` if it doesn't have both
+pub fn string_contents(literal: &str) -> Option<&str> {
+    literal.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// Coarse token classification for syntax highlighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HighlightClass {
+    Keyword,
+    /// Symbolic operators, word operators like `and` are keywords
+    Operator,
+    /// Brackets, `,`, `.` and `;`
+    Punctuation,
+    /// String and char literals
+    String,
+    Number,
+    /// Identifiers and loop labels
+    Identifier,
+    /// Comments and line directives
+    Comment,
+    /// Anything the parser would reject as a token
+    Invalid,
+}
+
+impl TokenKind {
+    pub const fn highlight_class(self) -> HighlightClass {
+        use TokenKind::*;
+        match self {
+            LeftParen | RightParen | LeftBrace | RightBrace | Comma | Dot | Semicolon => {
+                HighlightClass::Punctuation
+            }
+            Minus | Plus | Div | Mul | Bang | NotEqual | Equal | EqualEqual | StrictEqual |
+            StrictNotEqual | QuestionQuestion | Greater | GreaterEqual | Less | LessEqual | DotDot => {
+                HighlightClass::Operator
+            }
+            Identifier | Label => HighlightClass::Identifier,
+            String | Char => HighlightClass::String,
+            Number => HighlightClass::Number,
+            And | Assert | Because | Break | Class | Continue | Dbg | Else | False | For | Format |
+            Fn | If | In | Mut | Not | Or | Print | Rec | Return | Super | This | True | Let |
+            While => HighlightClass::Keyword,
+            Comment | LineDirective => HighlightClass::Comment,
+            InvalidNumber | Eof | Error => HighlightClass::Invalid,
+        }
+    }
+}
+
+/// Classifies every token, comment and line directive of `source` in source order, whitespace is
+/// left out
+pub fn highlight(source: &str) -> Vec<(FreeSpan, HighlightClass)> {
+    let mut lexer = Lexer::with_trivia(source);
+    let mut spans = Vec::new();
+    loop {
+        let token = lexer.next();
+        if token.kind == TokenKind::Eof {
+            break;
+        }
+        spans.push((token.span, token.kind.highlight_class()));
+    }
+    let trivia = lexer.take_trivia().expect("lexer collects trivia");
+    let comments = trivia.leading.values().flatten()
+        .chain(trivia.trailing.values())
+        .chain(&trivia.directives);
+    spans.extend(comments.map(|span| (*span, HighlightClass::Comment)));
+    spans.sort_by_key(|(span, _)| span.start);
+    spans
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Token {
     pub kind: TokenKind,
     pub span: FreeSpan,
 }
 
+impl Token {
+    /// Creates a token the parser implies without it being in the source, like an omitted `;`
+    ///
+    /// Its span is empty and placed at the start of `at`, so anchoring it into the source the
+    /// token has been implied in gives an empty string.
+    pub fn synthetic(kind: TokenKind, at: FreeSpan) -> Token {
+        Token {
+            kind,
+            span: FreeSpan { start: at.start, end: at.start },
+        }
+    }
+
+    /// Whether the token has been created by [`Token::synthetic`], only `Eof` is empty otherwise
+    pub fn is_synthetic(&self) -> bool {
+        self.span.start == self.span.end && self.kind != TokenKind::Eof
+    }
+}
+
+/// Comments collected by the lexer
+///
+/// Comments are attached to tokens by their start position. A comment on the same line right after
+/// a token trails that token, every other comment leads the token following it.
+#[derive(Debug, Default)]
+pub struct Trivia {
+    leading: BTreeMap<u32, Vec<FreeSpan>>,
+    trailing: BTreeMap<u32, FreeSpan>,
+    directives: Vec<FreeSpan>,
+}
+
+impl Trivia {
+    /// Returns the spans of the well-formed line directives, in source order
+    pub fn directives(&self) -> &[FreeSpan] {
+        &self.directives
+    }
+
+    /// Returns the comments preceding `token`
+    pub fn leading(&self, token: Token) -> &[FreeSpan] {
+        self.leading.get(&token.span.start)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the comment following `token` on the same line
+    pub fn trailing(&self, token: Token) -> Option<FreeSpan> {
+        self.trailing.get(&token.span.start).copied()
+    }
+}
+
 pub struct Lexer<'src> {
     inner: logos::Lexer<'src, TokenKind>,
     current: TokenKind,
+    consumed: usize,
+    /// End of the last consumed token
+    consumed_end: u32,
+    trivia: Option<Trivia>,
+    source_map: SourceMap,
 }
 
 impl<'src> Lexer<'src> {
     pub fn new(source: &'src str) -> Lexer<'src> {
-        let mut inner = logos::Lexer::new(source);
-        let current = inner.next().unwrap_or(TokenKind::Eof);
-        Lexer { current, inner }
+        Lexer::new_impl(source, None, 0)
     }
 
-    /// Returns the current token
+    /// Creates a lexer which collects comments instead of throwing them away
+    pub fn with_trivia(source: &'src str) -> Lexer<'src> {
+        Lexer::new_impl(source, Some(Trivia::default()), 0)
+    }
+
+    /// `start` has to be the start of a token, or of whitespace or a comment before one
+    fn new_impl(source: &'src str, trivia: Option<Trivia>, start: usize) -> Lexer<'src> {
+        let mut lexer = Lexer {
+            inner: logos::Lexer::new(source),
+            current: TokenKind::Eof,
+            consumed: 0,
+            consumed_end: 0,
+            trivia,
+            source_map: SourceMap::default(),
+        };
+        // skip a `#!` interpreter line, only at the very start of the source
+        if start == 0 && source.starts_with("#!") {
+            lexer.inner.bump(source.find('\n').unwrap_or(source.len()));
+        } else {
+            lexer.inner.bump(start);
+        }
+        lexer.advance(None);
+        lexer
+    }
+
+    /// Moves to the next token which is not a comment or a line directive
+    ///
+    /// `prev` is the token that has been current until now.
+    fn advance(&mut self, prev: Option<FreeSpan>) {
+        let mut leading = Vec::new();
+        loop {
+            self.current = self.inner.next().unwrap_or(TokenKind::Eof);
+            if self.current == TokenKind::LineDirective {
+                let (source, span) = (self.inner.source(), self.inner.span());
+                if self.source_map.push_directive(source, span.clone()) {
+                    if let Some(trivia) = &mut self.trivia {
+                        trivia.directives.push(span.into());
+                    }
+                    continue;
+                }
+                // line number doesn't fit
+                self.current = TokenKind::Error;
+            }
+            if self.current != TokenKind::Comment {
+                break;
+            }
+            if let Some(trivia) = &mut self.trivia {
+                let comment = FreeSpan::from(self.inner.span());
+                let source = self.inner.source();
+                let trails = prev
+                    .filter(|_| leading.is_empty())
+                    .filter(|prev| !source[(prev.end as usize)..(comment.start as usize)].contains('\n'));
+                if let Some(prev) = trails {
+                    trivia.trailing.insert(prev.start, comment);
+                } else {
+                    leading.push(comment);
+                }
+            }
+        }
+        if let (Some(trivia), false) = (&mut self.trivia, leading.is_empty()) {
+            let start = self.inner.span().start as u32;
+            trivia.leading.insert(start, leading);
+        }
+    }
+
+    /// Returns the current token, `Eof` is placed at [`eof_span`](Lexer::eof_span)
     pub fn peek(&self) -> Token {
+        let span = if self.current == TokenKind::Eof {
+            self.eof_span()
+        } else {
+            self.inner.span().into()
+        };
         Token {
             kind: self.current,
-            span: self.inner.span().into(),
+            span,
         }
     }
 
+    /// Returns an empty span at the end of the source
+    pub fn eof_span(&self) -> FreeSpan {
+        let len = self.source().len();
+        FreeSpan::from(len..len)
+    }
+
     /// Returns the current token and advance the lexer
     #[allow(clippy::should_implement_trait)] // Iterator returns an Option we always return a Token
     pub fn next(&mut self) -> Token {
         let last = self.peek();
-        self.current = self.inner.next().unwrap_or(TokenKind::Eof);
+        if last.kind != TokenKind::Eof {
+            self.consumed += 1;
+            self.consumed_end = last.span.end;
+        }
+        self.advance(Some(last.span));
         last
     }
 
+    /// Returns the collected comments, `None` if the lexer hasn't been created
+    /// [`with_trivia`](Lexer::with_trivia)
+    pub fn take_trivia(&mut self) -> Option<Trivia> {
+        self.trivia.take()
+    }
+
+    /// Returns the line directives seen so far
+    pub fn take_source_map(&mut self) -> SourceMap {
+        std::mem::take(&mut self.source_map)
+    }
+
+    /// Returns the position right after the last consumed token, 0 before the first one
+    pub fn consumed_end(&self) -> u32 {
+        self.consumed_end
+    }
+
+    /// Returns how many tokens have been consumed so far, `Eof` is never counted
+    pub fn token_count(&self) -> usize {
+        self.consumed
+    }
+
     pub fn source(&self) -> &'src str {
         self.inner.source()
     }
 }
+
+/// Tokens of a source kept up to date across edits, for editors lexing on every keystroke
+///
+/// An edit is lexed again from the token before it, which may merge with the edited text, until a
+/// token is the same as before the edit. Tokens after that one are only moved.
+#[derive(Debug, Default)]
+pub struct TokenCache {
+    /// Without the `Eof`
+    tokens: Vec<Token>,
+}
+
+impl TokenCache {
+    pub fn new(source: &str) -> TokenCache {
+        let mut lexer = Lexer::new(source);
+        let tokens = std::iter::from_fn(|| Some(lexer.next()).filter(|token| token.kind != TokenKind::Eof))
+            .collect();
+        TokenCache { tokens }
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Updates the tokens after `replaced`, a byte range of the previous source, has been replaced
+    /// by `inserted` bytes, resulting in `source`
+    ///
+    /// Returns the indices of the tokens which have been lexed again.
+    pub fn edit(&mut self, source: &str, replaced: Range<usize>, inserted: usize) -> Range<usize> {
+        let delta = inserted as isize - replaced.len() as isize;
+        let moved = |token: Token| Token {
+            kind: token.kind,
+            span: FreeSpan {
+                start: (token.span.start as isize + delta) as u32,
+                end: (token.span.end as isize + delta) as u32,
+            },
+        };
+
+        // one more token before the first touching the edit, `1.` and `5` can become `1.5`
+        let first = self.tokens.iter()
+            .position(|token| token.span.end as usize >= replaced.start)
+            .unwrap_or(self.tokens.len())
+            .saturating_sub(1);
+        // with no token before it, an edit in the leading trivia is lexed from the very start
+        let start = match first {
+            0 => 0,
+            _ => self.tokens[first].span.start as usize,
+        };
+        let mut old = self.tokens.iter()
+            .position(|token| token.span.start as usize >= replaced.end)
+            .unwrap_or(self.tokens.len())
+            .max(first);
+
+        let mut lexer = Lexer::new_impl(source, None, start);
+        let mut fresh = Vec::new();
+        let mut synced = false;
+        for token in std::iter::from_fn(|| Some(lexer.next()).filter(|token| token.kind != TokenKind::Eof)) {
+            while matches!(self.tokens.get(old), Some(prev) if moved(*prev).span.start < token.span.start) {
+                old += 1;
+            }
+            if matches!(self.tokens.get(old), Some(prev) if moved(*prev).span.range() == token.span.range() && prev.kind == token.kind) {
+                synced = true;
+                break;
+            }
+            fresh.push(token);
+        }
+        if !synced {
+            // lexed to the end, none of the old tokens is left
+            old = self.tokens.len();
+        }
+
+        let relexed = first..(first + fresh.len());
+        for token in &mut self.tokens[old..] {
+            *token = moved(*token);
+        }
+        self.tokens.splice(first..old, fresh);
+        relexed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{highlight, unescape_char, HighlightClass, Lexer, TokenCache, TokenKind};
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        let mut lexer = Lexer::new(source);
+        std::iter::from_fn(|| Some(lexer.next()).filter(|token| token.kind != TokenKind::Eof))
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_highlight() {
+        let source = "print \"hi\"; // greeting\nlet n = 1 + 2;";
+        let classes: Vec<_> = highlight(source).into_iter()
+            .map(|(span, class)| (span.anchor(source).as_str(), class))
+            .collect();
+        assert_eq!(classes, [
+            ("print", HighlightClass::Keyword),
+            ("\"hi\"", HighlightClass::String),
+            (";", HighlightClass::Punctuation),
+            ("// greeting", HighlightClass::Comment),
+            ("let", HighlightClass::Keyword),
+            ("n", HighlightClass::Identifier),
+            ("=", HighlightClass::Operator),
+            ("1", HighlightClass::Number),
+            ("+", HighlightClass::Operator),
+            ("2", HighlightClass::Number),
+            (";", HighlightClass::Punctuation),
+        ]);
+        assert_eq!(TokenKind::Not.highlight_class(), HighlightClass::Keyword);
+        assert_eq!(highlight("123abc")[0].1, HighlightClass::Invalid);
+
+        let source = "@line 7 \"gen.lox\"\nprint 1;";
+        let (span, class) = highlight(source)[0];
+        assert_eq!((span.anchor(source).as_str(), class), ("@line 7 \"gen.lox\"", HighlightClass::Comment));
+    }
+
+    #[test]
+    fn test_token_cache() {
+        let spans = |cache: &TokenCache| -> Vec<_> {
+            cache.tokens().iter().map(|token| (token.kind, token.span.range())).collect()
+        };
+        let edit = |before: &str, replaced: std::ops::Range<usize>, inserted: &str| {
+            let mut cache = TokenCache::new(before);
+            let mut after = before.to_owned();
+            after.replace_range(replaced.clone(), inserted);
+            let relexed = cache.edit(&after, replaced, inserted.len());
+            assert_eq!(spans(&cache), spans(&TokenCache::new(&after)), "editing into {:?}", after);
+            relexed
+        };
+
+        // only the identifier and the token before it are lexed again
+        let src = "let foo = bar + 1;\nprint foo;";
+        assert_eq!(edit(src, 12..12, "x"), 2..4);
+        // moved only
+        assert_eq!(edit(src, 0..0, " "), 0..0);
+
+        // tokens joining or splitting at the edit
+        assert_eq!(edit("a = = b;", 3..4, ""), 0..2);
+        assert_eq!(edit("x == y;", 3..3, " "), 0..3);
+        assert_eq!(edit("1. 5;", 2..3, ""), 0..1);
+        edit("print 1; // x\nprint 2;", 9..11, "");
+        edit("print 1; print 2;", 6..6, "\"");
+        edit("print 1;", 8..8, " print 2;");
+        edit("print 1; print 2;", 0..17, "");
+        edit("", 0..0, "print 1;");
+        // before the first token
+        assert_eq!(edit(" x;", 0..0, "y"), 0..1);
+        edit("// header\nprint 1;", 0..3, "print 0;");
+        edit("/* a */ x;", 3..3, " */ y; /*");
+    }
+
+    #[test]
+    fn test_shebang() {
+        let source = "#!/usr/bin/env rox\nprint 1;";
+        assert_eq!(kinds(source), [TokenKind::Print, TokenKind::Number, TokenKind::Semicolon]);
+        assert!(crate::parser::parse(source).is_ok());
+        assert_eq!(kinds("#!"), []);
+
+        // only the first line may be a shebang
+        assert_eq!(kinds("print 1;\n#!x")[3], TokenKind::Error);
+        assert_eq!(kinds(" #!x")[0], TokenKind::Error);
+    }
+
+    #[test]
+    fn test_invalid_number() {
+        assert_eq!(kinds("123abc"), [TokenKind::InvalidNumber]);
+        assert_eq!(kinds("1.5e3"), [TokenKind::InvalidNumber]);
+        assert_eq!(kinds("123 abc"), [TokenKind::Number, TokenKind::Identifier]);
+        assert_eq!(kinds("0..n"), [TokenKind::Number, TokenKind::DotDot, TokenKind::Identifier]);
+        assert_eq!(kinds("1.x"), [TokenKind::Number, TokenKind::Dot, TokenKind::Identifier]);
+    }
+
+    #[test]
+    fn test_char() {
+        assert_eq!(kinds("'a'"), [TokenKind::Char]);
+        assert_eq!(kinds(r"'\n' '\'' '\\'"), [TokenKind::Char; 3]);
+        assert_eq!(kinds("'a' + 'b'"), [TokenKind::Char, TokenKind::Plus, TokenKind::Char]);
+        assert_eq!(kinds("'ab'")[0], TokenKind::Error);
+        assert_eq!(kinds("''")[0], TokenKind::Error);
+        assert_eq!(kinds(r"'\x'")[0], TokenKind::Error);
+
+        assert_eq!(unescape_char("'é'"), Some('é'));
+        assert_eq!(unescape_char(r"'\''"), Some('\''));
+        assert_eq!(unescape_char(r"'\n'"), Some('\n'));
+        assert_eq!(unescape_char("'a"), None);
+    }
+
+    #[cfg_attr(not(feature = "unicode-identifiers"), ignore)]
+    #[test]
+    fn test_unicode_identifiers() {
+        let source = "let café = π;";
+        let mut lexer = Lexer::new(source);
+        lexer.next();
+        let name = lexer.next();
+        assert_eq!(name.kind, TokenKind::Identifier);
+        assert_eq!(name.span.anchor(source).as_str(), "café");
+        assert_eq!(kinds("π"), [TokenKind::Identifier]);
+        assert_eq!(kinds("_x1"), [TokenKind::Identifier]);
+        assert_eq!(kinds("1x"), [TokenKind::InvalidNumber]);
+        assert_eq!(kinds("1é"), [TokenKind::InvalidNumber]);
+    }
+}
@@ -13,14 +13,20 @@
 
 pub mod chunk;
 pub mod compiler;
+pub mod dialect;
 pub mod fmt;
 pub mod lexer;
 pub mod object;
 pub mod opcode;
 pub mod parser;
+pub mod source_map;
 pub mod span;
+pub mod stats;
 pub mod value;
 pub mod vm;
 
+pub use compiler::{compile_source, eval_const, CompileError};
+pub use dialect::Dialect;
+
 #[cfg(test)]
 mod test;
@@ -68,10 +68,10 @@ fn derive_partial_eq<'alloc, O: Object>(this: ObjectRef<'alloc, O>, other: Objec
         todo!("type error: type $object_ty doesn't support equality");
     }
 
-    if let Some(other) = other.downcast::<O>() {
-        <O as ObjectPartialEq>::eq(&*this, other)
-    } else {
-        todo!("type error: rhs type doesn't match");
+    // objects of different types are never equal, like values of different types
+    match other.downcast::<O>() {
+        Some(other) => <O as ObjectPartialEq>::eq(&*this, other),
+        None => false,
     }
 }
 
@@ -81,8 +81,17 @@ fn derive_hash_code<'alloc, O: Object>(this: ObjectRef<'alloc, O>) -> usize {
 
 
 pub mod function;
+pub mod range;
 pub mod string;
 
+/// Concrete type of an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjKind {
+    Function,
+    Range,
+    String,
+}
+
 
 ////////////////////////////////////////////////////////////////////////////////
 // Alloc/GC
@@ -506,6 +515,19 @@ impl<'alloc> ObjectRefAny<'alloc> {
         )
     }
 
+    pub fn kind(&self) -> ObjKind {
+        // every Object type has to be listed here
+        if self.is::<string::String>() {
+            ObjKind::String
+        } else if self.is::<range::Range>() {
+            ObjKind::Range
+        } else if self.is::<function::Function<'alloc>>() {
+            ObjKind::Function
+        } else {
+            unreachable!("unknown object type {}", self.vtable()._typename)
+        }
+    }
+
     pub fn downcast<O: Object>(self) -> Option<ObjectRef<'alloc, O>> {
         if self.is::<O>() {
             // SAFETY Just checked the type tag matches.
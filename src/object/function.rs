@@ -1,30 +1,88 @@
 use crate::chunk::Chunk;
 use crate::object::{Alloc, Object, ObjectRef, Trace};
 use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
 
 
 derive_Object!(['alloc] Function<'alloc>);
 pub struct Function<'alloc> {
-    pub(crate) _chunk: Chunk<'alloc>,
+    pub(crate) chunk: Chunk<'alloc>,
     pub(crate) name: Box<str>,
-    pub(crate) _arity: u32,
+    pub(crate) arity: u32,
 }
 
 unsafe impl<'alloc> Trace for Function<'alloc> {
     fn mark(&self) {
-        // nop
+        // strings and nested functions in the body's constant pool
+        self.chunk.constants().for_each(Trace::mark);
     }
 }
 
 impl<'alloc> Function<'alloc> {
-    pub fn new(_chunk: Chunk<'alloc>, _arity: u32, name: &str, alloc: &'alloc Alloc) -> ObjectRef<'alloc, Function<'alloc>> {
+    pub fn new(chunk: Chunk<'alloc>, arity: u32, name: &str, alloc: &'alloc Alloc) -> ObjectRef<'alloc, Function<'alloc>> {
         let name = name.into();
-        Object::init(Function { _chunk, name, _arity }, alloc)
+        Object::init(Function { chunk, name, arity }, alloc)
+    }
+
+    pub fn chunk(&self) -> &Chunk<'alloc> {
+        &self.chunk
+    }
+
+    pub fn arity(&self) -> u32 {
+        self.arity
     }
 }
 
+/// Functions are only equal to themselves
+impl<'alloc> PartialEq for Function<'alloc> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl<'alloc> Eq for Function<'alloc> {}
+
+impl<'alloc> Hash for Function<'alloc> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self, state)
+    }
+}
+
+/// Printed form, `<fn name>` or `<fn>` for an anonymous function
 impl<'alloc> Debug for Function<'alloc> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Function({})", self.name)
+        if self.name.is_empty() {
+            f.write_str("<fn>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Function;
+    use crate::chunk::Chunk;
+    use crate::object::string::String as ObjString;
+    use crate::object::Alloc;
+    use crate::value::Value;
+
+    #[test]
+    fn test_print() {
+        let alloc = Alloc::new();
+        let foo = Value::new_object(Function::new(Chunk::default(), 0, "foo", &alloc));
+        assert_eq!(format!("{:?}", foo), "<fn foo>");
+        let anonymous = Function::new(Chunk::default(), 1, "", &alloc);
+        assert_eq!(format!("{:?}", anonymous), "<fn>");
+    }
+
+    #[test]
+    fn test_identity() {
+        let alloc = Alloc::new();
+        let foo = Value::new_object(Function::new(Chunk::default(), 0, "foo", &alloc));
+        let same = Value::new_object(Function::new(Chunk::default(), 0, "foo", &alloc));
+        assert_eq!(foo, foo);
+        assert_ne!(foo, same);
+        assert_ne!(foo, Value::new_object(ObjString::new("foo", &alloc)));
     }
 }
@@ -0,0 +1,78 @@
+use crate::object::{Alloc, Object, ObjectRef, Trace};
+use std::fmt::{self, Debug};
+
+
+derive_Object!(Range);
+/// Half-open range of Numbers, `start..end` counting by `step`
+#[derive(PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+unsafe impl Trace for Range {
+    fn mark(&self) {
+        // nop
+    }
+}
+
+impl Range {
+    pub fn new<'alloc>(start: f64, end: f64, step: f64, alloc: &'alloc Alloc) -> ObjectRef<'alloc, Range> {
+        Object::init(Range { start, end, step }, alloc)
+    }
+
+    /// Number of elements, reversed ranges like `3..0` are empty
+    pub fn len(&self) -> usize {
+        let len = ((self.end - self.start) / self.step).ceil();
+        // also catches NaN and a zero step
+        if len > 0.0 && len.is_finite() {
+            len as usize
+        } else {
+            0
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<f64> {
+        if index < self.len() {
+            Some(self.start + index as f64 * self.step)
+        } else {
+            None
+        }
+    }
+}
+
+impl Debug for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}..{:?}", self.start, self.end)?;
+        if self.step != 1.0 {
+            write!(f, " by {:?}", self.step)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Range;
+    use crate::object::Alloc;
+
+    #[test]
+    fn test_len() {
+        let alloc = Alloc::new();
+        let range = Range::new(0.0, 3.0, 1.0, &alloc);
+        assert_eq!(range.len(), 3);
+        let elements: Vec<_> = (0..4).map(|i| range.get(i)).collect();
+        assert_eq!(elements, [Some(0.0), Some(1.0), Some(2.0), None]);
+
+        assert!(Range::new(2.0, 2.0, 1.0, &alloc).is_empty());
+        assert!(Range::new(3.0, 0.0, 1.0, &alloc).is_empty());
+        assert_eq!(Range::new(0.0, 2.5, 1.0, &alloc).len(), 3);
+        assert_eq!(Range::new(3.0, 0.0, -1.0, &alloc).len(), 3);
+        assert!(Range::new(0.0, 1.0, 0.0, &alloc).is_empty());
+    }
+}
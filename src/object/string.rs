@@ -1,8 +1,21 @@
 use crate::object::{Alloc, Object, ObjectRef, Trace};
-use std::fmt::{self, Debug};
+use std::cell::{Ref, RefCell};
+use std::fmt::{self, Debug, Write};
 use std::hash::{Hash, Hasher};
 
 
+/// Longest string `repeat` builds, in bytes
+pub const MAX_LEN: usize = 1 << 30;
+
+/// Why [`String::repeat`] failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepeatError {
+    /// `times` is not a non-negative integer
+    InvalidCount,
+    /// The result would be longer than [`MAX_LEN`]
+    TooLong,
+}
+
 derive_Object!(String);
 pub struct String {
     /// `fxhash::hash` of the `String`'s chars
@@ -10,6 +23,10 @@ pub struct String {
 
     /// string data
     chars: Box<str>,
+
+    /// Quoted and escaped form for printing, computed on first use. Strings are immutable so it
+    /// never needs to be invalidated.
+    display: RefCell<Option<Box<str>>>,
 }
 
 unsafe impl Trace for String {
@@ -25,17 +42,42 @@ impl String {
 
     pub fn new_owned<'alloc>(chars: Box<str>, alloc: &'alloc Alloc) -> ObjectRef<'alloc, String> {
         let hash = fxhash::hash(chars.as_bytes());
-        Object::init(String { hash, chars }, alloc)
+        Object::init(String { hash, chars, display: RefCell::new(None) }, alloc)
     }
 
     pub fn as_str(&self) -> &str {
         &self.chars
     }
+
+    /// Concatenates `times` copies of the string
+    pub fn repeat<'alloc>(&self, times: f64, alloc: &'alloc Alloc) -> Result<ObjectRef<'alloc, String>, RepeatError> {
+        if times < 0.0 || times.fract() != 0.0 {
+            return Err(RepeatError::InvalidCount);
+        }
+        // the cast saturates, huge counts still overflow the multiplication
+        let times = times as usize;
+        match self.chars.len().checked_mul(times) {
+            Some(len) if len <= MAX_LEN => {}
+            _ => return Err(RepeatError::TooLong),
+        }
+        let chars = self.chars.repeat(times);
+        Ok(String::new_owned(chars.into_boxed_str(), alloc))
+    }
+
+    /// The string as it's printed, quoted with special characters escaped
+    pub fn display(&self) -> Ref<'_, str> {
+        if self.display.borrow().is_none() {
+            let mut display = std::string::String::new();
+            write!(display, "{:?}", &*self.chars).unwrap();
+            *self.display.borrow_mut() = Some(display.into_boxed_str());
+        }
+        Ref::map(self.display.borrow(), |display| display.as_deref().unwrap())
+    }
 }
 
 impl Debug for String {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.chars.fmt(f)
+        f.write_str(&self.display())
     }
 }
 
@@ -59,3 +101,31 @@ impl super::ObjectHashCode for String {
         self.hash
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{RepeatError, String, MAX_LEN};
+    use crate::object::Alloc;
+
+    #[test]
+    fn test_repeat() {
+        let alloc = Alloc::new();
+        let string = String::new("ab", &alloc);
+        assert_eq!(string.repeat(3.0, &alloc).unwrap().as_str(), "ababab");
+        assert_eq!(string.repeat(-1.0, &alloc).err(), Some(RepeatError::InvalidCount));
+        assert_eq!(string.repeat(1.5, &alloc).err(), Some(RepeatError::InvalidCount));
+        assert_eq!(string.repeat((MAX_LEN / 2 + 1) as f64, &alloc).err(), Some(RepeatError::TooLong));
+        assert_eq!(string.repeat(1e18, &alloc).err(), Some(RepeatError::TooLong));
+        assert_eq!(String::new("", &alloc).repeat(1e18, &alloc).unwrap().as_str(), "");
+    }
+
+    #[test]
+    fn test_display_cached() {
+        let alloc = Alloc::new();
+        let string = String::new("say \"hi\"", &alloc);
+        let first: *const str = &*string.display();
+        assert_eq!(&*string.display(), r#""say \"hi\"""#);
+        assert_eq!(format!("{:?}", *string), r#""say \"hi\"""#);
+        assert!(std::ptr::eq(first, &*string.display()));
+    }
+}
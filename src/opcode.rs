@@ -23,12 +23,28 @@ pub enum OpCode {
     True,
     False,
     Pop,
+    /// Exchanges the two top values, no net stack effect. Used when operands are evaluated in a
+    /// different order than the consuming instruction expects them.
+    Swap,
+    /// Pushes a copy of the top value
+    Dup,
     GetLocal { slot: u16 },
     SetLocal { slot: u16 },
     GetGlobal { name_key: ConstKey },
     DefGlobal { name_key: ConstKey },
     SetGlobal { name_key: ConstKey },
+    /// Same as `GetGlobal` for a global resolved at compile time, `slot` indexes
+    /// [`Chunk::global_names`](crate::chunk::Chunk::global_names)
+    GetGlobalSlot { slot: u16 },
+    DefGlobalSlot { slot: u16 },
+    SetGlobalSlot { slot: u16 },
+    /// Compares the two top values, values of different types are never equal so there is no
+    /// coercion, `true == 1` is `false`. Numbers within `f64::EPSILON` of each other are equal,
+    /// `0.1 + 0.2 == 0.3` is `true`.
     Equal,
+    /// Same as `Equal` except Numbers have to be exactly equal, `0.1 + 0.2 === 0.3` is `false`.
+    /// There is a single Number type, `1 === 1.0` is `true`.
+    StrictEqual,
     Greater,
     Less,
     Add,
@@ -37,12 +53,41 @@ pub enum OpCode {
     Divide,
     Not,
     Negate,
+    /// Replaces the top value with a Bool telling whether it is `()`, cheaper than comparing
+    /// against a `Unit` with `Equal`
+    IsUnit,
+    /// Replaces the top value with the String it prints as, Strings are left as they are
+    Stringify,
+    /// Pops `end` and `start` Numbers, pushes the Range `start..end`
+    BuildRange,
+    /// Replaces an iterable with its number of elements
+    Len,
+    /// Pops an index and an iterable, pushes the element at the index
+    GetIndex,
     Assert,
+    /// Same as `Assert`, the failure carries the string constant `reason_key`
+    AssertBecause { reason_key: ConstKey },
+    /// Same as `Assert`, the failure carries a snapshot of the stack below the asserted value
+    DebugAssert,
     Print,
+    /// Prints the top value like `Print` together with the source text of the instruction's span
+    /// and leaves it on the stack
+    Dbg,
+    /// Flushes the VM's output, ends every compiled program
+    Flush,
+    /// Coverage counter of block `id` of [`Chunk::coverage_map`](crate::chunk::Chunk::coverage_map),
+    /// no effect on the stack
+    Hit { id: u16 },
     Jump { offset: u16 },
     JumpIfTrue { offset: u16 },
     JumpIfFalse { offset: u16 },
+    JumpLong { offset: u32 },
+    JumpIfTrueLong { offset: u32 },
+    JumpIfFalseLong { offset: u32 },
     Loop { offset: u16 },
+    /// Calls the value below the top `arity` arguments, replacing the callee and the arguments
+    /// with the returned value
+    Call { arity: u8 },
     Return,
 }
 
@@ -52,12 +97,18 @@ opcodes! {
     TRUE,
     FALSE,
     POP,
+    SWAP,
+    DUP,
     GET_LOCAL,
     SET_LOCAL,
     GET_GLOBAL,
     DEF_GLOBAL,
     SET_GLOBAL,
+    GET_GLOBAL_SLOT,
+    DEF_GLOBAL_SLOT,
+    SET_GLOBAL_SLOT,
     EQUAL,
+    STRICT_EQUAL,
     GREATER,
     LESS,
     ADD,
@@ -66,12 +117,26 @@ opcodes! {
     DIVIDE,
     NOT,
     NEGATE,
+    IS_UNIT,
+    STRINGIFY,
+    BUILD_RANGE,
+    LEN,
+    GET_INDEX,
     ASSERT,
+    ASSERT_BECAUSE,
+    DEBUG_ASSERT,
     PRINT,
+    DBG,
+    FLUSH,
+    HIT,
     JUMP,
     JUMP_IF_TRUE,
     JUMP_IF_FALSE,
+    JUMP_LONG,
+    JUMP_IF_TRUE_LONG,
+    JUMP_IF_FALSE_LONG,
     LOOP,
+    CALL,
     RETURN,
 }
 
@@ -85,6 +150,8 @@ impl OpCode {
             [Self::TRUE, rest @ .. ]      => (OpCode::True, rest),
             [Self::FALSE, rest @ .. ]     => (OpCode::False, rest),
             [Self::POP, rest @ .. ]       => (OpCode::Pop, rest),
+            [Self::SWAP, rest @ .. ]      => (OpCode::Swap, rest),
+            [Self::DUP, rest @ .. ]       => (OpCode::Dup, rest),
             [Self::GET_LOCAL, x, y, rest @ .. ] => {
                 (OpCode::GetLocal { slot: u16::from_le_bytes([*x, *y]) }, rest)
             }
@@ -100,7 +167,17 @@ impl OpCode {
             [Self::SET_GLOBAL, x, y, rest @ .. ] => {
                 (OpCode::SetGlobal { name_key: ConstKey::from_le_bytes([*x, *y]) }, rest)
             }
+            [Self::GET_GLOBAL_SLOT, x, y, rest @ .. ] => {
+                (OpCode::GetGlobalSlot { slot: u16::from_le_bytes([*x, *y]) }, rest)
+            }
+            [Self::DEF_GLOBAL_SLOT, x, y, rest @ .. ] => {
+                (OpCode::DefGlobalSlot { slot: u16::from_le_bytes([*x, *y]) }, rest)
+            }
+            [Self::SET_GLOBAL_SLOT, x, y, rest @ .. ] => {
+                (OpCode::SetGlobalSlot { slot: u16::from_le_bytes([*x, *y]) }, rest)
+            }
             [Self::EQUAL, rest @ .. ]     => (OpCode::Equal, rest),
+            [Self::STRICT_EQUAL, rest @ .. ] => (OpCode::StrictEqual, rest),
             [Self::GREATER, rest @ .. ]   => (OpCode::Greater, rest),
             [Self::LESS, rest @ .. ]      => (OpCode::Less, rest),
             [Self::ADD, rest @ .. ]       => (OpCode::Add, rest),
@@ -109,8 +186,22 @@ impl OpCode {
             [Self::DIVIDE, rest @ .. ]    => (OpCode::Divide, rest),
             [Self::NOT, rest @ .. ]       => (OpCode::Not, rest),
             [Self::NEGATE, rest @ .. ]    => (OpCode::Negate, rest),
+            [Self::IS_UNIT, rest @ .. ]   => (OpCode::IsUnit, rest),
+            [Self::STRINGIFY, rest @ .. ] => (OpCode::Stringify, rest),
+            [Self::BUILD_RANGE, rest @ .. ] => (OpCode::BuildRange, rest),
+            [Self::LEN, rest @ .. ]       => (OpCode::Len, rest),
+            [Self::GET_INDEX, rest @ .. ] => (OpCode::GetIndex, rest),
             [Self::ASSERT, rest @ .. ]    => (OpCode::Assert, rest),
+            [Self::ASSERT_BECAUSE, x, y, rest @ .. ] => {
+                (OpCode::AssertBecause { reason_key: ConstKey::from_le_bytes([*x, *y]) }, rest)
+            }
+            [Self::DEBUG_ASSERT, rest @ .. ] => (OpCode::DebugAssert, rest),
             [Self::PRINT, rest @ .. ]     => (OpCode::Print, rest),
+            [Self::DBG, rest @ .. ]       => (OpCode::Dbg, rest),
+            [Self::FLUSH, rest @ .. ]     => (OpCode::Flush, rest),
+            [Self::HIT, x, y, rest @ .. ] => {
+                (OpCode::Hit { id: u16::from_le_bytes([*x, *y]) }, rest)
+            }
             [Self::JUMP, x, y, rest @ .. ] => {
                 (OpCode::Jump { offset: u16::from_le_bytes([*x, *y]) }, rest)
             }
@@ -120,9 +211,19 @@ impl OpCode {
             [Self::JUMP_IF_FALSE, x, y, rest @ .. ] => {
                 (OpCode::JumpIfFalse { offset: u16::from_le_bytes([*x, *y]) }, rest)
             }
+            [Self::JUMP_LONG, a, b, c, d, rest @ .. ] => {
+                (OpCode::JumpLong { offset: u32::from_le_bytes([*a, *b, *c, *d]) }, rest)
+            }
+            [Self::JUMP_IF_TRUE_LONG, a, b, c, d, rest @ .. ] => {
+                (OpCode::JumpIfTrueLong { offset: u32::from_le_bytes([*a, *b, *c, *d]) }, rest)
+            }
+            [Self::JUMP_IF_FALSE_LONG, a, b, c, d, rest @ .. ] => {
+                (OpCode::JumpIfFalseLong { offset: u32::from_le_bytes([*a, *b, *c, *d]) }, rest)
+            }
             [Self::LOOP, x, y, rest @ .. ] => {
                 (OpCode::Loop { offset: u16::from_le_bytes([*x, *y]) }, rest)
             }
+            [Self::CALL, arity, rest @ .. ] => (OpCode::Call { arity: *arity }, rest),
             [Self::RETURN, rest @ .. ]    => (OpCode::Return, rest),
             _ => return None,
         })
@@ -134,49 +235,319 @@ impl OpCode {
             OpCode::Constant { key: key_arg } |
             OpCode::GetGlobal { name_key: key_arg } |
             OpCode::DefGlobal { name_key: key_arg } |
-            OpCode::SetGlobal { name_key: key_arg } => {
+            OpCode::SetGlobal { name_key: key_arg } |
+            OpCode::AssertBecause { reason_key: key_arg } => {
                 code.extend(key_arg.to_le_bytes());
             }
             OpCode::GetLocal { slot: u16_arg } |
             OpCode::SetLocal { slot: u16_arg } |
+            OpCode::GetGlobalSlot { slot: u16_arg } |
+            OpCode::DefGlobalSlot { slot: u16_arg } |
+            OpCode::SetGlobalSlot { slot: u16_arg } |
+            OpCode::Hit { id: u16_arg } |
             OpCode::Jump { offset: u16_arg } |
             OpCode::JumpIfTrue { offset: u16_arg } |
             OpCode::JumpIfFalse { offset: u16_arg } |
             OpCode::Loop { offset: u16_arg } => {
                 code.extend(u16_arg.to_le_bytes());
             },
+            OpCode::JumpLong { offset: u32_arg } |
+            OpCode::JumpIfTrueLong { offset: u32_arg } |
+            OpCode::JumpIfFalseLong { offset: u32_arg } => {
+                code.extend(u32_arg.to_le_bytes());
+            },
+            OpCode::Call { arity } => code.push(arity),
             _ => {}
         }
     }
 
     pub const fn tag(self) -> u8 {
         match self {
-            OpCode::Constant { .. }     => Self::CONSTANT,
-            OpCode::Unit                => Self::UNIT,
-            OpCode::True                => Self::TRUE,
-            OpCode::False               => Self::FALSE,
-            OpCode::Pop                 => Self::POP,
-            OpCode::GetLocal { .. }     => Self::GET_LOCAL,
-            OpCode::SetLocal { .. }     => Self::SET_LOCAL,
-            OpCode::GetGlobal { .. }    => Self::GET_GLOBAL,
-            OpCode::DefGlobal { .. }    => Self::DEF_GLOBAL,
-            OpCode::SetGlobal { .. }    => Self::SET_GLOBAL,
-            OpCode::Equal               => Self::EQUAL,
-            OpCode::Greater             => Self::GREATER,
-            OpCode::Less                => Self::LESS,
-            OpCode::Add                 => Self::ADD,
-            OpCode::Subtract            => Self::SUBTRACT,
-            OpCode::Multiply            => Self::MULTIPLY,
-            OpCode::Divide              => Self::DIVIDE,
-            OpCode::Not                 => Self::NOT,
-            OpCode::Negate              => Self::NEGATE,
-            OpCode::Assert              => Self::ASSERT,
-            OpCode::Print               => Self::PRINT,
-            OpCode::Jump { .. }         => Self::JUMP,
-            OpCode::JumpIfTrue { .. }   => Self::JUMP_IF_TRUE,
-            OpCode::JumpIfFalse { .. }  => Self::JUMP_IF_FALSE,
-            OpCode::Loop { .. }         => Self::LOOP,
-            OpCode::Return              => Self::RETURN,
+            OpCode::Constant { .. }        => Self::CONSTANT,
+            OpCode::Unit                   => Self::UNIT,
+            OpCode::True                   => Self::TRUE,
+            OpCode::False                  => Self::FALSE,
+            OpCode::Pop                    => Self::POP,
+            OpCode::Swap                   => Self::SWAP,
+            OpCode::Dup                    => Self::DUP,
+            OpCode::GetLocal { .. }        => Self::GET_LOCAL,
+            OpCode::SetLocal { .. }        => Self::SET_LOCAL,
+            OpCode::GetGlobal { .. }       => Self::GET_GLOBAL,
+            OpCode::DefGlobal { .. }       => Self::DEF_GLOBAL,
+            OpCode::SetGlobal { .. }       => Self::SET_GLOBAL,
+            OpCode::GetGlobalSlot { .. }   => Self::GET_GLOBAL_SLOT,
+            OpCode::DefGlobalSlot { .. }   => Self::DEF_GLOBAL_SLOT,
+            OpCode::SetGlobalSlot { .. }   => Self::SET_GLOBAL_SLOT,
+            OpCode::Equal                  => Self::EQUAL,
+            OpCode::StrictEqual            => Self::STRICT_EQUAL,
+            OpCode::Greater                => Self::GREATER,
+            OpCode::Less                   => Self::LESS,
+            OpCode::Add                    => Self::ADD,
+            OpCode::Subtract               => Self::SUBTRACT,
+            OpCode::Multiply               => Self::MULTIPLY,
+            OpCode::Divide                 => Self::DIVIDE,
+            OpCode::Not                    => Self::NOT,
+            OpCode::Negate                 => Self::NEGATE,
+            OpCode::IsUnit                 => Self::IS_UNIT,
+            OpCode::Stringify              => Self::STRINGIFY,
+            OpCode::BuildRange             => Self::BUILD_RANGE,
+            OpCode::Len                    => Self::LEN,
+            OpCode::GetIndex               => Self::GET_INDEX,
+            OpCode::Assert                 => Self::ASSERT,
+            OpCode::AssertBecause { .. }   => Self::ASSERT_BECAUSE,
+            OpCode::DebugAssert            => Self::DEBUG_ASSERT,
+            OpCode::Print                  => Self::PRINT,
+            OpCode::Dbg                    => Self::DBG,
+            OpCode::Flush                  => Self::FLUSH,
+            OpCode::Hit { .. }             => Self::HIT,
+            OpCode::Jump { .. }            => Self::JUMP,
+            OpCode::JumpIfTrue { .. }      => Self::JUMP_IF_TRUE,
+            OpCode::JumpIfFalse { .. }     => Self::JUMP_IF_FALSE,
+            OpCode::JumpLong { .. }        => Self::JUMP_LONG,
+            OpCode::JumpIfTrueLong { .. }  => Self::JUMP_IF_TRUE_LONG,
+            OpCode::JumpIfFalseLong { .. } => Self::JUMP_IF_FALSE_LONG,
+            OpCode::Loop { .. }            => Self::LOOP,
+            OpCode::Call { .. }            => Self::CALL,
+            OpCode::Return                 => Self::RETURN,
+        }
+    }
+
+    /// Number of operand bytes following the opcode `tag`, `None` for unknown tags
+    ///
+    /// Found by decoding the tag followed by zeroes so the widths can't drift from
+    /// [`decode`](OpCode::decode). Key and `u16` operands take two bytes, the long jumps four and the
+    /// arity of `Call` one.
+    pub fn operand_len(tag: u8) -> Option<usize> {
+        const MAX_OPERAND_LEN: usize = 4;
+        let mut code = [0; 1 + MAX_OPERAND_LEN];
+        code[0] = tag;
+        let (_, rest) = OpCode::decode(&code)?;
+        Some(MAX_OPERAND_LEN - rest.len())
+    }
+
+    /// Classifies the opcode for optimization passes, every opcode has to be listed explicitly
+    pub const fn category(self) -> Category {
+        match self {
+            OpCode::Constant { .. } |
+            OpCode::Unit |
+            OpCode::True |
+            OpCode::False                  => Category::ConstantLoad,
+            OpCode::GetLocal { .. } |
+            OpCode::SetLocal { .. }        => Category::LocalAccess,
+            OpCode::Jump { .. } |
+            OpCode::JumpLong { .. } |
+            OpCode::Loop { .. }            => Category::Jump { conditional: false },
+            OpCode::JumpIfTrue { .. } |
+            OpCode::JumpIfFalse { .. } |
+            OpCode::JumpIfTrueLong { .. } |
+            OpCode::JumpIfFalseLong { .. } => Category::Jump { conditional: true },
+            OpCode::Return                 => Category::Return,
+            OpCode::Pop |
+            OpCode::Swap |
+            OpCode::Dup |
+            OpCode::GetGlobal { .. } |
+            OpCode::DefGlobal { .. } |
+            OpCode::SetGlobal { .. } |
+            OpCode::GetGlobalSlot { .. } |
+            OpCode::DefGlobalSlot { .. } |
+            OpCode::SetGlobalSlot { .. } |
+            OpCode::Equal |
+            OpCode::StrictEqual |
+            OpCode::Greater |
+            OpCode::Less |
+            OpCode::Add |
+            OpCode::Subtract |
+            OpCode::Multiply |
+            OpCode::Divide |
+            OpCode::Not |
+            OpCode::Negate |
+            OpCode::IsUnit |
+            OpCode::Stringify |
+            OpCode::BuildRange |
+            OpCode::Len |
+            OpCode::GetIndex |
+            OpCode::Assert |
+            OpCode::AssertBecause { .. } |
+            OpCode::DebugAssert |
+            OpCode::Print |
+            OpCode::Dbg |
+            OpCode::Flush |
+            OpCode::Hit { .. } |
+            OpCode::Call { .. }            => Category::Other,
+        }
+    }
+
+    /// Returns how many values the instruction pops and pushes
+    ///
+    /// Instructions only peeking at values, like `SetLocal` or the conditional jumps, pop and push
+    /// them back.
+    pub const fn stack_effect(self) -> (u16, u16) {
+        match self {
+            OpCode::Constant { .. } |
+            OpCode::Unit |
+            OpCode::True |
+            OpCode::False |
+            OpCode::GetLocal { .. } |
+            OpCode::GetGlobal { .. } |
+            OpCode::GetGlobalSlot { .. } => (0, 1),
+            OpCode::Pop |
+            OpCode::DefGlobal { .. } |
+            OpCode::DefGlobalSlot { .. } |
+            OpCode::Assert |
+            OpCode::AssertBecause { .. } |
+            OpCode::DebugAssert |
+            OpCode::Print |
+            OpCode::Return => (1, 0),
+            OpCode::Swap => (2, 2),
+            OpCode::Dup => (1, 2),
+            OpCode::SetLocal { .. } |
+            OpCode::SetGlobal { .. } |
+            OpCode::SetGlobalSlot { .. } |
+            OpCode::Not |
+            OpCode::Negate |
+            OpCode::IsUnit |
+            OpCode::Stringify |
+            OpCode::Len |
+            OpCode::Dbg |
+            OpCode::JumpIfTrue { .. } |
+            OpCode::JumpIfFalse { .. } |
+            OpCode::JumpIfTrueLong { .. } |
+            OpCode::JumpIfFalseLong { .. } => (1, 1),
+            OpCode::Equal |
+            OpCode::StrictEqual |
+            OpCode::Greater |
+            OpCode::Less |
+            OpCode::Add |
+            OpCode::Subtract |
+            OpCode::Multiply |
+            OpCode::Divide |
+            OpCode::BuildRange |
+            OpCode::GetIndex => (2, 1),
+            OpCode::Call { arity } => (arity as u16 + 1, 1),
+            OpCode::Flush |
+            OpCode::Hit { .. } |
+            OpCode::Jump { .. } |
+            OpCode::JumpLong { .. } |
+            OpCode::Loop { .. } => (0, 0),
+        }
+    }
+
+    /// Any jump, including `Loop`
+    pub const fn is_jump(self) -> bool {
+        matches!(self.category(), Category::Jump { .. })
+    }
+
+    /// Pushes a value known at compile time
+    pub const fn is_constant_load(self) -> bool {
+        matches!(self.category(), Category::ConstantLoad)
+    }
+
+    pub const fn is_local_access(self) -> bool {
+        matches!(self.category(), Category::LocalAccess)
+    }
+
+    /// Execution never continues with the following instruction
+    pub const fn is_terminator(self) -> bool {
+        matches!(self.category(), Category::Jump { conditional: false } | Category::Return)
+    }
+}
+
+/// Opcode classes, see [`OpCode::category`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    Jump { conditional: bool },
+    /// `Constant`, `Unit`, `True` and `False`
+    ConstantLoad,
+    LocalAccess,
+    Return,
+    Other,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Category, OpCode};
+    use crate::chunk::ConstKey;
+
+    #[test]
+    fn test_category() {
+        let key = ConstKey::from_le_bytes([0, 0]);
+        assert!(OpCode::Jump { offset: 1 }.is_jump());
+        assert!(OpCode::JumpIfFalseLong { offset: 1 }.is_jump());
+        assert!(OpCode::Loop { offset: 1 }.is_jump());
+        assert!(!OpCode::Return.is_jump());
+
+        assert!(OpCode::Constant { key }.is_constant_load());
+        assert!(OpCode::True.is_constant_load());
+        assert!(!OpCode::GetGlobal { name_key: key }.is_constant_load());
+
+        assert!(OpCode::SetLocal { slot: 0 }.is_local_access());
+        assert!(!OpCode::SetGlobal { name_key: key }.is_local_access());
+
+        assert!(OpCode::Return.is_terminator());
+        assert!(OpCode::Jump { offset: 1 }.is_terminator());
+        assert!(!OpCode::JumpIfTrue { offset: 1 }.is_terminator());
+        assert_eq!(OpCode::Add.category(), Category::Other);
+    }
+
+    #[test]
+    fn test_stack_effect() {
+        assert_eq!(OpCode::Add.stack_effect(), (2, 1));
+        assert_eq!(OpCode::SetLocal { slot: 0 }.stack_effect(), (1, 1));
+        assert_eq!(OpCode::Dup.stack_effect(), (1, 2));
+        assert_eq!(OpCode::JumpIfFalse { offset: 1 }.stack_effect(), (1, 1));
+        assert_eq!(OpCode::Loop { offset: 1 }.stack_effect(), (0, 0));
+        assert_eq!(OpCode::Call { arity: 255 }.stack_effect(), (256, 1));
+    }
+
+    #[test]
+    fn test_operand_len() {
+        assert_eq!(OpCode::operand_len(OpCode::CONSTANT), Some(2));
+        assert_eq!(OpCode::operand_len(OpCode::ADD), Some(0));
+        assert_eq!(OpCode::operand_len(OpCode::GET_LOCAL), Some(2));
+        assert_eq!(OpCode::operand_len(OpCode::JUMP_IF_FALSE_LONG), Some(4));
+        assert_eq!(OpCode::operand_len(OpCode::CALL), Some(1));
+        assert_eq!(OpCode::operand_len(OpCode::RETURN + 1), None);
+
+        // agrees with the encoded instructions
+        let key = ConstKey::from_le_bytes([1, 2]);
+        for opcode in [OpCode::Pop, OpCode::DefGlobal { name_key: key }, OpCode::Loop { offset: 3 }] {
+            let mut code = Vec::new();
+            opcode.encode(&mut code);
+            assert_eq!(OpCode::operand_len(opcode.tag()), Some(code.len() - 1));
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let opcodes = [
+            OpCode::Swap,
+            OpCode::Dup,
+            OpCode::StrictEqual,
+            OpCode::IsUnit,
+            OpCode::Stringify,
+            OpCode::DebugAssert,
+            OpCode::Dbg,
+            OpCode::Flush,
+            OpCode::Hit { id: 7 },
+            OpCode::Pop,
+            OpCode::GetLocal { slot: 258 },
+            OpCode::SetGlobalSlot { slot: 3 },
+            OpCode::AssertBecause { reason_key: ConstKey::from_le_bytes([1, 2]) },
+            OpCode::JumpLong { offset: 70000 },
+            OpCode::Call { arity: 3 },
+            OpCode::Return,
+        ];
+        let mut code = Vec::new();
+        for opcode in opcodes {
+            opcode.encode(&mut code);
+        }
+
+        let mut rest = code.as_slice();
+        for expected in opcodes {
+            let (opcode, next) = OpCode::decode(rest).unwrap();
+            assert_eq!(opcode, expected);
+            rest = next;
         }
+        assert!(rest.is_empty());
     }
 }
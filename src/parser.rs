@@ -1,5 +1,10 @@
-use crate::lexer::{Lexer, Token, TokenKind};
+use crate::lexer::{Lexer, Token, TokenKind, Trivia};
+use crate::source_map::SourceMap;
+use crate::span::FreeSpan;
+use crate::stats::Stats;
+use std::fmt;
 use std::num::ParseFloatError;
+use std::time::Instant;
 
 
 pub mod ast;
@@ -9,7 +14,8 @@ use ast::*;
 
 // Parsing implementation
 //
-// This parsing algorithm is **not** resilient, it will bail on the first error encountered.
+// This parsing algorithm bails on the first error encountered, unless it's created by
+// `parse_resilient` which skips to the next statement and carries on.
 // Precedence on infix expressions is handled using the Pratt binding power algorithm.
 
 #[derive(Debug)]
@@ -32,23 +38,177 @@ pub enum Error {
         token: Token,
         cause: ParseFloatError,
     },
-    InvalidAssignmentTarget,
+    /// Number literal directly followed by identifier characters, `123abc`
+    NumberWithSuffix {
+        token: Token,
+    },
+    /// `span` is the whole left hand side
+    InvalidAssignmentTarget {
+        span: FreeSpan,
+    },
+    /// Statement not terminated by a `;`, `span` is empty and placed right after the statement
+    MissingSemicolon {
+        span: FreeSpan,
+    },
+    /// Source is longer than [`Options::max_source_len`]
+    ProgramTooLarge {
+        len: usize,
+        max: usize,
+    },
+}
+
+impl Error {
+    /// Returns where the error happened, `None` for errors about the whole source
+    pub fn span(&self) -> Option<FreeSpan> {
+        match self {
+            Error::UnexpectedToken { found, .. } |
+            Error::UnexpectedToken2 { found, .. } |
+            Error::ExpectedExpressionStart { found } |
+            Error::ExpectedInfixOrPostfixOperator { found } => Some(found.span),
+            Error::InvalidNumberLiteral { token, .. } |
+            Error::NumberWithSuffix { token } => Some(token.span),
+            Error::MissingSemicolon { span } |
+            Error::InvalidAssignmentTarget { span } => Some(*span),
+            Error::ProgramTooLarge { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnexpectedToken { found, expected } => {
+                write!(f, "expected {:?}, found {:?}", expected, found.kind)
+            }
+            Error::UnexpectedToken2 { found, expected } => {
+                write!(f, "expected one of {:?}, found {:?}", expected, found.kind)
+            }
+            Error::ExpectedExpressionStart { found } => {
+                write!(f, "expected an expression, found {:?}", found.kind)
+            }
+            Error::ExpectedInfixOrPostfixOperator { found } => {
+                write!(f, "expected an operator, found {:?}", found.kind)
+            }
+            Error::InvalidNumberLiteral { cause, .. } => write!(f, "invalid number literal: {}", cause),
+            Error::NumberWithSuffix { .. } => f.write_str("number literal followed by identifier characters"),
+            Error::InvalidAssignmentTarget { .. } => f.write_str("invalid assignment target"),
+            Error::MissingSemicolon { .. } => f.write_str("missing `;` after statement"),
+            Error::ProgramTooLarge { len, max } => {
+                write!(f, "source is {} bytes long, at most {} are accepted", len, max)
+            }
+        }
+    }
+}
+
+/// Parser configuration
+///
+/// The default options parse the strict language, every option relaxes or extends it.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Allow omitting the terminating semicolon of the last statement in a block, `{ print 1 }`
+    pub lenient_semicolons: bool,
+    /// Accept `!` as an alias of `not`, like in Lox, `!x` parses exactly like `not x`
+    pub symbolic_not: bool,
+    /// Accept the `not` keyword as prefix operator, on by default
+    pub word_not: bool,
+    /// Longest source in bytes accepted before parsing, 64 MiB by default
+    pub max_source_len: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            lenient_semicolons: false,
+            symbolic_not: false,
+            word_not: true,
+            max_source_len: 64 << 20,
+        }
+    }
 }
 
 struct Parser<'src> {
     lexer: Lexer<'src>,
+    options: Options,
+
+    /// Number of items, statements and expressions parsed so far
+    nodes: usize,
+
+    /// Errors recovered from, `None` unless the parser is resilient
+    errors: Option<Vec<Error>>,
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
 pub fn parse(src: &str) -> Result<Program> {
-    Parser {
-        lexer: Lexer::new(src),
-    }.program()
+    parse_with(src, &Options::default())
+}
+
+pub fn parse_with(src: &str, options: &Options) -> Result<Program> {
+    Parser::new(Lexer::new(src), options.clone()).program()
+}
+
+/// Same as [`parse`] but collects the errors instead of stopping at the first
+///
+/// An item failing to parse is replaced by an [`ErrorItem`] and the tokens up to the next statement
+/// are skipped, that is past a `;` or until a keyword starting one or the end of the block.
+pub fn parse_resilient(src: &str) -> (Program, Vec<Error>) {
+    let mut parser = Parser::new(Lexer::new(src), Options::default());
+    parser.errors = Some(Vec::new());
+    let program = match parser.program() {
+        Ok(program) => program,
+        // only happens before anything is parsed
+        Err(error) => return (Vec::new(), vec![error]),
+    };
+    (program, parser.errors.unwrap_or_default())
+}
+
+/// Parses `src` as a single expression, with nothing following it
+pub fn parse_expression(src: &str) -> Result<Expression> {
+    let mut parser = Parser::new(Lexer::new(src), Options::default());
+    let expr = parser.expression()?;
+    parser.expect_next(TokenKind::Eof)?;
+    Ok(expr)
+}
+
+/// Same as [`parse`] but keeps the comments in a side table
+pub fn parse_with_trivia(src: &str) -> Result<(Program, Trivia)> {
+    let mut parser = Parser::new(Lexer::with_trivia(src), Options::default());
+    let program = parser.program()?;
+    let trivia = parser.lexer.take_trivia().expect("lexer collects trivia");
+    Ok((program, trivia))
+}
+
+/// Same as [`parse`] but also records the line directives into `map`, up to the error if there is
+/// one
+pub fn parse_with_source_map(src: &str, map: &mut SourceMap) -> Result<Program> {
+    let mut parser = Parser::new(Lexer::new(src), Options::default());
+    let program = parser.program();
+    *map = parser.lexer.take_source_map();
+    program
+}
+
+/// Same as [`parse`] but also records the token and AST node counts into `stats`.
+pub fn parse_with_stats(src: &str, stats: &mut Stats) -> Result<Program> {
+    let start = Instant::now();
+    let mut parser = Parser::new(Lexer::new(src), Options::default());
+    let program = parser.program();
+    stats.tokens = parser.lexer.token_count();
+    stats.ast_nodes = parser.nodes;
+    stats.parse_time = start.elapsed();
+    program
 }
 
 // Utility functions for parsing
 impl<'src> Parser<'src> {
+    fn new(lexer: Lexer<'src>, options: Options) -> Parser<'src> {
+        Parser {
+            lexer,
+            options,
+            nodes: 0,
+            errors: None,
+        }
+    }
+
     fn expect_next(&mut self, kind: TokenKind) -> Result<Token> {
         let token = self.lexer.next();
         if token.kind == kind {
@@ -61,6 +221,26 @@ impl<'src> Parser<'src> {
         }
     }
 
+    /// Expects a statement terminating semicolon
+    ///
+    /// With [`Options::lenient_semicolons`] a missing semicolon right before a closing brace is
+    /// implied, the returned token is then empty and placed at the start of the brace. Otherwise a
+    /// missing semicolon is reported where it should have been instead of at the following token.
+    fn expect_semicolon(&mut self) -> Result<Token> {
+        if self.options.lenient_semicolons {
+            if let Some(right_brace_tok) = self.match_peek(TokenKind::RightBrace) {
+                return Ok(Token::synthetic(TokenKind::Semicolon, right_brace_tok.span));
+            }
+        }
+        match self.match_peek(TokenKind::Semicolon) {
+            Some(_) => Ok(self.lexer.next()),
+            None => {
+                let at = self.lexer.consumed_end();
+                Err(Error::MissingSemicolon { span: FreeSpan { start: at, end: at } })
+            }
+        }
+    }
+
     fn peek_kind(&self) -> TokenKind {
         self.lexer.peek().kind
     }
@@ -77,6 +257,11 @@ impl<'src> Parser<'src> {
 
 impl<'src> Parser<'src> {
     fn program(&mut self) -> Result<Program> {
+        let len = self.lexer.source().len();
+        if len > self.options.max_source_len {
+            return Err(Error::ProgramTooLarge { len, max: self.options.max_source_len });
+        }
+
         let mut program = Vec::new();
         while self.peek_kind() != TokenKind::Eof {
             let item = self.item()?;
@@ -86,13 +271,51 @@ impl<'src> Parser<'src> {
     }
 
     fn item(&mut self) -> Result<Item> {
-        // TODO recovery
-        Ok(match self.peek_kind() {
-            TokenKind::Class => Item::Class(self.class_item()?),
-            TokenKind::Fn => Item::Fn(self.fn_item()?),
-            TokenKind::Let => Item::Let(self.let_item()?),
-            _ => Item::Statement(self.statement()?),
-        })
+        self.nodes += 1;
+        let start = self.lexer.peek().span.start;
+        let consumed = self.lexer.token_count();
+        let item = match self.peek_kind() {
+            TokenKind::Class => self.class_item().map(Item::Class),
+            TokenKind::Fn => self.fn_item().map(Item::Fn),
+            TokenKind::Let => self.let_item().map(Item::Let),
+            _ => self.statement().map(Item::Statement),
+        };
+        match (item, &mut self.errors) {
+            (Err(error), Some(errors)) => {
+                errors.push(error);
+                // always make progress, the next item would fail on the same token otherwise
+                if self.lexer.token_count() == consumed {
+                    self.lexer.next();
+                }
+                self.synchronize();
+                let span = FreeSpan { start, end: self.lexer.consumed_end().max(start) };
+                Ok(Item::Error(ErrorItem { span }))
+            }
+            (item, _) => item,
+        }
+    }
+
+    /// Skips tokens until the start of the next statement after an error
+    fn synchronize(&mut self) {
+        // the failing token may have been the `;` ending the statement
+        if self.lexer.source()[..self.lexer.consumed_end() as usize].ends_with(';') {
+            return;
+        }
+        loop {
+            match self.peek_kind() {
+                TokenKind::Semicolon => {
+                    self.lexer.next();
+                    return;
+                }
+                TokenKind::Class | TokenKind::Fn | TokenKind::Let | TokenKind::Label |
+                TokenKind::For | TokenKind::If | TokenKind::Assert | TokenKind::Print |
+                TokenKind::Return | TokenKind::While | TokenKind::Break | TokenKind::Continue |
+                TokenKind::RightBrace | TokenKind::Eof => return,
+                _ => {
+                    self.lexer.next();
+                }
+            }
+        }
     }
 
     fn class_item(&mut self) -> Result<ClassItem> {
@@ -148,26 +371,44 @@ impl<'src> Parser<'src> {
         } else {
             None
         };
-        let semicolon_tok = self.expect_next(TokenKind::Semicolon)?;
+        let semicolon_tok = self.expect_semicolon()?;
         Ok(LetItem { let_tok, mut_tok, rec_tok, name, init, semicolon_tok })
     }
 
     fn statement(&mut self) -> Result<Statement> {
+        self.nodes += 1;
         Ok(match self.peek_kind() {
-            TokenKind::For => Statement::For(self.for_stmt()?),
+            TokenKind::Label => {
+                let label = self.lexer.next();
+                match self.peek_kind() {
+                    TokenKind::For => Statement::For(self.for_stmt(Some(label))?),
+                    TokenKind::While => Statement::While(self.while_stmt(Some(label))?),
+                    _ => return Err(Error::UnexpectedToken2 {
+                        found: self.lexer.next(),
+                        expected: &[TokenKind::For, TokenKind::While],
+                    }),
+                }
+            }
+            TokenKind::For => Statement::For(self.for_stmt(None)?),
             TokenKind::If => Statement::If(self.if_stmt()?),
             TokenKind::Assert => Statement::Assert(self.assert_stmt()?),
             TokenKind::Print => Statement::Print(self.print_stmt()?),
             TokenKind::Return => Statement::Return(self.return_stmt()?),
-            TokenKind::While => Statement::While(self.while_stmt()?),
+            TokenKind::While => Statement::While(self.while_stmt(None)?),
+            TokenKind::Break => Statement::Break(self.break_stmt()?),
+            TokenKind::Continue => Statement::Continue(self.continue_stmt()?),
             TokenKind::LeftBrace => Statement::Block(self.block()?),
             _ => Statement::Expr(self.expr_stmt()?),
         })
     }
 
-    fn for_stmt(&mut self) -> Result<ForStmt> {
-        // our for doesn't make sense until we have working function calls
-        todo!()
+    fn for_stmt(&mut self, label: Option<Token>) -> Result<ForStmt> {
+        let for_tok = self.expect_next(TokenKind::For)?;
+        let elem = self.name()?;
+        let in_tok = self.expect_next(TokenKind::In)?;
+        let iter = self.expression()?;
+        let body = self.block()?;
+        Ok(ForStmt { label, for_tok, elem, in_tok, iter, body })
     }
 
     fn if_stmt(&mut self) -> Result<IfStmt> {
@@ -187,29 +428,63 @@ impl<'src> Parser<'src> {
     fn assert_stmt(&mut self) -> Result<AssertStmt> {
         let assert_tok = self.expect_next(TokenKind::Assert)?;
         let expr = self.expression()?;
-        let semicolon_tok = self.expect_next(TokenKind::Semicolon)?;
-        Ok(AssertStmt { assert_tok, expr, semicolon_tok })
+        let because = if let Some(because_tok) = self.match_peek(TokenKind::Because) {
+            self.lexer.next();
+            let reason = self.expect_next(TokenKind::String)?;
+            Some(AssertBecause { because_tok, reason })
+        } else {
+            None
+        };
+        let semicolon_tok = self.expect_semicolon()?;
+        Ok(AssertStmt { assert_tok, expr, because, semicolon_tok })
     }
 
     fn print_stmt(&mut self) -> Result<PrintStmt> {
         let print_tok = self.expect_next(TokenKind::Print)?;
         let expr = self.expression()?;
-        let semicolon_tok = self.expect_next(TokenKind::Semicolon)?;
+        let semicolon_tok = self.expect_semicolon()?;
         Ok(PrintStmt { print_tok, expr, semicolon_tok })
     }
 
     fn return_stmt(&mut self) -> Result<ReturnStmt> {
         let return_tok = self.expect_next(TokenKind::Return)?;
-        let expr = self.expression()?;
-        let semicolon_tok = self.expect_next(TokenKind::Semicolon)?;
+        let expr = match self.peek_kind() {
+            TokenKind::Semicolon => None,
+            TokenKind::RightBrace if self.options.lenient_semicolons => None,
+            _ => Some(self.expression()?),
+        };
+        let semicolon_tok = self.expect_semicolon()?;
         Ok(ReturnStmt { return_tok, expr, semicolon_tok })
     }
 
-    fn while_stmt(&mut self) -> Result<WhileStmt> {
+    fn while_stmt(&mut self, label: Option<Token>) -> Result<WhileStmt> {
         let while_tok = self.expect_next(TokenKind::While)?;
         let pred = self.expression()?;
         let body = self.block()?;
-        Ok(WhileStmt { while_tok, pred, body })
+        Ok(WhileStmt { label, while_tok, pred, body })
+    }
+
+    fn break_stmt(&mut self) -> Result<BreakStmt> {
+        let break_tok = self.expect_next(TokenKind::Break)?;
+        let label = self.loop_target()?;
+        let semicolon_tok = self.expect_semicolon()?;
+        Ok(BreakStmt { break_tok, label, semicolon_tok })
+    }
+
+    fn continue_stmt(&mut self) -> Result<ContinueStmt> {
+        let continue_tok = self.expect_next(TokenKind::Continue)?;
+        let label = self.loop_target()?;
+        let semicolon_tok = self.expect_semicolon()?;
+        Ok(ContinueStmt { continue_tok, label, semicolon_tok })
+    }
+
+    /// Optional label name after `break` or `continue`
+    fn loop_target(&mut self) -> Result<Option<Identifier>> {
+        if self.match_peek(TokenKind::Identifier).is_some() {
+            Ok(Some(self.name()?))
+        } else {
+            Ok(None)
+        }
     }
 
     fn block(&mut self) -> Result<Block> {
@@ -225,7 +500,7 @@ impl<'src> Parser<'src> {
 
     fn expr_stmt(&mut self) -> Result<ExprStmt> {
         let expr = self.expression()?;
-        let semicolon_tok = self.expect_next(TokenKind::Semicolon)?;
+        let semicolon_tok = self.expect_semicolon()?;
         Ok(ExprStmt { expr, semicolon_tok })
     }
 
@@ -234,6 +509,7 @@ impl<'src> Parser<'src> {
     }
 
     fn expr_bp(&mut self, min_bp: u8) -> Result<Expression> {
+        self.nodes += 1;
         let mut lhs = {
             let token = self.lexer.next();
             match token.kind {
@@ -248,8 +524,41 @@ impl<'src> Parser<'src> {
                     let right_paren_tok = self.expect_next(TokenKind::RightParen)?;
                     Expression::Group(GroupExpr { left_paren_tok, expr, right_paren_tok })
                 }
+                TokenKind::Dbg => {
+                    let left_paren_tok = self.expect_next(TokenKind::LeftParen)?;
+                    let expr = Box::new(self.expr_bp(0)?);
+                    let right_paren_tok = self.expect_next(TokenKind::RightParen)?;
+                    Expression::Dbg(DbgExpr { dbg_tok: token, left_paren_tok, expr, right_paren_tok })
+                }
+                TokenKind::Bang if self.options.symbolic_not => {
+                    let ((), r_bp) = prefix_binding_power(TokenKind::Not);
+                    let expr = self.expr_bp(r_bp)?;
+                    Expression::Unary(UnaryExpr {
+                        // the rest of the pipeline only knows `not`
+                        operator: Token { kind: TokenKind::Not, span: token.span },
+                        expr: Box::new(expr),
+                    })
+                }
+                TokenKind::Format => {
+                    let left_paren_tok = self.expect_next(TokenKind::LeftParen)?;
+                    let template = self.expect_next(TokenKind::String)?;
+                    let mut arguments = Delimited::default();
+                    while let Some(comma_tok) = self.match_peek(TokenKind::Comma) {
+                        self.lexer.next();
+                        arguments.delim.push(comma_tok);
+                        arguments.items.push(self.expr_bp(0)?);
+                    }
+                    let right_paren_tok = self.expect_next(TokenKind::RightParen)?;
+                    Expression::Format(FormatExpr {
+                        format_tok: token,
+                        left_paren_tok,
+                        template,
+                        arguments,
+                        right_paren_tok,
+                    })
+                }
                 TokenKind::Minus |
-                TokenKind::Not => {
+                TokenKind::Not if token.kind == TokenKind::Minus || self.options.word_not => {
                     let ((), r_bp) = prefix_binding_power(token.kind);
                     let expr = self.expr_bp(r_bp)?;
                     Expression::Unary(UnaryExpr {
@@ -263,9 +572,13 @@ impl<'src> Parser<'src> {
                 TokenKind::Super |
                 TokenKind::Number |
                 TokenKind::String |
+                TokenKind::Char |
                 TokenKind::Identifier => {
                     Expression::Primary(PrimaryExpr { token })
                 }
+                TokenKind::InvalidNumber => {
+                    return Err(Error::NumberWithSuffix { token });
+                }
                 _ => {
                     return Err(Error::ExpectedExpressionStart {
                         found: token,
@@ -279,10 +592,14 @@ impl<'src> Parser<'src> {
             match operator.kind {
                 // infix operators
                 TokenKind::Equal |
+                TokenKind::DotDot |
                 TokenKind::Or |
                 TokenKind::And |
                 TokenKind::NotEqual |
                 TokenKind::EqualEqual |
+                TokenKind::StrictNotEqual |
+                TokenKind::StrictEqual |
+                TokenKind::QuestionQuestion |
                 TokenKind::Greater |
                 TokenKind::GreaterEqual |
                 TokenKind::Less |
@@ -295,11 +612,29 @@ impl<'src> Parser<'src> {
                 TokenKind::LeftParen => {}
                 // expression end
                 TokenKind::Semicolon |
+                TokenKind::Comma |
                 TokenKind::RightParen |
                 TokenKind::LeftBrace |
+                TokenKind::RightBrace |
+                TokenKind::Because |
                 TokenKind::Eof => {
                     break;
                 }
+                // start of the next statement, the semicolon is reported missing
+                TokenKind::Assert |
+                TokenKind::Break |
+                TokenKind::Class |
+                TokenKind::Continue |
+                TokenKind::For |
+                TokenKind::Fn |
+                TokenKind::If |
+                TokenKind::Label |
+                TokenKind::Let |
+                TokenKind::Print |
+                TokenKind::Return |
+                TokenKind::While => {
+                    break;
+                }
                 _ => {
                     return Err(Error::ExpectedInfixOrPostfixOperator {
                         found: operator,
@@ -312,7 +647,31 @@ impl<'src> Parser<'src> {
                     break;
                 }
 
-                todo!("parse function call");
+                let left_paren_tok = self.lexer.next();
+                let mut arguments = Delimited::default();
+                while !matches!(self.peek_kind(), TokenKind::Eof | TokenKind::RightParen) {
+                    arguments.items.push(self.expr_bp(0)?);
+                    match self.peek_kind() {
+                        TokenKind::RightParen => break,
+                        TokenKind::Comma => {
+                            arguments.delim.push(self.lexer.next());
+                        }
+                        _ => return Err(Error::UnexpectedToken2 {
+                            found: self.lexer.next(),
+                            expected: &[TokenKind::Comma, TokenKind::RightParen],
+                        })
+                    }
+                }
+                let right_paren_tok = self.expect_next(TokenKind::RightParen)?;
+                self.nodes += 1;
+                lhs = Expression::Call(CallExpr {
+                    fun: Box::new(lhs),
+                    left_paren_tok,
+                    arguments,
+                    right_paren_tok,
+                });
+
+                continue;
             }
 
             if let Some((l_bp, r_bp)) = infix_binding_power(operator.kind) {
@@ -323,6 +682,7 @@ impl<'src> Parser<'src> {
                 self.lexer.next(); // throw away the peeked operator token
 
                 let rhs = self.expr_bp(r_bp)?;
+                self.nodes += 1;
                 lhs = Expression::Binary(BinaryExpr {
                     lhs: Box::new(lhs),
                     operator,
@@ -348,15 +708,19 @@ fn prefix_binding_power(kind: TokenKind) -> ((), u8) {
     match kind {
         // unary (higher than factor for infix operators)
         TokenKind::Not |
-        TokenKind::Minus => ((), 15),
+        TokenKind::Minus => ((), 19),
 
         _ => unreachable!(),
     }
 }
 
-fn postfix_binding_power(_kind: TokenKind) -> Option<(u8, ())> {
-    // TODO
-    None
+fn postfix_binding_power(kind: TokenKind) -> Option<(u8, ())> {
+    Some(match kind {
+        // call (higher than unary, `-f(x)` negates the result)
+        TokenKind::LeftParen    => (21, ()),
+
+        _ => return None,
+    })
 }
 
 fn infix_binding_power(kind: TokenKind) -> Option<(u8, u8)> {
@@ -365,25 +729,233 @@ fn infix_binding_power(kind: TokenKind) -> Option<(u8, u8)> {
     Some(match kind {
         // assignment
         TokenKind::Equal        => (2, 1),
+        // range, `0..n + 1` ends at `n + 1`
+        TokenKind::DotDot       => (3, 4),
         // or
-        TokenKind::Or           => (3, 4),
+        TokenKind::Or           => (5, 6),
         // and
-        TokenKind::And          => (5, 6),
+        TokenKind::And          => (7, 8),
         // equality
         TokenKind::EqualEqual |
-        TokenKind::NotEqual     => (7, 8),
+        TokenKind::NotEqual |
+        TokenKind::StrictEqual |
+        TokenKind::StrictNotEqual => (9, 10),
         // comparison
         TokenKind::Less |
         TokenKind::LessEqual |
         TokenKind::Greater |
-        TokenKind::GreaterEqual => (9, 10),
+        TokenKind::GreaterEqual => (11, 12),
+        // unit coalescing, `a ?? b == c` compares the result
+        TokenKind::QuestionQuestion => (14, 13),
         // term
         TokenKind::Minus |
-        TokenKind::Plus         => (11, 12),
+        TokenKind::Plus         => (15, 16),
         // factor
         TokenKind::Div |
-        TokenKind::Mul          => (13, 14),
+        TokenKind::Mul          => (17, 18),
 
         _ => return None,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::{parse, parse_resilient, parse_with, parse_with_trivia, Error, Item, Options, Statement};
+    use crate::lexer::TokenKind;
+    use crate::span::FreeSpan;
+    use std::assert_matches::assert_matches;
+
+    #[test]
+    fn test_symbolic_not() {
+        use crate::compiler::compile;
+        use crate::object::Alloc;
+
+        let options = Options { symbolic_not: true, ..Options::default() };
+        let opcodes = |src: &str| {
+            let alloc = Alloc::new();
+            let chunk = compile(src, parse_with(src, &options).unwrap(), &alloc).unwrap();
+            chunk.opcodes().collect::<Vec<_>>()
+        };
+        assert_eq!(opcodes("print !x and !!y;"), opcodes("print not x and not not y;"));
+        assert_eq!(opcodes("print !x == y;"), opcodes("print not x == y;"));
+
+        assert_matches!(parse("print !x;").err(), Some(Error::ExpectedExpressionStart { .. }));
+    }
+
+    #[test]
+    fn test_lenient_semicolons() {
+        let lenient = Options { lenient_semicolons: true, ..Options::default() };
+
+        assert!(parse_with("{ print 1 }", &lenient).is_ok());
+        assert!(parse_with("{ let a = 1; a = 2 }", &lenient).is_ok());
+        assert!(parse("{ print 1 }").is_err());
+
+        // only the last statement of a block may omit it
+        assert!(parse_with("{ print 1 print 2 }", &lenient).is_err());
+        assert!(parse_with("print 1", &lenient).is_err());
+
+        // the omitted `;` is implied right before the brace
+        let src = "{ print 1 }";
+        let semicolon_tok = match &parse_with(src, &lenient).unwrap()[..] {
+            [Item::Statement(Statement::Block(block))] => match &block.body[..] {
+                [Item::Statement(Statement::Print(print_stmt))] => print_stmt.semicolon_tok,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        assert!(semicolon_tok.is_synthetic());
+        assert_eq!(semicolon_tok.span.range(), 10..10);
+        assert_eq!(semicolon_tok.span.anchor(src).as_str(), "");
+    }
+
+    #[test]
+    fn test_missing_semicolon() {
+        let missing = |src| match parse(src).err() {
+            Some(Error::MissingSemicolon { span }) => Some(span.range()),
+            _ => None,
+        };
+        assert_eq!(missing("print 1 + 2\nprint 3;"), Some(11..11));
+        assert_eq!(missing("{ let a = 1 }"), Some(11..11));
+        assert_eq!(missing("{ a = 1; b }"), Some(10..10));
+        assert_eq!(missing("break"), Some(5..5));
+        assert_eq!(missing("{ a; }"), None);
+        assert!(parse("{ a; }").is_ok());
+    }
+
+    #[test]
+    fn test_call() {
+        use crate::fmt::SourceDebug;
+
+        let src = "print -f(a, b + 1)(c);";
+        let program = parse(src).unwrap();
+        let print_stmt = match &program[0] {
+            Item::Statement(Statement::Print(print_stmt)) => print_stmt,
+            _ => panic!("expected a print statement"),
+        };
+        assert_eq!(format!("{:?}", print_stmt.expr.wrap(src)), "-(Call(Call(f, a, +(b, 1)), c))");
+
+        assert!(parse("f();").is_ok());
+        assert!(parse("f(a,);").is_ok());
+        assert_matches!(parse("f(a b);").err(), Some(Error::ExpectedInfixOrPostfixOperator { .. }));
+        assert_matches!(parse("f(a;").err(), Some(Error::UnexpectedToken2 { .. }));
+    }
+
+    #[test]
+    fn test_number_with_suffix() {
+        assert_matches!(parse("print 123abc;").err(), Some(Error::NumberWithSuffix { .. }));
+        assert_matches!(parse("print 123 abc;").err(), Some(Error::ExpectedInfixOrPostfixOperator { .. }));
+        assert!(parse("print 123 + abc;").is_ok());
+    }
+
+    #[test]
+    fn test_eof_span() {
+        let eof = |src| match parse(src).err() {
+            Some(Error::ExpectedExpressionStart { found }) |
+            Some(Error::UnexpectedToken { found, .. }) => {
+                assert_eq!(found.kind, TokenKind::Eof);
+                Some(found.span.range())
+            }
+            _ => None,
+        };
+        assert_eq!(eof("let x ="), Some(7..7));
+        assert_eq!(eof("let x = // trailing\n  "), Some(22..22));
+        assert_eq!(eof("{ print 1;"), Some(10..10));
+    }
+
+    #[test]
+    fn test_parse_resilient() {
+        let src = "let = 1; print 1 print 2; { let a = ; print a; } print 3;";
+        let (program, errors) = parse_resilient(src);
+        assert_matches!(errors[..], [
+            Error::UnexpectedToken { expected: TokenKind::Identifier, .. },
+            Error::MissingSemicolon { .. },
+            Error::ExpectedExpressionStart { .. },
+        ]);
+        let skipped: Vec<_> = program.iter()
+            .filter_map(|item| match item {
+                Item::Error(error_item) => Some(error_item.span.anchor(src).as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(skipped, ["let = 1;", "print 1"]);
+        assert_eq!(program.len(), 5);
+        // the block still parses, with the bad `let` replaced
+        assert!(matches!(&program[3], Item::Statement(Statement::Block(block))
+            if matches!(block.body[..], [Item::Error(_), Item::Statement(Statement::Print(_))])));
+
+        // nothing is skipped without errors, a stray `}` doesn't stop the parser
+        let (program, errors) = parse_resilient("print 1; print 2;");
+        assert!(errors.is_empty() && program.len() == 2);
+        let (program, errors) = parse_resilient("} print 1;");
+        assert_eq!((program.len(), errors.len()), (2, 1));
+        assert_matches!(parse("let = 1; let = 2;").err(), Some(Error::UnexpectedToken { .. }));
+    }
+
+    #[test]
+    fn test_max_source_len() {
+        let options = Options { max_source_len: 8, ..Options::default() };
+        assert!(parse_with("print 1;", &options).is_ok());
+        assert_matches!(parse_with("print 10;", &options).err(), Some(Error::ProgramTooLarge { len: 9, max: 8 }));
+    }
+
+    #[test]
+    fn test_assert_because() {
+        let src = r#"assert 1 == 1 because "math"; assert true;"#;
+        let program = parse(src).unwrap();
+        let asserts: Vec<_> = program.iter()
+            .map(|item| match item {
+                Item::Statement(Statement::Assert(assert_stmt)) => assert_stmt,
+                _ => panic!("expected an assert statement"),
+            })
+            .collect();
+        let reason = asserts[0].because.as_ref().unwrap().reason;
+        assert_eq!(reason.span.anchor(src).as_str(), r#""math""#);
+        assert!(asserts[1].because.is_none());
+
+        assert!(parse("assert true because;").is_err());
+        assert!(parse("assert true because 1;").is_err());
+    }
+
+    #[test]
+    fn test_loop_labels() {
+        let src = "outer: while true { break outer; }";
+        let program = parse(src).unwrap();
+        let while_stmt = match &program[0] {
+            Item::Statement(Statement::While(while_stmt)) => while_stmt,
+            _ => panic!("expected a while statement"),
+        };
+        assert_eq!(while_stmt.label.unwrap().span.anchor(src).as_str(), "outer:");
+        let target = match &while_stmt.body.body[0] {
+            Item::Statement(Statement::Break(break_stmt)) => break_stmt.label.unwrap(),
+            _ => panic!("expected a break statement"),
+        };
+        assert_eq!(target.token.span.anchor(src).as_str(), "outer");
+
+        assert!(parse("for x in 0..1 { continue; }").is_ok());
+        assert!(parse("outer: print 1;").is_err());
+    }
+
+    #[test]
+    fn test_trivia() {
+        let src = "// note\n// more\nlet a = 1; // trailing\nprint a;\n// last";
+        let (program, trivia) = parse_with_trivia(src).unwrap();
+        let comments = |spans: &[FreeSpan]| -> Vec<&str> {
+            spans.iter().map(|span| span.anchor(src).as_str()).collect()
+        };
+
+        let let_item = match &program[0] {
+            Item::Let(let_item) => let_item,
+            _ => panic!("expected a let item"),
+        };
+        assert_eq!(comments(trivia.leading(let_item.let_tok)), ["// note", "// more"]);
+        let trailing = trivia.trailing(let_item.semicolon_tok).unwrap();
+        assert_eq!(trailing.anchor(src).as_str(), "// trailing");
+
+        let print_stmt = match &program[1] {
+            Item::Statement(Statement::Print(print_stmt)) => print_stmt,
+            _ => panic!("expected a print statement"),
+        };
+        assert!(trivia.leading(print_stmt.print_tok).is_empty());
+        assert!(trivia.trailing(print_stmt.semicolon_tok).is_none());
+    }
+}
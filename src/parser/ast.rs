@@ -8,6 +8,7 @@
 //! [Appendix I]: https://craftinginterpreters.com/appendix-i.html
 
 use crate::lexer::Token;
+use crate::span::FreeSpan;
 
 
 mod fmt;
@@ -23,6 +24,13 @@ pub enum Item {
     Fn(FnItem),
     Let(LetItem),
     Statement(Statement),
+    /// Placeholder for source skipped by [`parse_resilient`](crate::parser::parse_resilient)
+    Error(ErrorItem),
+}
+
+pub struct ErrorItem {
+    /// From the start of the item that failed to parse to the end of the skipped tokens
+    pub span: FreeSpan,
 }
 
 pub struct ClassItem {
@@ -81,6 +89,8 @@ pub enum Statement {
     Print(PrintStmt),
     Return(ReturnStmt),
     While(WhileStmt),
+    Break(BreakStmt),
+    Continue(ContinueStmt),
     Block(Block),
 }
 
@@ -90,6 +100,8 @@ pub struct ExprStmt {
 }
 
 pub struct ForStmt {
+    /// `label:` token, including the colon
+    pub label: Option<Token>,
     pub for_tok: Token,
     pub elem: Identifier,
     pub in_tok: Token,
@@ -112,9 +124,16 @@ pub struct ElseBranch {
 pub struct AssertStmt {
     pub assert_tok: Token,
     pub expr: Expression,
+    pub because: Option<AssertBecause>,
     pub semicolon_tok: Token,
 }
 
+pub struct AssertBecause {
+    pub because_tok: Token,
+    /// String literal token
+    pub reason: Token,
+}
+
 pub struct PrintStmt {
     pub print_tok: Token,
     pub expr: Expression,
@@ -123,16 +142,31 @@ pub struct PrintStmt {
 
 pub struct ReturnStmt {
     pub return_tok: Token,
-    pub expr: Expression,
+    /// `None` for a bare `return;`, which returns `()`
+    pub expr: Option<Expression>,
     pub semicolon_tok: Token,
 }
 
 pub struct WhileStmt {
+    /// `label:` token, including the colon
+    pub label: Option<Token>,
     pub while_tok: Token,
     pub pred: Expression,
     pub body: Block,
 }
 
+pub struct BreakStmt {
+    pub break_tok: Token,
+    pub label: Option<Identifier>,
+    pub semicolon_tok: Token,
+}
+
+pub struct ContinueStmt {
+    pub continue_tok: Token,
+    pub label: Option<Identifier>,
+    pub semicolon_tok: Token,
+}
+
 pub struct Block {
     pub left_brace_tok: Token,
     pub body: Vec<Item>,
@@ -151,6 +185,8 @@ pub enum Expression {
     Field(FieldExpr),
     Group(GroupExpr),
     Call(CallExpr),
+    Dbg(DbgExpr),
+    Format(FormatExpr),
     Primary(PrimaryExpr),
 }
 
@@ -193,11 +229,30 @@ pub struct CallExpr {
     pub right_paren_tok: Token,
 }
 
+/// `dbg(expr)`, evaluates to `expr` after printing it
+pub struct DbgExpr {
+    pub dbg_tok: Token,
+    pub left_paren_tok: Token,
+    pub expr: Box<Expression>,
+    pub right_paren_tok: Token,
+}
+
+/// `format("{} + {}", a, b)`, each `{}` of the template is replaced by the next argument
+pub struct FormatExpr {
+    pub format_tok: Token,
+    pub left_paren_tok: Token,
+    /// String literal token
+    pub template: Token,
+    /// Every argument is preceded by a comma, there is no trailing one
+    pub arguments: Delimited<Token, Expression>,
+    pub right_paren_tok: Token,
+}
+
 // PrimaryExpr can have the following tokens:
 // - Nil
 // - True, False
 // - This, Super
-// - Number, String, Identifier
+// - Number, String, Char, Identifier
 pub struct PrimaryExpr {
     pub token: Token,
 }
@@ -20,6 +20,7 @@ impl SourceDebug for Item {
             Item::Fn(inner) => inner.fmt(source, f),
             Item::Let(inner) => inner.fmt(source, f),
             Item::Statement(inner) => inner.fmt(source, f),
+            Item::Error(_) => f.write_str("Error"),
         }
     }
 }
@@ -64,8 +65,11 @@ impl SourceDebug for FnParam {
 
 impl SourceDebug for LetItem {
     fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // `mut` and `rec` exclude each other
         let mut w = if self.mut_tok.is_some() {
             f.debug_tuple("LetMut")
+        } else if self.rec_tok.is_some() {
+            f.debug_tuple("LetRec")
         } else {
             f.debug_tuple("Let")
         };
@@ -87,6 +91,8 @@ impl SourceDebug for Statement {
             Statement::Print(inner) => inner.fmt(source, f),
             Statement::Return(inner) => inner.fmt(source, f),
             Statement::While(inner) => inner.fmt(source, f),
+            Statement::Break(inner) => inner.fmt(source, f),
+            Statement::Continue(inner) => inner.fmt(source, f),
             Statement::Block(inner) => inner.fmt(source, f),
         }
     }
@@ -100,11 +106,14 @@ impl SourceDebug for ExprStmt {
 
 impl SourceDebug for ForStmt {
     fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("For")
-            .field("elem", &self.elem.wrap(source))
-            .field("iter", &self.iter.wrap(source))
-            .field("body", &self.body.wrap(source))
-            .finish()
+        let mut w = f.debug_struct("For");
+        if let Some(label) = &self.label {
+            w.field("label", &label.span.anchor(source).as_str());
+        }
+        w.field("elem", &self.elem.wrap(source));
+        w.field("iter", &self.iter.wrap(source));
+        w.field("body", &self.body.wrap(source));
+        w.finish()
     }
 }
 
@@ -122,9 +131,12 @@ impl SourceDebug for IfStmt {
 
 impl SourceDebug for AssertStmt {
     fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_tuple("Assert")
-            .field(&self.expr.wrap(source))
-            .finish()
+        let mut w = f.debug_tuple("Assert");
+        w.field(&self.expr.wrap(source));
+        if let Some(because) = &self.because {
+            w.field(&because.reason.span.anchor(source).as_str());
+        }
+        w.finish()
     }
 }
 
@@ -138,18 +150,43 @@ impl SourceDebug for PrintStmt {
 
 impl SourceDebug for ReturnStmt {
     fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_tuple("Return")
-            .field(&self.expr.wrap(source))
-            .finish()
+        let mut w = f.debug_tuple("Return");
+        if let Some(expr) = &self.expr {
+            w.field(&expr.wrap(source));
+        }
+        w.finish()
     }
 }
 
 impl SourceDebug for WhileStmt {
     fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("While")
-            .field("pred", &self.pred.wrap(source))
-            .field("body", &self.body.wrap(source))
-            .finish()
+        let mut w = f.debug_struct("While");
+        if let Some(label) = &self.label {
+            w.field("label", &label.span.anchor(source).as_str());
+        }
+        w.field("pred", &self.pred.wrap(source));
+        w.field("body", &self.body.wrap(source));
+        w.finish()
+    }
+}
+
+impl SourceDebug for BreakStmt {
+    fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut w = f.debug_tuple("Break");
+        if let Some(label) = &self.label {
+            w.field(&label.wrap(source));
+        }
+        w.finish()
+    }
+}
+
+impl SourceDebug for ContinueStmt {
+    fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut w = f.debug_tuple("Continue");
+        if let Some(label) = &self.label {
+            w.field(&label.wrap(source));
+        }
+        w.finish()
     }
 }
 
@@ -166,7 +203,9 @@ impl SourceDebug for Expression {
             Expression::Unary(inner) => inner.fmt(source, f),
             // Expression::Field(inner) => inner.fmt(source, f),
             Expression::Group(inner) => inner.fmt(source, f),
-            // Expression::Call(inner) => inner.fmt(source, f),
+            Expression::Call(inner) => inner.fmt(source, f),
+            Expression::Dbg(inner) => inner.fmt(source, f),
+            Expression::Format(inner) => inner.fmt(source, f),
             Expression::Primary(inner) => inner.fmt(source, f),
             _ => Ok(())
         }
@@ -200,6 +239,36 @@ impl SourceDebug for GroupExpr {
     }
 }
 
+impl SourceDebug for DbgExpr {
+    fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Dbg")
+            .field(&self.expr.wrap(source))
+            .finish()
+    }
+}
+
+impl SourceDebug for CallExpr {
+    fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut w = f.debug_tuple("Call");
+        w.field(&self.fun.wrap(source));
+        for argument in &self.arguments.items {
+            w.field(&argument.wrap(source));
+        }
+        w.finish()
+    }
+}
+
+impl SourceDebug for FormatExpr {
+    fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut w = f.debug_tuple("Format");
+        w.field(&format_args!("{}", self.template.span.anchor(source).as_str()));
+        for argument in &self.arguments.items {
+            w.field(&argument.wrap(source));
+        }
+        w.finish()
+    }
+}
+
 impl SourceDebug for PrimaryExpr {
     fn fmt(&self, source: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str(self.token.span.anchor(source).as_str())
@@ -211,3 +280,31 @@ impl SourceDebug for Identifier {
         f.write_str(self.token.span.anchor(source).as_str())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::fmt::SourceDebug;
+    use crate::parser::parse;
+
+    fn dump(src: &str) -> String {
+        format!("{:?}", parse(src).unwrap().as_slice().wrap(src))
+    }
+
+    #[test]
+    fn test_dbg() {
+        assert_eq!(dump("let x = dbg(1 + 2) * 3;"), "[Let(x, *(Dbg(+(1, 2)), 3))]");
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(dump(r#"print format("{} {}", 1, a + 2);"#), r#"[Print(Format("{} {}", 1, +(a, 2)))]"#);
+    }
+
+    #[test]
+    fn test_let_item() {
+        assert_eq!(dump("let x = 1 + 2;"), "[Let(x, +(1, 2))]");
+        assert_eq!(dump("let y;"), "[Let(y)]");
+        assert_eq!(dump("let mut z = ();"), "[LetMut(z, Unit)]");
+        assert_eq!(dump("let rec f = 1;"), "[LetRec(f, 1)]");
+    }
+}
@@ -15,6 +15,7 @@ impl Spanned for Item {
             Item::Fn(inner) => inner.span(),
             Item::Let(inner) => inner.span(),
             Item::Statement(inner) => inner.span(),
+            Item::Error(inner) => inner.span,
         }
     }
 }
@@ -59,6 +60,8 @@ impl Spanned for Statement {
             Statement::Print(inner) => inner.span(),
             Statement::Return(inner) => inner.span(),
             Statement::While(inner) => inner.span(),
+            Statement::Break(inner) => inner.span(),
+            Statement::Continue(inner) => inner.span(),
             Statement::Block(inner) => inner.span(),
         }
     }
@@ -72,7 +75,7 @@ impl Spanned for ExprStmt {
 
 impl Spanned for ForStmt {
     fn span(&self) -> FreeSpan {
-        join(self.for_tok.span, self.body.span())
+        join(self.label.unwrap_or(self.for_tok).span, self.body.span())
     }
 }
 
@@ -99,6 +102,12 @@ impl Spanned for AssertStmt {
     }
 }
 
+impl Spanned for AssertBecause {
+    fn span(&self) -> FreeSpan {
+        join(self.because_tok.span, self.reason.span)
+    }
+}
+
 impl Spanned for PrintStmt {
     fn span(&self) -> FreeSpan {
         join(self.print_tok.span, self.semicolon_tok.span)
@@ -113,7 +122,19 @@ impl Spanned for ReturnStmt {
 
 impl Spanned for WhileStmt {
     fn span(&self) -> FreeSpan {
-        join(self.while_tok.span, self.body.span())
+        join(self.label.unwrap_or(self.while_tok).span, self.body.span())
+    }
+}
+
+impl Spanned for BreakStmt {
+    fn span(&self) -> FreeSpan {
+        join(self.break_tok.span, self.semicolon_tok.span)
+    }
+}
+
+impl Spanned for ContinueStmt {
+    fn span(&self) -> FreeSpan {
+        join(self.continue_tok.span, self.semicolon_tok.span)
     }
 }
 
@@ -131,6 +152,8 @@ impl Spanned for Expression {
             Expression::Field(inner) => inner.span(),
             Expression::Group(inner) => inner.span(),
             Expression::Call(inner) => inner.span(),
+            Expression::Dbg(inner) => inner.span(),
+            Expression::Format(inner) => inner.span(),
             Expression::Primary(inner) => inner.span(),
         }
     }
@@ -166,6 +189,18 @@ impl Spanned for CallExpr {
     }
 }
 
+impl Spanned for DbgExpr {
+    fn span(&self) -> FreeSpan {
+        join(self.dbg_tok.span, self.right_paren_tok.span)
+    }
+}
+
+impl Spanned for FormatExpr {
+    fn span(&self) -> FreeSpan {
+        join(self.format_tok.span, self.right_paren_tok.span)
+    }
+}
+
 impl Spanned for PrimaryExpr {
     fn span(&self) -> FreeSpan {
         self.token.span
@@ -0,0 +1,106 @@
+use crate::lexer::{Lexer, TokenKind};
+
+
+/// Original locations declared by `@line` directives
+///
+/// Generated code can contain `@line <n>` or `@line <n> "<file>"` to declare that the line after
+/// the directive is line `n` of `file`. Locations before any directive are the physical ones.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    /// Sorted by `start`
+    directives: Vec<Directive>,
+}
+
+#[derive(Debug)]
+struct Directive {
+    /// Offset of the first line the directive applies to
+    start: u32,
+    /// Physical line number of `start`
+    physical_line: u32,
+    line: u32,
+    file: Option<Box<str>>,
+}
+
+/// Location of some source offset, after applying line directives
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Location<'map> {
+    /// `None` for the source itself
+    pub file: Option<&'map str>,
+    /// Line numbered starting from 1
+    pub line: u32,
+}
+
+impl SourceMap {
+    /// Collects the line directives of `source`
+    pub fn new(source: &str) -> SourceMap {
+        let mut lexer = Lexer::new(source);
+        while lexer.next().kind != TokenKind::Eof {}
+        lexer.take_source_map()
+    }
+
+    /// Records the `@line` directive found at `directive` in `source`
+    ///
+    /// Returns `false` if the directive is malformed.
+    pub(crate) fn push_directive(&mut self, source: &str, directive: std::ops::Range<usize>) -> bool {
+        let text = &source[directive.clone()];
+        // `@line <n> "<file>"`, the file name may contain whitespace
+        let line = match text.split_whitespace().nth(1).map(str::parse) {
+            Some(Ok(line)) => line,
+            _ => return false,
+        };
+        let file = text.find('"')
+            .map(|quote| text[quote..].trim_matches('"').into());
+
+        let start = source[directive.end..].find('\n')
+            .map_or(source.len(), |len| directive.end + len + 1);
+        let physical_line = physical_line(source, start);
+        self.directives.push(Directive {
+            start: start as u32,
+            physical_line,
+            line,
+            file,
+        });
+        true
+    }
+
+    /// Returns the original location of `offset` in `source`
+    pub fn locate(&self, source: &str, offset: u32) -> Location<'_> {
+        let index = self.directives.partition_point(|directive| directive.start <= offset);
+        let physical = physical_line(source, offset as usize);
+        match index.checked_sub(1).map(|index| &self.directives[index]) {
+            Some(directive) => Location {
+                file: directive.file.as_deref(),
+                line: directive.line + (physical - directive.physical_line),
+            },
+            None => Location { file: None, line: physical },
+        }
+    }
+}
+
+fn physical_line(source: &str, offset: usize) -> u32 {
+    source.as_bytes()[..offset].iter().filter(|&&b| b == b'\n').count() as u32 + 1
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Location, SourceMap};
+    use crate::parser::{parse, Error};
+
+    #[test]
+    fn test_line_directive() {
+        let source = "print 1;\n@line 40 \"gen.lox\"\nprint 2;\nprint +;";
+        let token = match parse(source) {
+            Err(Error::ExpectedExpressionStart { found }) => found,
+            _ => panic!("expected a parse error"),
+        };
+        let map = SourceMap::new(source);
+        assert_eq!(map.locate(source, token.span.start), Location { file: Some("gen.lox"), line: 41 });
+        assert_eq!(map.locate(source, 0), Location { file: None, line: 1 });
+
+        let source = "@line 7\nprint 1;\n@line  2\t\"other file.lox\"\nprint 2;";
+        let map = SourceMap::new(source);
+        let offset = |needle| source.find(needle).unwrap() as u32;
+        assert_eq!(map.locate(source, offset("print 1")), Location { file: None, line: 7 });
+        assert_eq!(map.locate(source, offset("print 2")), Location { file: Some("other file.lox"), line: 2 });
+    }
+}
@@ -1,3 +1,4 @@
+use crate::source_map::{Location, SourceMap};
 use std::fmt::{self, Debug};
 use std::ops::Range;
 
@@ -43,6 +44,40 @@ impl FreeSpan {
         }
     }
 
+    /// Returns the part of the span on the line it starts on, without the line break
+    pub fn first_line(self, source: &str) -> FreeSpan {
+        let slice = &source[self.range()];
+        let len = slice.find('\n').unwrap_or(slice.len());
+        FreeSpan {
+            start: self.start,
+            end: self.start + len as u32,
+        }
+    }
+
+    /// Returns the part of the span on `line`, `None` if the span is not on that line
+    ///
+    /// Lines are numbered starting from 1 like [`Span::lines`], the line break is not included.
+    pub fn clamp_to_line(self, source: &str, line: u32) -> Option<FreeSpan> {
+        assert!(line > 0, "lines are numbered from 1");
+
+        let mut line_start = 0;
+        for _ in 1..line {
+            line_start += source[line_start..].find('\n')? + 1;
+        }
+        let line_end = source[line_start..].find('\n')
+            .map_or(source.len(), |len| line_start + len);
+
+        let start = Ord::max(self.start as usize, line_start);
+        let end = Ord::min(self.end as usize, line_end);
+        // an empty span is still on the line it's placed on
+        let empty = self.start == self.end;
+        if start < end || (empty && start == end) {
+            Some(FreeSpan::from(start..end))
+        } else {
+            None
+        }
+    }
+
     pub fn join(a: FreeSpan, b: FreeSpan) -> FreeSpan {
         FreeSpan {
             start: Ord::min(a.start, b.start),
@@ -83,6 +118,11 @@ impl<'src> Span<'src> {
         (self.start as usize)..(self.end as usize)
     }
 
+    /// Returns the original location of the start of the span, after the line directives of `map`
+    pub fn location<'map>(&self, map: &'map SourceMap) -> Location<'map> {
+        map.locate(self.source, self.start)
+    }
+
     pub fn as_str(&self) -> &'src str {
         &self.source[self.range()]
     }
@@ -255,6 +295,22 @@ dolor sit",
         assert_eq!(sp.columns(), (1, 1));
     }
 
+    #[test]
+    fn test_truncate_to_line() {
+        let source = "let a = 1 +\n    2;";
+        let sp = FreeSpan::from(8..17); // `1 +\n    2`
+        assert_eq!(sp.first_line(source).anchor(source).as_str(), "1 +");
+
+        let line = |line| sp.clamp_to_line(source, line).map(|sp| sp.anchor(source).as_str());
+        assert_eq!(line(1), Some("1 +"));
+        assert_eq!(line(2), Some("    2"));
+        assert_eq!(line(3), None);
+
+        let sp = FreeSpan::from(0..3); // `let`
+        assert_eq!(sp.first_line(source).anchor(source).as_str(), "let");
+        assert!(sp.clamp_to_line(source, 2).is_none());
+    }
+
     #[cfg_attr(not(feature = "full-unicode"), ignore)]
     #[test]
     fn test_columns_unicode() {
@@ -0,0 +1,30 @@
+//! Opt-in statistics gathered by the front end.
+
+use std::time::Duration;
+
+
+/// Counters and timings collected while parsing and compiling a program.
+///
+/// Filled in by [`crate::parser::parse_with_stats`] and
+/// [`crate::compiler::compile_with_stats`], either of them only touches the fields of its own
+/// pass so the same `Stats` can be passed to both.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    /// Number of tokens produced by the lexer, not counting the final `Eof`
+    pub tokens: usize,
+
+    /// Number of items, statements and expressions in the AST
+    pub ast_nodes: usize,
+
+    /// Number of emitted instructions
+    pub instructions: usize,
+
+    /// Number of unique entries in the constant pool
+    pub constants: usize,
+
+    /// Wall time spent in `parse`
+    pub parse_time: Duration,
+
+    /// Wall time spent in `compile`
+    pub compile_time: Duration,
+}
@@ -1,7 +1,11 @@
+use crate::chunk::Chunk;
 use crate::compiler::compile;
 use crate::fmt::SourceDebug;
 use crate::object::Alloc;
+use crate::opcode::OpCode;
 use crate::parser::parse;
+use crate::span::FreeSpan;
+use crate::value::Value;
 use crate::vm::{RuntimeErrorKind, VmError, VM};
 
 
@@ -44,11 +48,152 @@ fn type_error() {
     );
 }
 
+#[test]
+fn call() {
+    run!(
+        "let n = 1; n(2);",
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), span }) if span.as_str() == "n(2)"
+    );
+    run!(
+        "fn f() { } f();",
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::Unsupported(_), .. }),
+    );
+}
+
+#[test]
+fn assert_because() {
+    run!(r#"assert 1 < 2 because "ordering";"#);
+    run!(
+        r#"assert 2 < 1 because "ordering";"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::AssertionError(Some(reason)), .. })
+            if reason == "ordering"
+    );
+    run!(
+        "assert false;",
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::AssertionError(None), .. }),
+    );
+}
+
+#[test]
+fn assert_conjuncts() {
+    run!(
+        "let a = true; let b = false; assert a and b;",
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::AssertionError(None), span })
+            if span.as_str() == "b"
+    );
+    run!(
+        r#"let a = 1; assert a > 0 and a < 1 and a == 1 because "bounds";"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::AssertionError(Some(reason)), span })
+            if span.as_str() == "a < 1" && reason == "bounds"
+    );
+    run!("assert true and 1 < 2;");
+    // operands only need to be truthy, like when asserting the whole condition
+    run!("let a = 1; assert a and true;");
+    run!(
+        "let a = 1; assert a and false;",
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::AssertionError(None), span })
+            if span.as_str() == "false"
+    );
+}
+
+#[test]
+fn debug_assert() {
+    use crate::compiler::{compile_with, Options};
+
+    let alloc = Alloc::new();
+    let src = "{ let a = 1; let b = true; assert b and a > 2; }";
+    let options = Options { debug_asserts: true, ..Options::default() };
+    let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+    let res = VM::new(&chunk, src, &alloc).run();
+    std::assert_matches::assert_matches!(
+        res,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::DebugAssertionError { depth: 2, top }, .. })
+            if top == ["true", "1.0"]
+    );
+}
+
+#[test]
+fn catch_asserts() {
+    use crate::compiler::{compile_with, Options};
+
+    let alloc = Alloc::new();
+    let src = r#"let mut n = 0; assert n == 1 because "first"; n = n + 1; assert false; assert n == 1;"#;
+    let options = Options { catch_asserts: true, ..Options::default() };
+    let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+    let (_, caught) = VM::new(&chunk, src, &alloc).run_catching().unwrap();
+    std::assert_matches::assert_matches!(&caught[..], [
+        VmError::RuntimeError { kind: RuntimeErrorKind::AssertionError(Some(reason)), .. },
+        VmError::RuntimeError { kind: RuntimeErrorKind::AssertionError(None), span },
+    ] if reason == "first" && span.as_str() == "assert false;");
+
+    // type errors still stop execution
+    let src = "assert 1;";
+    let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+    std::assert_matches::assert_matches!(VM::new(&chunk, src, &alloc).run_catching().err(), Some(_));
+}
+
+#[test]
+fn resolved_globals() {
+    use crate::compiler::{compile_with, Options};
+
+    let alloc = Alloc::new();
+    let options = Options { resolve_globals: true, ..Options::default() };
+    let src = "let mut a = 1; let b = a + 1; a = b * 3; assert a == 6; let a = 0; assert a == 0;";
+    let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+    std::assert_matches::assert_matches!(VM::new(&chunk, src, &alloc).run(), Ok(v) if v.is_unit());
+
+    // resolved but read before it's defined
+    let src = "print later; let later = 1;";
+    let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+    std::assert_matches::assert_matches!(
+        VM::new(&chunk, src, &alloc).run(),
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::UndefinedGlobalVariable(name), .. })
+            if name == "later"
+    );
+}
+
+#[test]
+fn swap() {
+    // no syntax needs operands reordered yet, build the chunk by hand
+    let alloc = Alloc::new();
+    let span = FreeSpan { start: 0, end: 0 };
+    let mut chunk = Chunk::default();
+    chunk.emit_constant(Value::new_float(1.0), span);
+    chunk.emit_constant(Value::new_float(3.0), span);
+    chunk.emit(OpCode::Swap, span);
+    chunk.emit(OpCode::Subtract, span);
+    chunk.emit(OpCode::Return, span);
+
+    let res = VM::new(&chunk, "", &alloc).run();
+    std::assert_matches::assert_matches!(res, Ok(v) if v.to_float() == Some(2.0));
+}
+
+#[test]
+fn unit_coalesce() {
+    run!("let a = (); assert a ?? 1 == 1; let b = false; assert b ?? 1 == false;");
+    // right associative, the right hand side is only evaluated for `()`
+    run!("let a = (); let b = (); assert a ?? b ?? 2 == 2; let c = 3; assert c ?? 1 + true == 3;");
+}
+
 #[test]
 fn weird_expr() {
     run!("assert not (5 - 4 > 3 * 2 == not ());");
 }
 
+#[test]
+fn strict_equality() {
+    // there is a single Number type, `1` and `1.0` are the same value
+    run!("assert 1 === 1.0; assert 1 == 1.0;");
+    run!("let a = 0.1; let b = 0.2; assert a + b == 0.3; assert a + b /== 0.3;");
+    run!(r#"assert 1 /== "1"; assert () === (); assert "a" + "b" === "ab";"#);
+}
+
+#[test]
+fn float_equality() {
+    run!("assert 0 == -0; assert 0 === -0;");
+    run!("let nan = 0 / 0; assert nan /= nan; assert not (nan == nan); assert nan /== nan;");
+}
+
 #[test]
 fn strings_ops() {
     run!(r#"assert "string" == "string";"#);
@@ -56,6 +201,63 @@ fn strings_ops() {
     run!(r#"assert "foo" + "bar" == "foobar";"#);
 }
 
+#[test]
+fn add_types() {
+    run!(r#"assert "a" + "b" == "ab";"#);
+    run!("assert 1 + 2 == 3;");
+    // the error points at the `+`
+    run!(
+        r#"let a = 1; print a + "b";"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), span })
+            if span.as_str() == "+"
+    );
+    // and so do the other arithmetic and comparison errors
+    run!(
+        r#"let a = 1; print a - "b";"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), span })
+            if span.as_str() == "-"
+    );
+    run!(
+        r#"let a = 1; print a <= "b";"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), span })
+            if span.as_str() == "<="
+    );
+    run!(
+        r#"print "a" + ();"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), .. })
+    );
+}
+
+#[test]
+fn chars() {
+    run!(r#"assert 'a' == "a"; assert 'a' + 'b' == "ab"; assert '\'' * 2 == "''";"#);
+}
+
+#[test]
+fn format() {
+    run!(r#"let a = 1; assert format("{} + {} = {}", a, 2, a + 2) == "1.0 + 2.0 = 3.0";"#);
+    run!(r#"assert format("{}{}!", "hi", true) == "hitrue!"; assert format("") == "";"#);
+}
+
+#[test]
+fn string_repeat() {
+    run!(r#"assert "ab" * 3 == "ababab";"#);
+    run!(r#"let n = 2; assert "ab" * n == "abab"; assert "ab" * 0 == "";"#);
+    run!(
+        r#""ab" * -1;"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), .. }),
+    );
+    run!(
+        r#"let n = 1.5; "ab" * n;"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), .. }),
+    );
+    // the count is folded, the repeat isn't and fails at runtime
+    run!(
+        r#"print "ab" * (1000000000 * 1000000000);"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::StringTooLong, .. }),
+    );
+}
+
 #[test]
 fn print() {
     run!(r#"
@@ -64,6 +266,174 @@ fn print() {
     "#);
 }
 
+#[test]
+fn dbg() {
+    run!(r#"
+        let x = dbg(1 + 2);
+        assert x == 3;
+        print dbg(x) * dbg(2);
+    "#);
+    run!("dbg(1 + 2) * 2;", Ok(v) if v.is_unit());
+
+    let alloc = Alloc::new();
+    let src = "let x = dbg(1 + 2);";
+    let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+    let mut diagnostics = Vec::new();
+    VM::new(&chunk, src, &alloc).with_diagnostics(&mut diagnostics).run().unwrap();
+    assert_eq!(String::from_utf8(diagnostics).unwrap(), "[line 1] 1 + 2 = 3.0\n");
+}
+
+#[test]
+fn line_directive() {
+    use crate::compiler::compile_source_mapped;
+    use crate::source_map::{Location, SourceMap};
+
+    let alloc = Alloc::new();
+    let src = "dbg(1);\n@line 40 \"gen.lox\"\ndbg(2);\nprint 1 + true;";
+    let mut map = SourceMap::default();
+    let chunk = compile_source_mapped(src, &alloc, &mut map).unwrap();
+    let mut diagnostics = Vec::new();
+    let error = VM::new(&chunk, src, &alloc)
+        .with_diagnostics(&mut diagnostics)
+        .with_source_map(&map)
+        .run()
+        .err()
+        .unwrap();
+    assert_eq!(error.location(&map), Some(Location { file: Some("gen.lox"), line: 41 }));
+    assert_eq!(String::from_utf8(diagnostics).unwrap(), "[line 1] 1 = 1.0\n[gen.lox line 40] 2 = 2.0\n");
+}
+
+#[test]
+fn undefined_global() {
+    run!(
+        "print 1 + foo;",
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::UndefinedGlobalVariable(_), span })
+            if span.as_str() == "foo"
+    );
+    run!(
+        "bar = 1;",
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::UndefinedGlobalVariable(_), span })
+            if span.as_str() == "bar"
+    );
+}
+
+#[test]
+fn attached_source() {
+    use crate::compiler::{compile_with, Options};
+
+    let alloc = Alloc::new();
+    let chunk = {
+        let src = String::from("let a = 1;\nprint a + true;");
+        let options = Options { attach_source: true, ..Options::default() };
+        let (chunk, _) = compile_with(&src, parse(&src).unwrap(), &alloc, &options).unwrap();
+        chunk
+    };
+    let listing = chunk.disassemble_attached().unwrap();
+    assert!(listing.contains("print a + true;"));
+
+    let res = VM::with_attached_source(&chunk, &alloc).unwrap().run();
+    std::assert_matches::assert_matches!(
+        res,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), span })
+            if span.as_str() == "+" && span.lines() == (2, 2)
+    );
+    assert!(VM::with_attached_source(&compile("", parse("").unwrap(), &alloc).unwrap(), &alloc).is_none());
+}
+
+#[test]
+fn strip_debug() {
+    let alloc = Alloc::new();
+    let src = "{ let a = 1; print a; }\nprint 1 + true;";
+    let mut chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+    let code_len = chunk.code().len();
+    chunk.strip_debug();
+    assert!(chunk.is_stripped());
+    assert!(chunk.spans().is_empty() && chunk.locals_debug_info().is_empty());
+    assert_eq!(chunk.code().len(), code_len);
+    assert_eq!(chunk.instruction_count(), chunk.opcodes().count());
+    assert!(chunk.steps().all(|(_, _, span)| span.range() == (0..0)));
+    assert!(chunk.disassemble(src).contains("Print"));
+
+    // still runs, the error has no location anymore
+    let res = VM::new(&chunk, src, &alloc).run();
+    std::assert_matches::assert_matches!(
+        res,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), span }) if span.as_str().is_empty()
+    );
+}
+
+#[test]
+fn coverage() {
+    use crate::compiler::{compile_with, Options};
+    use crate::object::function::Function as ObjFunction;
+
+    let alloc = Alloc::new();
+    let options = Options { coverage: true, ..Options::default() };
+    let src = "let x = 1;\nif x == 1 { print 1; } else { print 2; }\nfor i in 0..3 { print i; }";
+    let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+    let blocks: Vec<_> = chunk.coverage_map().iter()
+        .map(|span| span.anchor(src).as_str())
+        .collect();
+    assert_eq!(blocks, [src, "{ print 1; }", "{ print 2; }", "for i in 0..3 { print i; }", "{ print i; }"]);
+    let ids: Vec<_> = chunk.opcodes()
+        .filter_map(|opcode| match opcode {
+            OpCode::Hit { id } => Some(id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(ids, [0, 1, 2, 3, 4]);
+
+    let mut hits = Vec::new();
+    let res = VM::new(&chunk, src, &alloc).with_output(std::io::sink()).with_coverage(&mut hits).run();
+    assert!(res.is_ok());
+    assert_eq!(hits, [1, 1, 0, 1, 3]);
+
+    // code after a `break` only runs if the loop goes on
+    let src = "let x = 1; while x == 1 { if x == 1 { break; } print 0; }";
+    let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+    let blocks: Vec<_> = chunk.coverage_map().iter()
+        .map(|span| span.anchor(src).as_str())
+        .collect();
+    assert_eq!(blocks[2..], ["{ break; }", "print 0;"]);
+    let mut hits = Vec::new();
+    let res = VM::new(&chunk, src, &alloc).with_output(std::io::sink()).with_coverage(&mut hits).run();
+    assert!(res.is_ok());
+    assert_eq!(hits, [1, 1, 1, 0]);
+
+    // function bodies are counted in the chunk of the function
+    let src = "fn f() { print 1; }";
+    let (chunk, _) = compile_with(src, parse(src).unwrap(), &alloc, &options).unwrap();
+    let body = chunk.constants()
+        .find_map(|constant| constant.downcast::<ObjFunction>())
+        .unwrap();
+    let blocks: Vec<_> = body.chunk().coverage_map().iter()
+        .map(|span| span.anchor(src).as_str())
+        .collect();
+    assert_eq!(blocks, ["{ print 1; }"]);
+    std::assert_matches::assert_matches!(body.chunk().opcodes().next(), Some(OpCode::Hit { id: 0 }));
+
+    // opt-in only
+    let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+    assert!(chunk.coverage_map().is_empty());
+    assert!(!chunk.opcodes().any(|opcode| matches!(opcode, OpCode::Hit { .. })));
+}
+
+#[test]
+fn buffered_output() {
+    let alloc = Alloc::new();
+    let src = "for i in 0..100 { print i; } print true;";
+    let chunk = compile(src, parse(src).unwrap(), &alloc).unwrap();
+    assert_eq!(chunk.opcodes().last(), Some(OpCode::Flush));
+
+    let mut output = Vec::new();
+    let res = VM::new(&chunk, src, &alloc).with_buffered_output(&mut output).run();
+    assert!(res.is_ok());
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 101);
+    assert_eq!((lines[0], lines[99], lines[100]), ("0.0", "99.0", "true"));
+}
+
 #[test]
 fn global() {
     run!("
@@ -86,6 +456,159 @@ fn locals() {
     }");
 }
 
+#[test]
+fn local_slots() {
+    run!("{
+        let a = 1;
+        let b = 2;
+        assert a == 1;
+        assert b == 2;
+    }");
+}
+
+#[test]
+fn for_range() {
+    run!("
+        let mut sum = 0;
+        for x in 0..4 {
+            sum = sum + x;
+        }
+        assert sum == 6;
+        for x in 3..1 {
+            assert false;
+        }
+    ");
+    run!("{
+        let n = 2;
+        let mut squares = 0;
+        for i in 1..n + 1 {
+            let square = i * i;
+            squares = squares + square;
+        }
+        assert squares == 5;
+    }");
+}
+
+#[test]
+fn for_scope() {
+    // the hidden counter and end are popped once at exit, `after` takes their slot
+    run!("{
+        let before = 1;
+        for i in 0..5 {
+            let x = i;
+            if x == 1 {
+                continue;
+            }
+            if x == 3 {
+                break;
+            }
+        }
+        let after = 2;
+        assert before == 1;
+        assert after == 2;
+
+        let r = 0..3;
+        for i in r {
+            let x = i;
+            if x == 0 {
+                continue;
+            }
+        }
+        let last = 3;
+        assert last == 3;
+    }");
+}
+
+#[test]
+fn range_object() {
+    run!("
+        let r = 0..3;
+        let mut count = 0;
+        for x in r {
+            assert x == count;
+            count = count + 1;
+        }
+        assert count == 3;
+        for x in 3..0 {
+            assert false;
+        }
+        let empty = 2..2;
+        for x in empty {
+            assert false;
+        }
+    ");
+    run!(
+        "for x in 5 { }",
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), .. }),
+    );
+    run!(
+        r#"let r = "a"..1;"#,
+        Err(VmError::RuntimeError { kind: RuntimeErrorKind::TypeError(_), .. }),
+    );
+}
+
+#[test]
+fn break_continue() {
+    run!("{
+        let before = 7;
+        let mut count = 0;
+        outer: while true {
+            let mut i = 0;
+            while true {
+                let x = i;
+                i = i + 1;
+                count = count + x;
+                if i == 3 {
+                    break outer;
+                }
+            }
+        }
+        assert count == 3;
+        assert before == 7;
+    }");
+    run!("{
+        let mut sum = 0;
+        for i in 0..5 {
+            let skip = i == 2;
+            if skip {
+                continue;
+            }
+            sum = sum + i;
+        }
+        assert sum == 8;
+
+        let mut pairs = 0;
+        outer: for i in 0..3 {
+            for j in 0..3 {
+                if j > i {
+                    continue outer;
+                }
+                pairs = pairs + 1;
+            }
+        }
+        assert pairs == 6;
+
+        let mut last = ();
+        let r = 0..10;
+        for x in r {
+            if x == 4 {
+                break;
+            }
+            last = x;
+        }
+        assert last == 3;
+
+        let mut n = 0;
+        while n < 10 {
+            n = n + 1;
+            if n < 10 {
+                continue;
+            }
+            assert n == 10;
+        }
+    }");
+}
+
 #[test]
 fn ifs() {
     run!("
@@ -124,6 +647,9 @@ fn ifelse() {
 fn andor() {
     run!("assert true and true;");
     run!("assert false or true;");
+    run!("assert (true and 1 and 2) == 2;");
+    run!("assert (1 and false and 2) == false;");
+    run!("assert (() or false or 3) == 3;");
 }
 
 #[test]
@@ -137,6 +663,47 @@ fn whileloop() {
     }");
 }
 
+#[test]
+fn stats() {
+    use crate::compiler::compile_with_stats;
+    use crate::parser::parse_with_stats;
+    use crate::stats::Stats;
+
+    let alloc = Alloc::new();
+    let src = "let a = 1 + 2; print a;";
+    let mut stats = Stats::default();
+    let ast = parse_with_stats(src, &mut stats).unwrap();
+    compile_with_stats(src, ast, &alloc, &mut stats).unwrap();
+
+    assert_eq!(stats.tokens, 10);
+    // Let(a, +(1, 2)), Statement(Print(a))
+    assert_eq!(stats.ast_nodes, 7);
+    // Constant, Constant, Add, DefGlobal, GetGlobal, Print, Flush
+    assert_eq!(stats.instructions, 7);
+    // 1, 2, "a"
+    assert_eq!(stats.constants, 3);
+}
+
+#[test]
+fn long_jump() {
+    init();
+
+    let filler = "1;".repeat(20000);
+    let sources = [
+        format!("let hit = false; if true {{ hit = true; }} else {{ {} }} assert hit;", filler),
+        format!("let hit = false; if false {{ {} }} else {{ hit = true; }} assert hit;", filler),
+    ];
+
+    for src in &sources {
+        let alloc = Alloc::new();
+        let ast = parse(src).unwrap();
+        let chunk = compile(src, ast, &alloc).unwrap();
+        assert!(chunk.opcodes().any(|op| matches!(op, OpCode::JumpLong { .. } | OpCode::JumpIfFalseLong { .. })));
+        let res = VM::new(&chunk, src, &alloc).run();
+        std::assert_matches::assert_matches!(res, Ok(v) if v.is_unit());
+    }
+}
+
 #[ignore = "not yet implemented"]
 #[test]
 fn function() {
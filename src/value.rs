@@ -1,4 +1,7 @@
-use crate::object::{Object, ObjectRef, ObjectRefAny};
+use crate::object::range::Range as ObjRange;
+use crate::object::string::String as ObjString;
+use crate::object::{ObjKind, Object, ObjectRef, ObjectRefAny};
+use std::cmp::Ordering;
 use std::fmt::{self, Debug};
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
@@ -113,6 +116,11 @@ impl<'alloc> Value<'alloc> {
         }
     }
 
+    /// Returns the type of the object, `None` for inline values
+    pub fn obj_kind(&self) -> Option<ObjKind> {
+        self.to_object().map(|o| o.kind())
+    }
+
     pub fn downcast<O: Object>(self) -> Option<ObjectRef<'alloc, O>> {
         self.to_object()
             .and_then(ObjectRefAny::downcast)
@@ -135,6 +143,12 @@ impl<'alloc> Debug for Value<'alloc> {
     }
 }
 
+/// Equality of the `==` operator
+///
+/// Numbers follow IEEE comparison with an `f64::EPSILON` tolerance, `0.0 == -0.0` but
+/// `NaN /= NaN`. That makes the `Eq` impl not reflexive and different to the bitwise `Hash` for
+/// Numbers, Values which may be Numbers are not suitable map keys. The constant pool compares
+/// Numbers bitwise instead.
 impl<'alloc> PartialEq for Value<'alloc> {
     fn eq(&self, other: &Self) -> bool {
         // TODO in the future we might want to allow objects to implement their own equality with
@@ -142,7 +156,8 @@ impl<'alloc> PartialEq for Value<'alloc> {
 
         // first try comparing floats
         if let (Some(lhs), Some(rhs)) = (self.to_float(), other.to_float()) {
-            return (lhs - rhs).abs() <= f64::EPSILON;
+            // infinities are only exactly equal, their difference is NaN
+            return lhs == rhs || (lhs - rhs).abs() <= f64::EPSILON;
         }
 
         // if objects are not floats we can fast-path out by using bitrepr. if bitreprs are equal,
@@ -166,6 +181,59 @@ impl<'alloc> PartialEq for Value<'alloc> {
 
 impl<'alloc> Eq for Value<'alloc> {}
 
+impl<'alloc> Value<'alloc> {
+    /// Equality without the float tolerance of `==`, Numbers are compared exactly
+    ///
+    /// Values of different types are never equal, like with `==`. There is no integer type to
+    /// tell apart from floats, `1 === 1.0` is `true`.
+    pub fn strict_eq(&self, other: &Self) -> bool {
+        if let (Some(lhs), Some(rhs)) = (self.to_float(), other.to_float()) {
+            return lhs == rhs;
+        }
+        self == other
+    }
+}
+
+impl<'alloc> Value<'alloc> {
+    /// Total order for sorting
+    ///
+    /// Values of different types are ordered by type: Unit, Bool, Number, String, Range and then
+    /// Function. `false` is before `true`, Numbers are ordered by value with every `NaN` after all
+    /// the other Numbers and `0.0` equal to `-0.0`, Strings are ordered lexicographically by bytes
+    /// and Ranges by start and then end. Functions are all equal.
+    pub fn cmp_ordering(&self, other: &Self) -> Ordering {
+        fn rank(value: &Value) -> u8 {
+            match value.obj_kind() {
+                None if value.is_unit() => 0,
+                None if value.to_bool().is_some() => 1,
+                None => 2,
+                Some(ObjKind::String) => 3,
+                Some(ObjKind::Range) => 4,
+                Some(ObjKind::Function) => 5,
+            }
+        }
+
+        fn cmp_floats(lhs: f64, rhs: f64) -> Ordering {
+            lhs.partial_cmp(&rhs)
+                .unwrap_or_else(|| lhs.is_nan().cmp(&rhs.is_nan()))
+        }
+
+        if let (Some(lhs), Some(rhs)) = (self.to_float(), other.to_float()) {
+            return cmp_floats(lhs, rhs);
+        }
+        if let (Some(lhs), Some(rhs)) = (self.to_bool(), other.to_bool()) {
+            return lhs.cmp(&rhs);
+        }
+        if let (Some(lhs), Some(rhs)) = (self.downcast::<ObjString>(), other.downcast::<ObjString>()) {
+            return lhs.as_str().cmp(rhs.as_str());
+        }
+        if let (Some(lhs), Some(rhs)) = (self.downcast::<ObjRange>(), other.downcast::<ObjRange>()) {
+            return cmp_floats(lhs.start, rhs.start).then(cmp_floats(lhs.end, rhs.end));
+        }
+        rank(self).cmp(&rank(other))
+    }
+}
+
 impl<'alloc> Hash for Value<'alloc> {
     fn hash<H>(&self, state: &mut H)
     where
@@ -180,3 +248,87 @@ impl<'alloc> Hash for Value<'alloc> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Value;
+    use crate::object::range::Range as ObjRange;
+    use crate::object::string::String as ObjString;
+    use crate::object::{Alloc, ObjKind};
+    use std::mem::size_of;
+
+    #[test]
+    fn test_cmp_ordering() {
+        let alloc = Alloc::new();
+        let string = |s| Value::new_object(ObjString::new(s, &alloc));
+        let float = Value::new_float;
+        let mut values = vec![
+            string("b"), float(f64::NAN), float(2.0), Value::new_bool(true), string("a"),
+            float(f64::NEG_INFINITY), Value::new_unit(), float(f64::INFINITY), float(-1.5),
+            Value::new_bool(false), string("ab"),
+        ];
+        values.sort_by(Value::cmp_ordering);
+        let sorted: Vec<_> = values.iter().map(|value| format!("{:?}", value)).collect();
+        assert_eq!(sorted, [
+            "()", "false", "true", "-inf", "-1.5", "2.0", "inf", "NaN", r#""a""#, r#""ab""#, r#""b""#,
+        ]);
+
+        assert!(float(f64::NAN).cmp_ordering(&float(-f64::NAN)).is_eq());
+        assert!(float(0.0).cmp_ordering(&float(-0.0)).is_eq());
+        let range = |start, end| Value::new_object(ObjRange::new(start, end, 1.0, &alloc));
+        assert!(range(0.0, 2.0).cmp_ordering(&range(0.0, 3.0)).is_lt());
+        assert!(string("z").cmp_ordering(&range(0.0, 1.0)).is_lt());
+    }
+
+    #[test]
+    fn test_float_equality() {
+        let float = Value::new_float;
+        assert!(float(0.0) == float(-0.0));
+        assert!(float(0.0).strict_eq(&float(-0.0)));
+        assert!(float(f64::NAN) != float(f64::NAN));
+        assert!(!float(f64::NAN).strict_eq(&float(f64::NAN)));
+        assert!(float(f64::INFINITY) == float(f64::INFINITY));
+        assert!(float(f64::INFINITY) != float(f64::NEG_INFINITY));
+    }
+
+    #[test]
+    fn test_nan_boxing() {
+        assert_eq!(size_of::<Value>(), 8);
+
+        let unit = Value::new_unit();
+        assert!(unit.is_unit() && unit.is_falsy());
+        assert_eq!(unit.to_bool(), None);
+        assert_eq!(unit.to_float(), None);
+
+        for b in [true, false] {
+            let value = Value::new_bool(b);
+            assert_eq!(value.to_bool(), Some(b));
+            assert_eq!(value.is_falsy(), !b);
+            assert_eq!(value.to_float(), None);
+            assert!(!value.is_unit());
+        }
+
+        for n in [0.0, -0.0, 1.5, f64::INFINITY, f64::NEG_INFINITY, f64::MIN_POSITIVE] {
+            let value = Value::new_float(n);
+            assert_eq!(value.to_float().map(f64::to_bits), Some(n.to_bits()));
+            assert!(value.to_bool().is_none() && value.to_object().is_none());
+        }
+        // NaNs produced by arithmetic stay floats
+        assert!(Value::new_float(f64::NAN).to_float().unwrap().is_nan());
+        assert!(Value::new_float(-f64::NAN).to_float().unwrap().is_nan());
+
+        let alloc = Alloc::new();
+        let value = Value::new_object(ObjString::new("boxed", &alloc));
+        assert_eq!(value.downcast::<ObjString>().unwrap().as_str(), "boxed");
+        assert!(value.to_float().is_none() && value.to_bool().is_none() && !value.is_falsy());
+    }
+
+    #[test]
+    fn test_obj_kind() {
+        let alloc = Alloc::new();
+        assert_eq!(Value::new_object(ObjString::new("s", &alloc)).obj_kind(), Some(ObjKind::String));
+        assert_eq!(Value::new_object(ObjRange::new(0.0, 1.0, 1.0, &alloc)).obj_kind(), Some(ObjKind::Range));
+        assert_eq!(Value::new_float(1.0).obj_kind(), None);
+        assert_eq!(Value::new_unit().obj_kind(), None);
+    }
+}
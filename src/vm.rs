@@ -1,11 +1,15 @@
 use crate::chunk::{Chunk, ConstKey};
-use crate::object::string::String as ObjString;
+use crate::object::function::Function as ObjFunction;
+use crate::object::range::Range as ObjRange;
+use crate::object::string::{RepeatError, String as ObjString};
 use crate::object::{Alloc, ObjectRef, Trace};
 use crate::opcode::OpCode;
+use crate::source_map::{Location, SourceMap};
 use crate::span::Span;
 use crate::value::Value;
 use fxhash::FxHashMap as HashMap;
 use log::{debug, trace};
+use std::io::{self, BufWriter, Write};
 
 
 pub struct VM<'code, 'src, 'alloc> {
@@ -15,6 +19,18 @@ pub struct VM<'code, 'src, 'alloc> {
     ip: &'code [u8],
     stack: Vec<Value<'alloc>>,
     globals: HashMap<ObjectRef<'alloc, ObjString>, Value<'alloc>>,
+    /// Globals resolved at compile time, `None` until defined
+    global_slots: Vec<Option<Value<'alloc>>>,
+    /// Failed assertions of a chunk which [catches them](Chunk::catch_asserts)
+    caught: Vec<VmError<'src>>,
+    /// Where `print` writes to, stdout by default
+    output: Box<dyn Write + 'code>,
+    /// Where `dbg` writes to, stderr by default
+    diagnostics: Box<dyn Write + 'code>,
+    /// Line directives `dbg` reports the lines with, see [`with_source_map`](VM::with_source_map)
+    source_map: Option<&'code SourceMap>,
+    /// How often each coverage block has been entered, see [`with_coverage`](VM::with_coverage)
+    coverage: Option<&'code mut Vec<u32>>,
 }
 
 #[derive(Debug)]
@@ -24,6 +40,18 @@ pub enum VmError<'src> {
         span: Span<'src>,
         kind: RuntimeErrorKind,
     },
+    /// Writing to the output or the diagnostics failed
+    Output(io::Error),
+}
+
+impl<'src> VmError<'src> {
+    /// Where a runtime error happened after the line directives of `map`, `None` for other errors
+    pub fn location<'map>(&self, map: &'map SourceMap) -> Option<Location<'map>> {
+        match self {
+            VmError::RuntimeError { span, .. } => Some(span.location(map)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -31,14 +59,30 @@ pub enum CodeError {
     UnexpectedEndOfCode,
     InvalidConstantKey(ConstKey),
     InvalidStackSlot(u16),
+    InvalidGlobalSlot(u16),
     PopEmptyStack,
 }
 
+/// How many values a failed `DebugAssert` reports
+pub const DEBUG_ASSERT_VALUES: usize = 4;
+
 #[derive(Debug)]
 pub enum RuntimeErrorKind {
-    AssertionError,
+    /// Failed assertion with the `because` reason if it had one
+    AssertionError(Option<String>),
+    /// Failed `DebugAssert`, with the stack depth and up to [`DEBUG_ASSERT_VALUES`] values from the
+    /// top of the stack, topmost first
+    DebugAssertionError {
+        depth: usize,
+        top: Vec<String>,
+    },
     TypeError(&'static str),
+    IndexOutOfBounds,
     UndefinedGlobalVariable(String),
+    /// String would grow past [`MAX_LEN`](crate::object::string::MAX_LEN)
+    StringTooLong,
+    /// Valid code the VM can't run yet
+    Unsupported(&'static str),
 }
 
 impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
@@ -50,9 +94,53 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
             ip: chunk.code(),
             stack: Vec::default(),
             globals: HashMap::default(),
+            global_slots: vec![None; chunk.global_names().len()],
+            caught: Vec::default(),
+            output: Box::new(io::stdout()),
+            diagnostics: Box::new(io::stderr()),
+            source_map: None,
+            coverage: None,
         }
     }
 
+    /// Makes `print` write to `output` instead of stdout
+    pub fn with_output(mut self, output: impl Write + 'code) -> VM<'code, 'src, 'alloc> {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Same as [`with_output`](VM::with_output) but batches the writes, they reach `output` when
+    /// the buffer is full or on `Flush`
+    pub fn with_buffered_output(self, output: impl Write + 'code) -> VM<'code, 'src, 'alloc> {
+        self.with_output(BufWriter::new(output))
+    }
+
+    /// Makes `dbg` write to `diagnostics` instead of stderr
+    pub fn with_diagnostics(mut self, diagnostics: impl Write + 'code) -> VM<'code, 'src, 'alloc> {
+        self.diagnostics = Box::new(diagnostics);
+        self
+    }
+
+    /// Makes `dbg` report the lines and files declared by the line directives of `map`, it has to
+    /// come from the source of the chunk
+    pub fn with_source_map(mut self, map: &'code SourceMap) -> VM<'code, 'src, 'alloc> {
+        self.source_map = Some(map);
+        self
+    }
+
+    /// Counts the `Hit`s of each block of the [coverage map](Chunk::coverage_map) into `hits`,
+    /// which is resized to one counter per block
+    pub fn with_coverage(mut self, hits: &'code mut Vec<u32>) -> VM<'code, 'src, 'alloc> {
+        hits.resize(self.chunk.coverage_map().len(), 0);
+        self.coverage = Some(hits);
+        self
+    }
+
+    /// Same as [`new`](VM::new) with the source attached to `chunk`, `None` if there is none
+    pub fn with_attached_source(chunk: &'code Chunk<'alloc>, alloc: &'alloc Alloc) -> Option<VM<'code, 'code, 'alloc>> {
+        Some(VM::new(chunk, chunk.source()?, alloc))
+    }
+
     fn pop(&mut self) -> Result<Value<'alloc>, VmError<'src>> {
         self.stack.pop()
             .ok_or(VmError::CompileError(CodeError::PopEmptyStack))
@@ -82,22 +170,27 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
             span_idx += 1;
         };
 
-        self.chunk.spans()
-            .get(span_idx)
-            .expect("missing span information")
-            .anchor(self.source)
+        self.chunk.span_at(span_idx).anchor(self.source)
     }
 
     fn gc(&mut self) {
         self.stack.iter().for_each(Trace::mark);
         self.globals.iter().for_each(|(key, val)| { key.mark(); val.mark() });
+        self.global_slots.iter().flatten().for_each(Trace::mark);
         self.chunk.constants().for_each(Trace::mark);
 
         // SAFETY we've marked all the roots
         unsafe { self.alloc.sweep(); }
     }
 
-    pub fn run(mut self) -> Result<Value<'alloc>, VmError<'src>> {
+    /// Runs the chunk to completion, assertion failures caught by the chunk are discarded
+    pub fn run(self) -> Result<Value<'alloc>, VmError<'src>> {
+        self.run_catching().map(|(value, _)| value)
+    }
+
+    /// Same as [`run`](VM::run) but also returns the assertion failures, in the order they
+    /// happened, if the chunk [catches them](Chunk::catch_asserts)
+    pub fn run_catching(mut self) -> Result<(Value<'alloc>, Vec<VmError<'src>>), VmError<'src>> {
         loop {
             #[cfg(feature = "gc-stress")]
             self.gc();
@@ -105,7 +198,7 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
             let offset = (self.ip.as_ptr() as usize) - (self.chunk.code().as_ptr() as usize);
             let (opcode, next) = match OpCode::decode(self.ip) {
                 Some(res) => res,
-                None => return Ok(Value::new_unit()),
+                None => return Ok((Value::new_unit(), self.caught)),
             };
             trace!("stack {:?}", &self.stack);
             trace!("decode {:04}: {:?}", offset, opcode);
@@ -116,12 +209,18 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
                 OpCode::True => self.op_true()?,
                 OpCode::False => self.op_false()?,
                 OpCode::Pop => self.op_pop()?,
+                OpCode::Swap => self.op_swap()?,
+                OpCode::Dup => self.op_dup()?,
                 OpCode::GetLocal { slot } => self.op_get_local(slot)?,
                 OpCode::SetLocal { slot } => self.op_set_local(slot)?,
                 OpCode::GetGlobal { name_key } => self.op_get_global(name_key, offset)?,
                 OpCode::DefGlobal { name_key } => self.op_def_global(name_key)?,
                 OpCode::SetGlobal { name_key } => self.op_set_global(name_key, offset)?,
+                OpCode::GetGlobalSlot { slot } => self.op_get_global_slot(slot, offset)?,
+                OpCode::DefGlobalSlot { slot } => self.op_def_global_slot(slot)?,
+                OpCode::SetGlobalSlot { slot } => self.op_set_global_slot(slot, offset)?,
                 OpCode::Equal => self.op_equal()?,
+                OpCode::StrictEqual => self.op_strict_equal()?,
                 OpCode::Greater => self.op_greater(offset)?,
                 OpCode::Less => self.op_less(offset)?,
                 OpCode::Add => self.op_add(offset)?,
@@ -130,17 +229,31 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
                 OpCode::Divide => self.op_divide(offset)?,
                 OpCode::Not => self.op_not()?,
                 OpCode::Negate => self.op_negate(offset)?,
-                OpCode::Assert => self.op_assert(offset)?,
+                OpCode::IsUnit => self.op_is_unit()?,
+                OpCode::Stringify => self.op_stringify()?,
+                OpCode::BuildRange => self.op_build_range(offset)?,
+                OpCode::Len => self.op_len(offset)?,
+                OpCode::GetIndex => self.op_get_index(offset)?,
+                OpCode::Assert => self.op_assert(None, offset)?,
+                OpCode::AssertBecause { reason_key } => self.op_assert(Some(reason_key), offset)?,
+                OpCode::DebugAssert => self.op_debug_assert(offset)?,
                 OpCode::Print => self.op_print()?,
-                OpCode::Jump { offset } => self.op_jump(offset)?,
-                OpCode::JumpIfTrue { offset } => self.op_jump_if_true(offset)?,
-                OpCode::JumpIfFalse { offset } => self.op_jump_if_false(offset)?,
+                OpCode::Dbg => self.op_dbg(offset)?,
+                OpCode::Flush => self.op_flush()?,
+                OpCode::Hit { id } => self.op_hit(id),
+                OpCode::Jump { offset } => self.op_jump(offset.into())?,
+                OpCode::JumpIfTrue { offset } => self.op_jump_if_true(offset.into())?,
+                OpCode::JumpIfFalse { offset } => self.op_jump_if_false(offset.into())?,
+                OpCode::JumpLong { offset } => self.op_jump(offset as usize)?,
+                OpCode::JumpIfTrueLong { offset } => self.op_jump_if_true(offset as usize)?,
+                OpCode::JumpIfFalseLong { offset } => self.op_jump_if_false(offset as usize)?,
                 OpCode::Loop { offset } => self.op_loop(offset)?,
+                OpCode::Call { arity } => self.op_call(arity, offset)?,
                 OpCode::Return => {
                     // return is inlined because we need to break the dispatch loop (for now)
                     let value = self.pop()?;
                     debug!("return value {:?}", &value);
-                    break Ok(value);
+                    break Ok((value, self.caught));
                 }
             }
         }
@@ -177,6 +290,21 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
         Ok(())
     }
 
+    fn op_swap(&mut self) -> Result<(), VmError<'src>> {
+        let len = self.stack.len();
+        if len < 2 {
+            return Err(VmError::CompileError(CodeError::PopEmptyStack));
+        }
+        self.stack.swap(len - 1, len - 2);
+        Ok(())
+    }
+
+    fn op_dup(&mut self) -> Result<(), VmError<'src>> {
+        let value = self.peek()?;
+        self.push(value);
+        Ok(())
+    }
+
     fn op_get_local(&mut self, slot: u16) -> Result<(), VmError<'src>> {
         let value = *self.stack.get(slot as usize)
             .ok_or(VmError::CompileError(CodeError::InvalidStackSlot(slot)))?;
@@ -230,6 +358,41 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
         Ok(())
     }
 
+    fn global_slot(&mut self, slot: u16) -> Result<&mut Option<Value<'alloc>>, VmError<'src>> {
+        self.global_slots.get_mut(slot as usize)
+            .ok_or(VmError::CompileError(CodeError::InvalidGlobalSlot(slot)))
+    }
+
+    fn undefined_global(&self, slot: u16, offset: usize) -> VmError<'src> {
+        VmError::RuntimeError {
+            span: self.get_span(offset),
+            kind: RuntimeErrorKind::UndefinedGlobalVariable(self.chunk.global_names()[slot as usize].to_string()),
+        }
+    }
+
+    fn op_get_global_slot(&mut self, slot: u16, offset: usize) -> Result<(), VmError<'src>> {
+        match *self.global_slot(slot)? {
+            Some(value) => self.push(value),
+            None => return Err(self.undefined_global(slot, offset)),
+        }
+        Ok(())
+    }
+
+    fn op_def_global_slot(&mut self, slot: u16) -> Result<(), VmError<'src>> {
+        let value = self.pop()?;
+        *self.global_slot(slot)? = Some(value);
+        Ok(())
+    }
+
+    fn op_set_global_slot(&mut self, slot: u16, offset: usize) -> Result<(), VmError<'src>> {
+        let value = self.peek()?;
+        match self.global_slot(slot)? {
+            Some(global) => *global = value,
+            None => return Err(self.undefined_global(slot, offset)),
+        }
+        Ok(())
+    }
+
     fn op_equal(&mut self) -> Result<(), VmError<'src>> {
         let rhs = self.pop()?;
         let lhs = self.pop()?;
@@ -238,6 +401,14 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
         Ok(())
     }
 
+    fn op_strict_equal(&mut self) -> Result<(), VmError<'src>> {
+        let rhs = self.pop()?;
+        let lhs = self.pop()?;
+        let result = lhs.strict_eq(&rhs);
+        self.push(Value::new_bool(result));
+        Ok(())
+    }
+
     fn op_greater(&mut self, offset: usize) -> Result<(), VmError<'src>> {
         let rhs = self.pop()?;
         let lhs = self.pop()?;
@@ -301,12 +472,25 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
     fn op_multiply(&mut self, offset: usize) -> Result<(), VmError<'src>> {
         let rhs = self.pop()?;
         let lhs = self.pop()?;
-        let result = match (lhs.to_float(), rhs.to_float()) {
-            (Some(lhs), Some(rhs)) => Value::new_float(lhs * rhs),
-            _ => return Err(VmError::RuntimeError {
+        let result = if let (Some(lhs), Some(rhs)) = (lhs.to_float(), rhs.to_float()) {
+            Value::new_float(lhs * rhs)
+        } else if let (Some(lhs), Some(rhs)) = (lhs.downcast::<ObjString>(), rhs.to_float()) {
+            let repeated = lhs.repeat(rhs, self.alloc)
+                .map_err(|error| VmError::RuntimeError {
+                    span: self.get_span(offset),
+                    kind: match error {
+                        RepeatError::InvalidCount => {
+                            RuntimeErrorKind::TypeError("String can only be repeated a non-negative integer number of times")
+                        }
+                        RepeatError::TooLong => RuntimeErrorKind::StringTooLong,
+                    },
+                })?;
+            Value::new_object(repeated)
+        } else {
+            return Err(VmError::RuntimeError {
                 span: self.get_span(offset),
-                kind: RuntimeErrorKind::TypeError("multiplication only supported on Numbers"),
-            }),
+                kind: RuntimeErrorKind::TypeError("multiplication only supported on Numbers, or a String and a Number"),
+            });
         };
         self.push(result);
         Ok(())
@@ -333,6 +517,23 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
         Ok(())
     }
 
+    fn op_is_unit(&mut self) -> Result<(), VmError<'src>> {
+        let value = self.pop()?;
+        self.push(Value::new_bool(value.is_unit()));
+        Ok(())
+    }
+
+    fn op_stringify(&mut self) -> Result<(), VmError<'src>> {
+        let value = self.pop()?;
+        if value.downcast::<ObjString>().is_some() {
+            self.push(value);
+        } else {
+            let string = format!("{:?}", value).into_boxed_str();
+            self.push(Value::new_object(ObjString::new_owned(string, self.alloc)));
+        }
+        Ok(())
+    }
+
     fn op_negate(&mut self, offset: usize) -> Result<(), VmError<'src>> {
         let value = self.pop()?;
         let value = value.to_float()
@@ -345,14 +546,70 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
         Ok(())
     }
 
-    fn op_assert(&mut self, offset: usize) -> Result<(), VmError<'src>> {
+    fn op_build_range(&mut self, offset: usize) -> Result<(), VmError<'src>> {
+        let end = self.pop()?;
+        let start = self.pop()?;
+        let range = match (start.to_float(), end.to_float()) {
+            (Some(start), Some(end)) => ObjRange::new(start, end, 1.0, self.alloc),
+            _ => return Err(VmError::RuntimeError {
+                span: self.get_span(offset),
+                kind: RuntimeErrorKind::TypeError("ranges only supported on Numbers"),
+            }),
+        };
+        self.push(Value::new_object(range));
+        Ok(())
+    }
+
+    fn op_len(&mut self, offset: usize) -> Result<(), VmError<'src>> {
         let value = self.pop()?;
-        match value.to_bool() {
-            Some(true) => {}
-            Some(false) => return Err(VmError::RuntimeError {
+        let range = value.downcast::<ObjRange>()
+            .ok_or_else(|| VmError::RuntimeError {
+                span: self.get_span(offset),
+                kind: RuntimeErrorKind::TypeError("only Ranges can be iterated"),
+            })?;
+        self.push(Value::new_float(range.len() as f64));
+        Ok(())
+    }
+
+    fn op_get_index(&mut self, offset: usize) -> Result<(), VmError<'src>> {
+        let index = self.pop()?;
+        let iterable = self.pop()?;
+        let (range, index) = match (iterable.downcast::<ObjRange>(), index.to_float()) {
+            (Some(range), Some(index)) => (range, index),
+            _ => return Err(VmError::RuntimeError {
                 span: self.get_span(offset),
-                kind: RuntimeErrorKind::AssertionError
+                kind: RuntimeErrorKind::TypeError("only Ranges can be indexed by Numbers"),
             }),
+        };
+        let element = Some(index)
+            .filter(|index| *index >= 0.0 && index.fract() == 0.0)
+            .and_then(|index| range.get(index as usize))
+            .ok_or_else(|| VmError::RuntimeError {
+                span: self.get_span(offset),
+                kind: RuntimeErrorKind::IndexOutOfBounds,
+            })?;
+        self.push(Value::new_float(element));
+        Ok(())
+    }
+
+    fn op_assert(&mut self, reason_key: Option<ConstKey>, offset: usize) -> Result<(), VmError<'src>> {
+        let value = self.pop()?;
+        match value.to_bool() {
+            Some(true) => {}
+            Some(false) => {
+                let reason = reason_key
+                    .map(|key| {
+                        self.chunk.get_constant(key)
+                            .and_then(Value::downcast::<ObjString>)
+                            .map(|reason| reason.as_str().to_string())
+                            .ok_or(VmError::CompileError(CodeError::InvalidConstantKey(key)))
+                    })
+                    .transpose()?;
+                return self.assertion_failed(VmError::RuntimeError {
+                    span: self.get_span(offset),
+                    kind: RuntimeErrorKind::AssertionError(reason),
+                });
+            }
             _ => return Err(VmError::RuntimeError {
                 span: self.get_span(offset),
                 kind: RuntimeErrorKind::TypeError("asserted expression must return a Bool"),
@@ -361,31 +618,92 @@ impl<'code, 'src, 'alloc> VM<'code, 'src, 'alloc> {
         Ok(())
     }
 
+    /// Stops execution with `error` unless the chunk catches assertions
+    fn assertion_failed(&mut self, error: VmError<'src>) -> Result<(), VmError<'src>> {
+        if self.chunk.catch_asserts() {
+            self.caught.push(error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    fn op_debug_assert(&mut self, offset: usize) -> Result<(), VmError<'src>> {
+        let value = self.peek()?;
+        if value.to_bool() == Some(false) {
+            self.pop()?;
+            let top = self.stack.iter()
+                .rev()
+                .take(DEBUG_ASSERT_VALUES)
+                .map(|value| format!("{:?}", value))
+                .collect();
+            return self.assertion_failed(VmError::RuntimeError {
+                span: self.get_span(offset),
+                kind: RuntimeErrorKind::DebugAssertionError { depth: self.stack.len(), top },
+            });
+        }
+        self.op_assert(None, offset)
+    }
+
+    /// There are no call frames to run a function's chunk in yet, calls only check their callee
+    fn op_call(&mut self, arity: u8, offset: usize) -> Result<(), VmError<'src>> {
+        let callee = self.stack.len().checked_sub(usize::from(arity) + 1)
+            .map(|index| self.stack[index])
+            .ok_or(VmError::CompileError(CodeError::PopEmptyStack))?;
+        let kind = if callee.downcast::<ObjFunction>().is_some() {
+            RuntimeErrorKind::Unsupported("function calls")
+        } else {
+            RuntimeErrorKind::TypeError("only functions can be called")
+        };
+        Err(VmError::RuntimeError { span: self.get_span(offset), kind })
+    }
+
     fn op_print(&mut self) -> Result<(), VmError<'src>> {
         let value = self.pop()?;
-        println!("{:?}", value);
-        Ok(())
+        writeln!(self.output, "{:?}", value).map_err(VmError::Output)
     }
 
-    fn op_jump(&mut self, offset: u16) -> Result<(), VmError<'src>> {
-        let offset = offset as usize;
+    fn op_flush(&mut self) -> Result<(), VmError<'src>> {
+        self.output.flush().map_err(VmError::Output)
+    }
+
+    fn op_hit(&mut self, id: u16) {
+        if let Some(hit) = self.coverage.as_mut().and_then(|hits| hits.get_mut(usize::from(id))) {
+            *hit = hit.saturating_add(1);
+        }
+    }
+
+    fn op_dbg(&mut self, offset: usize) -> Result<(), VmError<'src>> {
+        let value = self.peek()?;
+        let span = self.get_span(offset);
+        let location = match self.source_map {
+            Some(map) => span.location(map),
+            None => Location { file: None, line: span.lines().0 },
+        };
+        let (line, src) = (location.line, span.as_str());
+        match location.file {
+            Some(file) => writeln!(self.diagnostics, "[{} line {}] {} = {:?}", file, line, src, value),
+            None => writeln!(self.diagnostics, "[line {}] {} = {:?}", line, src, value),
+        }
+        .map_err(VmError::Output)
+    }
+
+    fn op_jump(&mut self, offset: usize) -> Result<(), VmError<'src>> {
         self.ip = &self.ip[offset..];
         Ok(())
     }
 
-    fn op_jump_if_true(&mut self, offset: u16) -> Result<(), VmError<'src>> {
+    fn op_jump_if_true(&mut self, offset: usize) -> Result<(), VmError<'src>> {
         let value = self.peek()?;
         if !value.is_falsy() {
-            let offset = offset as usize;
             self.ip = &self.ip[offset..];
         }
         Ok(())
     }
 
-    fn op_jump_if_false(&mut self, offset: u16) -> Result<(), VmError<'src>> {
+    fn op_jump_if_false(&mut self, offset: usize) -> Result<(), VmError<'src>> {
         let value = self.peek()?;
         if value.is_falsy() {
-            let offset = offset as usize;
             self.ip = &self.ip[offset..];
         }
         Ok(())